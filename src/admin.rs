@@ -1,6 +1,8 @@
+use crate::{data::FilterRule, db::JobQueueStats};
 use activitystreams::iri_string::types::IriString;
 use std::collections::{BTreeMap, BTreeSet};
 use time::OffsetDateTime;
+use uuid::Uuid;
 
 pub mod client;
 pub mod routes;
@@ -30,3 +32,120 @@ pub(crate) struct LastSeen {
     pub(crate) last_seen: BTreeMap<OffsetDateTime, BTreeSet<String>>,
     pub(crate) never: Vec<String>,
 }
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct Breakers {
+    pub(crate) breakers: BTreeMap<String, BreakerState>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct BreakerState {
+    pub(crate) failures: usize,
+    pub(crate) last_attempt: OffsetDateTime,
+    pub(crate) last_success: OffsetDateTime,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct PendingFollows {
+    pub(crate) pending_follows: Vec<PendingFollower>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct PendingFollower {
+    pub(crate) actor_id: IriString,
+    pub(crate) received_at: OffsetDateTime,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct Actors {
+    actors: Vec<IriString>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct Upstreams {
+    pub(crate) upstreams: Vec<UpstreamRelay>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct UpstreamRelay {
+    pub(crate) actor_id: IriString,
+    pub(crate) accepted: bool,
+    pub(crate) followed_at: OffsetDateTime,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct SigningKey {
+    pub(crate) key_id: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct DeadLetters {
+    pub(crate) dead_letters: Vec<DeadLetter>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct DeadLetter {
+    pub(crate) id: Uuid,
+    pub(crate) inbox: IriString,
+    pub(crate) activity_id: Option<IriString>,
+    pub(crate) error: String,
+    pub(crate) retries: u32,
+    pub(crate) queued_at: OffsetDateTime,
+    pub(crate) failed_at: OffsetDateTime,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct DeadLetterIds {
+    pub(crate) ids: Vec<Uuid>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct Replayed {
+    pub(crate) replayed: usize,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct JobQueues {
+    pub(crate) queues: BTreeMap<String, QueueStats>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct QueueStats {
+    pub(crate) depth: usize,
+    pub(crate) in_flight: usize,
+    pub(crate) retrying: usize,
+    /// Seconds since the oldest job in the queue was first queued
+    pub(crate) oldest_age: Option<u64>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(crate) struct Filters {
+    pub(crate) filters: Vec<FilterRule>,
+}
+
+impl From<JobQueueStats> for QueueStats {
+    fn from(stats: JobQueueStats) -> Self {
+        QueueStats {
+            depth: stats.depth,
+            in_flight: stats.in_flight,
+            retrying: stats.retrying,
+            oldest_age: stats.oldest_age.map(|age| age.as_secs()),
+        }
+    }
+}
+
+impl std::fmt::Display for QueueStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} queued, {} running, {} retrying",
+            self.depth, self.in_flight, self.retrying
+        )?;
+
+        if let Some(age) = self.oldest_age {
+            write!(f, ", oldest {age}s")?;
+        }
+
+        Ok(())
+    }
+}
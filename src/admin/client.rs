@@ -1,13 +1,18 @@
 use crate::{
-    admin::{AllowedDomains, BlockedDomains, ConnectedActors, Domains, LastSeen},
+    admin::{
+        Actors, AllowedDomains, BlockedDomains, Breakers, ConnectedActors, DeadLetterIds,
+        DeadLetters, Domains, JobQueues, LastSeen, PendingFollows, Replayed, SigningKey, Upstreams,
+    },
     collector::Snapshot,
     config::{AdminUrlKind, Config},
     error::{Error, ErrorKind},
     extractors::XApiToken,
 };
+use activitystreams::iri_string::types::IriString;
 use actix_web::http::header::Header;
 use reqwest_middleware::ClientWithMiddleware;
 use serde::de::DeserializeOwned;
+use uuid::Uuid;
 
 pub(crate) async fn allow(
     client: &ClientWithMiddleware,
@@ -69,6 +74,13 @@ pub(crate) async fn stats(
     get_results(client, config, AdminUrlKind::Stats).await
 }
 
+pub(crate) async fn jobs(
+    client: &ClientWithMiddleware,
+    config: &Config,
+) -> Result<JobQueues, Error> {
+    get_results(client, config, AdminUrlKind::Jobs).await
+}
+
 pub(crate) async fn last_seen(
     client: &ClientWithMiddleware,
     config: &Config,
@@ -76,6 +88,137 @@ pub(crate) async fn last_seen(
     get_results(client, config, AdminUrlKind::LastSeen).await
 }
 
+pub(crate) async fn breakers(
+    client: &ClientWithMiddleware,
+    config: &Config,
+) -> Result<Breakers, Error> {
+    get_results(client, config, AdminUrlKind::Breakers).await
+}
+
+pub(crate) async fn reset_breaker(
+    client: &ClientWithMiddleware,
+    config: &Config,
+    domain: String,
+) -> Result<(), Error> {
+    delete(client, config, AdminUrlKind::Breaker(domain)).await
+}
+
+pub(crate) async fn pending_follows(
+    client: &ClientWithMiddleware,
+    config: &Config,
+) -> Result<PendingFollows, Error> {
+    get_results(client, config, AdminUrlKind::PendingFollows).await
+}
+
+pub(crate) async fn approve_follows(
+    client: &ClientWithMiddleware,
+    config: &Config,
+    domains: Vec<String>,
+) -> Result<(), Error> {
+    post_domains(client, config, domains, AdminUrlKind::ApproveFollows).await
+}
+
+pub(crate) async fn deny_follows(
+    client: &ClientWithMiddleware,
+    config: &Config,
+    domains: Vec<String>,
+) -> Result<(), Error> {
+    post_domains(client, config, domains, AdminUrlKind::DenyFollows).await
+}
+
+pub(crate) async fn upstreams(
+    client: &ClientWithMiddleware,
+    config: &Config,
+) -> Result<Upstreams, Error> {
+    get_results(client, config, AdminUrlKind::Upstreams).await
+}
+
+pub(crate) async fn follow_upstreams(
+    client: &ClientWithMiddleware,
+    config: &Config,
+    actors: Vec<IriString>,
+) -> Result<(), Error> {
+    post_actors(client, config, actors, AdminUrlKind::FollowUpstreams).await
+}
+
+pub(crate) async fn unfollow_upstreams(
+    client: &ClientWithMiddleware,
+    config: &Config,
+    actors: Vec<IriString>,
+) -> Result<(), Error> {
+    post_actors(client, config, actors, AdminUrlKind::UnfollowUpstreams).await
+}
+
+pub(crate) async fn dead_letters(
+    client: &ClientWithMiddleware,
+    config: &Config,
+) -> Result<DeadLetters, Error> {
+    get_results(client, config, AdminUrlKind::DeadLetters).await
+}
+
+pub(crate) async fn replay_dead_letters(
+    client: &ClientWithMiddleware,
+    config: &Config,
+    ids: Vec<Uuid>,
+) -> Result<Replayed, Error> {
+    let x_api_token = config.x_api_token().ok_or(ErrorKind::MissingApiToken)?;
+
+    let iri = config.generate_admin_url(AdminUrlKind::ReplayDeadLetters);
+
+    let res = client
+        .post(iri.as_str())
+        .header(XApiToken::name(), x_api_token.to_string())
+        .json(&DeadLetterIds { ids })
+        .send()
+        .await
+        .map_err(|e| ErrorKind::SendRequest(iri.to_string(), e.to_string()))?;
+
+    if !res.status().is_success() {
+        return Err(ErrorKind::Status(iri.to_string(), res.status()).into());
+    }
+
+    let replayed = res
+        .json()
+        .await
+        .map_err(|e| ErrorKind::ReceiveResponse(iri.to_string(), e.to_string()))?;
+
+    Ok(replayed)
+}
+
+pub(crate) async fn purge_dead_letters(
+    client: &ClientWithMiddleware,
+    config: &Config,
+) -> Result<(), Error> {
+    delete(client, config, AdminUrlKind::DeadLetters).await
+}
+
+pub(crate) async fn rotate_key(
+    client: &ClientWithMiddleware,
+    config: &Config,
+) -> Result<SigningKey, Error> {
+    let x_api_token = config.x_api_token().ok_or(ErrorKind::MissingApiToken)?;
+
+    let iri = config.generate_admin_url(AdminUrlKind::RotateKey);
+
+    let res = client
+        .post(iri.as_str())
+        .header(XApiToken::name(), x_api_token.to_string())
+        .send()
+        .await
+        .map_err(|e| ErrorKind::SendRequest(iri.to_string(), e.to_string()))?;
+
+    if !res.status().is_success() {
+        return Err(ErrorKind::Status(iri.to_string(), res.status()).into());
+    }
+
+    let signing_key = res
+        .json()
+        .await
+        .map_err(|e| ErrorKind::ReceiveResponse(iri.to_string(), e.to_string()))?;
+
+    Ok(signing_key)
+}
+
 async fn get_results<T: DeserializeOwned>(
     client: &ClientWithMiddleware,
     config: &Config,
@@ -128,3 +271,51 @@ async fn post_domains(
 
     Ok(())
 }
+
+async fn post_actors(
+    client: &ClientWithMiddleware,
+    config: &Config,
+    actors: Vec<IriString>,
+    url_kind: AdminUrlKind,
+) -> Result<(), Error> {
+    let x_api_token = config.x_api_token().ok_or(ErrorKind::MissingApiToken)?;
+
+    let iri = config.generate_admin_url(url_kind);
+
+    let res = client
+        .post(iri.as_str())
+        .header(XApiToken::name(), x_api_token.to_string())
+        .json(&Actors { actors })
+        .send()
+        .await
+        .map_err(|e| ErrorKind::SendRequest(iri.to_string(), e.to_string()))?;
+
+    if !res.status().is_success() {
+        return Err(ErrorKind::Status(iri.to_string(), res.status()).into());
+    }
+
+    Ok(())
+}
+
+async fn delete(
+    client: &ClientWithMiddleware,
+    config: &Config,
+    url_kind: AdminUrlKind,
+) -> Result<(), Error> {
+    let x_api_token = config.x_api_token().ok_or(ErrorKind::MissingApiToken)?;
+
+    let iri = config.generate_admin_url(url_kind);
+
+    let res = client
+        .delete(iri.as_str())
+        .header(XApiToken::name(), x_api_token.to_string())
+        .send()
+        .await
+        .map_err(|e| ErrorKind::SendRequest(iri.to_string(), e.to_string()))?;
+
+    if !res.status().is_success() {
+        return Err(ErrorKind::Status(iri.to_string(), res.status()).into());
+    }
+
+    Ok(())
+}
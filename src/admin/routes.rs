@@ -1,8 +1,15 @@
 use crate::{
-    admin::{AllowedDomains, BlockedDomains, ConnectedActors, Domains, LastSeen},
+    admin::{
+        Actors, AllowedDomains, BlockedDomains, BreakerState, Breakers, ConnectedActors,
+        DeadLetter, DeadLetterIds, DeadLetters, Domains, Filters, JobQueues, LastSeen,
+        PendingFollower, PendingFollows, Replayed, SigningKey, UpstreamRelay, Upstreams,
+    },
     collector::{MemoryCollector, Snapshot},
-    error::Error,
+    config::{Config, UrlKind},
+    data::{FilterRule, State},
+    error::{Error, ErrorKind},
     extractors::Admin,
+    jobs::JobServer,
 };
 use actix_web::{
     web::{self, Data, Json},
@@ -10,6 +17,7 @@ use actix_web::{
 };
 use std::collections::{BTreeMap, BTreeSet};
 use time::OffsetDateTime;
+use uuid::Uuid;
 
 pub(crate) async fn allow(
     admin: Admin,
@@ -21,19 +29,23 @@ pub(crate) async fn allow(
 }
 
 pub(crate) async fn disallow(
-    admin: Admin,
+    _admin: Admin,
+    state: Data<State>,
+    jobs: Data<JobServer>,
     Json(Domains { domains }): Json<Domains>,
 ) -> Result<HttpResponse, Error> {
-    admin.db_ref().remove_allows(domains).await?;
+    state.disallow_domains(&jobs, domains).await?;
 
     Ok(HttpResponse::NoContent().finish())
 }
 
 pub(crate) async fn block(
-    admin: Admin,
+    _admin: Admin,
+    state: Data<State>,
+    jobs: Data<JobServer>,
     Json(Domains { domains }): Json<Domains>,
 ) -> Result<HttpResponse, Error> {
-    admin.db_ref().add_blocks(domains).await?;
+    state.block_domains(&jobs, domains).await?;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -72,6 +84,18 @@ pub(crate) async fn stats(
     Ok(Json(collector.snapshot()))
 }
 
+pub(crate) async fn jobs(admin: Admin) -> Result<Json<JobQueues>, Error> {
+    let queues = admin
+        .db_ref()
+        .job_stats()
+        .await?
+        .into_iter()
+        .map(|(queue, stats)| (queue, stats.into()))
+        .collect();
+
+    Ok(Json(JobQueues { queues }))
+}
+
 pub(crate) async fn last_seen(admin: Admin) -> Result<Json<LastSeen>, Error> {
     let nodes = admin.db_ref().last_seen().await?;
 
@@ -89,6 +113,214 @@ pub(crate) async fn last_seen(admin: Admin) -> Result<Json<LastSeen>, Error> {
     Ok(Json(LastSeen { last_seen, never }))
 }
 
+pub(crate) async fn breakers(_admin: Admin, state: Data<State>) -> Result<Json<Breakers>, Error> {
+    let breakers = state
+        .breakers
+        .snapshot()
+        .into_iter()
+        .map(|(authority, breaker)| {
+            let breaker = BreakerState {
+                failures: breaker.failures,
+                last_attempt: breaker.last_attempt.into(),
+                last_success: breaker.last_success.into(),
+            };
+
+            (authority, breaker)
+        })
+        .collect();
+
+    Ok(Json(Breakers { breakers }))
+}
+
+pub(crate) async fn reset_breaker(
+    _admin: Admin,
+    state: Data<State>,
+    domain: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    if state.reset_breaker(&domain).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ErrorKind::NotFound.into())
+    }
+}
+
+pub(crate) async fn pending_follows(admin: Admin) -> Result<Json<PendingFollows>, Error> {
+    let pending_follows = admin
+        .db_ref()
+        .pending_follows()
+        .await?
+        .into_iter()
+        .map(|pending| PendingFollower {
+            actor_id: pending.actor.id,
+            received_at: pending.received_at,
+        })
+        .collect();
+
+    Ok(Json(PendingFollows { pending_follows }))
+}
+
+pub(crate) async fn approve_follows(
+    _admin: Admin,
+    state: Data<State>,
+    jobs: Data<JobServer>,
+    Json(Domains { domains }): Json<Domains>,
+) -> Result<HttpResponse, Error> {
+    state.approve_follows(&jobs, domains).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub(crate) async fn deny_follows(
+    _admin: Admin,
+    state: Data<State>,
+    jobs: Data<JobServer>,
+    Json(Domains { domains }): Json<Domains>,
+) -> Result<HttpResponse, Error> {
+    state.deny_follows(&jobs, domains).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub(crate) async fn upstreams(admin: Admin) -> Result<Json<Upstreams>, Error> {
+    let upstreams = admin
+        .db_ref()
+        .upstreams()
+        .await?
+        .into_iter()
+        .map(|upstream| UpstreamRelay {
+            actor_id: upstream.actor_id,
+            accepted: upstream.accepted,
+            followed_at: upstream.followed_at,
+        })
+        .collect();
+
+    Ok(Json(Upstreams { upstreams }))
+}
+
+pub(crate) async fn follow_upstreams(
+    _admin: Admin,
+    state: Data<State>,
+    jobs: Data<JobServer>,
+    Json(Actors { actors }): Json<Actors>,
+) -> Result<HttpResponse, Error> {
+    state.follow_upstreams(&jobs, actors).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub(crate) async fn unfollow_upstreams(
+    _admin: Admin,
+    state: Data<State>,
+    jobs: Data<JobServer>,
+    Json(Actors { actors }): Json<Actors>,
+) -> Result<HttpResponse, Error> {
+    state.unfollow_upstreams(&jobs, actors).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub(crate) async fn rotate_key(
+    _admin: Admin,
+    state: Data<State>,
+    jobs: Data<JobServer>,
+    config: Data<Config>,
+) -> Result<Json<SigningKey>, Error> {
+    let generation = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let key_id = config
+        .generate_url(UrlKind::RotatedKey(generation))
+        .to_string();
+
+    state
+        .rotate_key(&jobs, key_id.clone(), config.key_rotation_grace())
+        .await?;
+
+    Ok(Json(SigningKey { key_id }))
+}
+
+pub(crate) async fn dead_letters(admin: Admin) -> Result<Json<DeadLetters>, Error> {
+    let dead_letters = admin
+        .db_ref()
+        .dead_letters()
+        .await?
+        .into_iter()
+        .map(|dead_letter| DeadLetter {
+            id: dead_letter.id,
+            inbox: dead_letter.inbox,
+            activity_id: dead_letter.activity_id,
+            error: dead_letter.error,
+            retries: dead_letter.retries,
+            queued_at: dead_letter.queued_at,
+            failed_at: dead_letter.failed_at,
+        })
+        .collect();
+
+    Ok(Json(DeadLetters { dead_letters }))
+}
+
+pub(crate) async fn replay_dead_letters(
+    _admin: Admin,
+    state: Data<State>,
+    jobs: Data<JobServer>,
+    Json(DeadLetterIds { ids }): Json<DeadLetterIds>,
+) -> Result<Json<Replayed>, Error> {
+    let replayed = state.replay_dead_letters(&jobs, ids).await?;
+
+    Ok(Json(Replayed { replayed }))
+}
+
+pub(crate) async fn purge_dead_letters(admin: Admin) -> Result<HttpResponse, Error> {
+    admin.db_ref().clear_dead_letters().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub(crate) async fn purge_dead_letter(
+    admin: Admin,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    if admin
+        .db_ref()
+        .remove_dead_letters(vec![id.into_inner()])
+        .await?
+        .is_empty()
+    {
+        Err(ErrorKind::NotFound.into())
+    } else {
+        Ok(HttpResponse::NoContent().finish())
+    }
+}
+
+pub(crate) async fn filters(_admin: Admin, state: Data<State>) -> Result<Json<Filters>, Error> {
+    let filters = state.filters.rules();
+
+    Ok(Json(Filters { filters }))
+}
+
+pub(crate) async fn add_filter(
+    _admin: Admin,
+    state: Data<State>,
+    Json(rule): Json<FilterRule>,
+) -> Result<Json<FilterRule>, Error> {
+    let rule = state.add_filter(rule).await?;
+
+    Ok(Json(rule))
+}
+
+pub(crate) async fn remove_filter(
+    _admin: Admin,
+    state: Data<State>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    if state.remove_filter(id.into_inner()).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ErrorKind::NotFound.into())
+    }
+}
+
 pub(crate) async fn get_authority_cfg(
     _admin: Admin,
     state: Data<crate::data::State>,
@@ -116,7 +348,7 @@ pub(crate) async fn set_authority_cfg(
     domain: web::Path<String>,
     Json(cfg): Json<crate::data::NodeConfig>,
 ) -> Result<HttpResponse, Error> {
-    state.set_authority_cfg(&domain, cfg).await;
+    state.set_authority_cfg(&domain, cfg).await?;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -126,7 +358,7 @@ pub(crate) async fn clear_authority_cfg(
     state: Data<crate::data::State>,
     domain: web::Path<String>,
 ) -> Result<HttpResponse, Error> {
-    state.clear_authority_cfg(&domain).await;
+    state.clear_authority_cfg(&domain).await?;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -75,3 +75,64 @@ where
         Ok(())
     }
 }
+
+/// Lowercases a hashtag and drops the leading #, rejecting anything that can't be part of a tag
+pub(crate) fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim_start_matches('#').to_lowercase();
+
+    if tag.is_empty() || !tag.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    Some(tag)
+}
+
+/// Whether the actor wrote the object, so the copy the actor sent can be trusted over a fetch
+///
+/// The object has to live on the actor's server too, otherwise anyone could send their own
+/// version of a post from somewhere else
+pub(crate) fn is_authored_by(object: &serde_json::Value, actor_id: &IriString) -> bool {
+    let attributed_to = match &object["attributedTo"] {
+        serde_json::Value::Array(authors) => authors.first(),
+        author => Some(author),
+    };
+    let attributed_to = attributed_to.and_then(|author| author.as_str().or(author["id"].as_str()));
+
+    let same_authority = object["id"]
+        .as_str()
+        .and_then(|id| id.parse::<IriString>().ok())
+        .is_some_and(|id| id.authority_str() == actor_id.authority_str());
+
+    attributed_to == Some(actor_id.as_str()) && same_authority
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_authored_by;
+    use activitystreams::iri_string::types::IriString;
+    use serde_json::json;
+
+    #[test]
+    fn only_the_author_can_vouch_for_an_object() {
+        let actor: IriString = "https://example.com/users/a".parse().unwrap();
+
+        let own = json!({ "id": "https://example.com/notes/1", "attributedTo": actor.as_str() });
+        assert!(is_authored_by(&own, &actor));
+
+        let nested = json!({
+            "id": "https://example.com/notes/1",
+            "attributedTo": [{ "id": actor.as_str(), "type": "Person" }],
+        });
+        assert!(is_authored_by(&nested, &actor));
+
+        let forged =
+            json!({ "id": "https://victim.example/notes/1", "attributedTo": actor.as_str() });
+        assert!(!is_authored_by(&forged, &actor));
+
+        let someone_else = json!({
+            "id": "https://example.com/notes/1",
+            "attributedTo": "https://example.com/users/b",
+        });
+        assert!(!is_authored_by(&someone_else, &actor));
+    }
+}
@@ -1,4 +1,6 @@
+use activitystreams::iri_string::types::IriString;
 use clap::Parser;
+use uuid::Uuid;
 
 #[derive(Debug, Parser)]
 #[structopt(name = "relay", about = "An activitypub relay")]
@@ -15,7 +17,7 @@ pub(crate) struct Args {
     #[arg(short, long, help = "List allowed and blocked domains")]
     list: bool,
 
-    #[arg(short, long, help = "Get statistics from the server")]
+    #[arg(short, long, help = "Get statistics and job queues from the server")]
     stats: bool,
 
     #[arg(
@@ -24,6 +26,49 @@ pub(crate) struct Args {
         help = "List domains by when they were last succesfully contacted"
     )]
     contacted: bool,
+
+    #[arg(long, help = "List circuit breaker state for each domain")]
+    breakers: bool,
+
+    #[arg(
+        long,
+        num_args = 1..,
+        help = "A list of domains whose circuit breakers should be reset"
+    )]
+    reset_breakers: Vec<String>,
+
+    #[arg(long, help = "List follows waiting for approval")]
+    pending: bool,
+
+    #[arg(long, num_args = 1.., help = "A list of domains whose follows should be approved")]
+    approve: Vec<String>,
+
+    #[arg(long, num_args = 1.., help = "A list of domains whose follows should be denied")]
+    deny: Vec<String>,
+
+    #[arg(long, help = "List the relays this relay follows")]
+    upstreams: bool,
+
+    #[arg(long, num_args = 1.., help = "A list of relay actors this relay should follow")]
+    follow_upstream: Vec<IriString>,
+
+    #[arg(long, num_args = 1.., help = "A list of relay actors this relay should stop following")]
+    unfollow_upstream: Vec<IriString>,
+
+    #[arg(
+        long,
+        help = "Sign with a new key, keeping the current one through the grace period"
+    )]
+    rotate_key: bool,
+
+    #[arg(long, help = "List deliveries that were given up on")]
+    dead_letters: bool,
+
+    #[arg(long, num_args = 1.., help = "A list of dead letter ids to deliver again")]
+    replay_dead_letters: Vec<Uuid>,
+
+    #[arg(long, help = "Forget every delivery that was given up on")]
+    purge_dead_letters: bool,
 }
 
 impl Args {
@@ -33,6 +78,18 @@ impl Args {
             || self.list
             || self.stats
             || self.contacted
+            || self.breakers
+            || !self.reset_breakers.is_empty()
+            || self.pending
+            || !self.approve.is_empty()
+            || !self.deny.is_empty()
+            || self.upstreams
+            || !self.follow_upstream.is_empty()
+            || !self.unfollow_upstream.is_empty()
+            || self.rotate_key
+            || self.dead_letters
+            || !self.replay_dead_letters.is_empty()
+            || self.purge_dead_letters
     }
 
     pub(crate) fn new() -> Self {
@@ -62,4 +119,52 @@ impl Args {
     pub(crate) fn contacted(&self) -> bool {
         self.contacted
     }
+
+    pub(crate) fn breakers(&self) -> bool {
+        self.breakers
+    }
+
+    pub(crate) fn reset_breakers(&self) -> &[String] {
+        &self.reset_breakers
+    }
+
+    pub(crate) fn pending(&self) -> bool {
+        self.pending
+    }
+
+    pub(crate) fn approve(&self) -> &[String] {
+        &self.approve
+    }
+
+    pub(crate) fn deny(&self) -> &[String] {
+        &self.deny
+    }
+
+    pub(crate) fn upstreams(&self) -> bool {
+        self.upstreams
+    }
+
+    pub(crate) fn follow_upstream(&self) -> &[IriString] {
+        &self.follow_upstream
+    }
+
+    pub(crate) fn unfollow_upstream(&self) -> &[IriString] {
+        &self.unfollow_upstream
+    }
+
+    pub(crate) fn rotate_key(&self) -> bool {
+        self.rotate_key
+    }
+
+    pub(crate) fn dead_letters(&self) -> bool {
+        self.dead_letters
+    }
+
+    pub(crate) fn replay_dead_letters(&self) -> &[Uuid] {
+        &self.replay_dead_letters
+    }
+
+    pub(crate) fn purge_dead_letters(&self) -> bool {
+        self.purge_dead_letters
+    }
 }
@@ -411,3 +411,22 @@ impl Recorder for MemoryCollector {
             .get_or_create_histogram(key, |c| c.clone().into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryCollector;
+
+    #[test]
+    fn throttled_requests_are_counted() {
+        let collector = MemoryCollector::new();
+
+        metrics::with_local_recorder(&collector, || {
+            metrics::counter!("relay.inbox.throttled").increment(2);
+        });
+
+        let snapshot = collector.snapshot();
+        let counters = &snapshot.counters["relay.inbox.throttled"];
+        assert_eq!(counters.len(), 1);
+        assert_eq!(counters[0].value, 2);
+    }
+}
@@ -1,6 +1,9 @@
 use crate::{
+    data::{DedupPolicy, ObjectCachePolicy, RateLimitPolicy},
     error::Error,
     extractors::{AdminConfig, XApiToken},
+    jobs::BatchPolicy,
+    requests::{BreakerPolicy, DeliveryPolicy},
 };
 use activitystreams::{
     iri,
@@ -17,6 +20,7 @@ use std::{
     io::BufReader,
     net::{IpAddr, SocketAddr},
     path::PathBuf,
+    time::Duration,
 };
 use uuid::Uuid;
 
@@ -27,9 +31,15 @@ pub(crate) struct ParsedConfig {
     port: u16,
     debug: bool,
     restricted_mode: bool,
+    follow_approval: bool,
     validate_signatures: bool,
+    verify_integrity_proofs: bool,
+    ed25519_signatures: bool,
     https: bool,
     publish_blocks: bool,
+    hide_followers: bool,
+    hide_following: bool,
+    hide_outbox: bool,
     sled_path: PathBuf,
     source_repo: IriString,
     repository_commit_base: String,
@@ -50,6 +60,23 @@ pub(crate) struct ParsedConfig {
     proxy_username: Option<String>,
     proxy_password: Option<String>,
     signature_threads: Option<usize>,
+    breaker_failure_threshold: usize,
+    breaker_failure_wait: u64,
+    breaker_max_failure_wait: u64,
+    cache_objects: bool,
+    object_cache_size: usize,
+    object_cache_ttl: u64,
+    dedup_capacity: usize,
+    dedup_window: u64,
+    key_rotation_grace: u64,
+    inbox_rate_limit: u32,
+    inbox_rate_burst: u32,
+    deliver_authority_concurrency: usize,
+    deliver_authority_rate: u32,
+    delivery_batch_window: u64,
+    delivery_batch_size: usize,
+    dead_letter_capacity: usize,
+    pending_follow_capacity: usize,
 }
 
 #[derive(Clone)]
@@ -59,8 +86,15 @@ pub struct Config {
     port: u16,
     debug: bool,
     restricted_mode: bool,
+    follow_approval: bool,
     validate_signatures: bool,
+    verify_integrity_proofs: bool,
+    ed25519_signatures: bool,
+    key_rotation_grace: Duration,
     publish_blocks: bool,
+    hide_followers: bool,
+    hide_following: bool,
+    hide_outbox: bool,
     base_uri: IriAbsoluteString,
     sled_path: PathBuf,
     source_repo: IriString,
@@ -77,6 +111,14 @@ pub struct Config {
     client_timeout: u64,
     proxy_config: Option<ProxyConfig>,
     signature_threads: Option<usize>,
+    breaker_policy: BreakerPolicy,
+    object_cache_policy: Option<ObjectCachePolicy>,
+    dedup_policy: DedupPolicy,
+    rate_limit_policy: RateLimitPolicy,
+    delivery_policy: DeliveryPolicy,
+    batch_policy: Option<BatchPolicy>,
+    dead_letter_capacity: usize,
+    pending_follow_capacity: usize,
 }
 
 #[derive(Clone)]
@@ -102,13 +144,19 @@ pub enum UrlKind {
     Activity,
     Actor,
     Followers,
+    FollowersPage(usize),
     Following,
+    FollowingPage(usize),
     Inbox,
+    Ed25519Key,
     Index,
     MainKey,
     Media(Uuid),
     NodeInfo,
     Outbox,
+    OutboxPage(usize),
+    RotatedKey(u64),
+    Tag(String),
 }
 
 #[derive(Debug)]
@@ -121,7 +169,19 @@ pub enum AdminUrlKind {
     Blocked,
     Connected,
     Stats,
+    Jobs,
     LastSeen,
+    Breakers,
+    Breaker(String),
+    PendingFollows,
+    ApproveFollows,
+    DenyFollows,
+    Upstreams,
+    FollowUpstreams,
+    UnfollowUpstreams,
+    RotateKey,
+    DeadLetters,
+    ReplayDeadLetters,
 }
 
 impl std::fmt::Debug for Config {
@@ -132,8 +192,15 @@ impl std::fmt::Debug for Config {
             .field("port", &self.port)
             .field("debug", &self.debug)
             .field("restricted_mode", &self.restricted_mode)
+            .field("follow_approval", &self.follow_approval)
             .field("validate_signatures", &self.validate_signatures)
+            .field("verify_integrity_proofs", &self.verify_integrity_proofs)
+            .field("ed25519_signatures", &self.ed25519_signatures)
+            .field("key_rotation_grace", &self.key_rotation_grace)
             .field("publish_blocks", &self.publish_blocks)
+            .field("hide_followers", &self.hide_followers)
+            .field("hide_following", &self.hide_following)
+            .field("hide_outbox", &self.hide_outbox)
             .field("base_uri", &self.base_uri.to_string())
             .field("sled_path", &self.sled_path)
             .field("source_repo", &self.source_repo.to_string())
@@ -154,6 +221,14 @@ impl std::fmt::Debug for Config {
             .field("client_timeout", &self.client_timeout)
             .field("proxy_config", &self.proxy_config)
             .field("signature_threads", &self.signature_threads)
+            .field("breaker_policy", &self.breaker_policy)
+            .field("object_cache_policy", &self.object_cache_policy)
+            .field("dedup_policy", &self.dedup_policy)
+            .field("rate_limit_policy", &self.rate_limit_policy)
+            .field("delivery_policy", &self.delivery_policy)
+            .field("batch_policy", &self.batch_policy)
+            .field("dead_letter_capacity", &self.dead_letter_capacity)
+            .field("pending_follow_capacity", &self.pending_follow_capacity)
             .finish()
     }
 }
@@ -166,9 +241,15 @@ impl Config {
             .set_default("port", 8080u64)?
             .set_default("debug", true)?
             .set_default("restricted_mode", false)?
+            .set_default("follow_approval", false)?
             .set_default("validate_signatures", true)?
+            .set_default("verify_integrity_proofs", false)?
+            .set_default("ed25519_signatures", false)?
             .set_default("https", true)?
             .set_default("publish_blocks", false)?
+            .set_default("hide_followers", false)?
+            .set_default("hide_following", false)?
+            .set_default("hide_outbox", false)?
             .set_default("sled_path", "./sled/db-0-34")?
             .set_default("source_repo", "https://git.asonix.dog/asonix/relay")?
             .set_default("repository_commit_base", "/src/commit/")?
@@ -189,6 +270,23 @@ impl Config {
             .set_default("proxy_username", None as Option<&str>)?
             .set_default("proxy_password", None as Option<&str>)?
             .set_default("signature_threads", None as Option<u64>)?
+            .set_default("breaker_failure_threshold", 10u64)?
+            .set_default("breaker_failure_wait", 60 * 60 * 24u64)?
+            .set_default("breaker_max_failure_wait", 60 * 60 * 24u64)?
+            .set_default("cache_objects", false)?
+            .set_default("object_cache_size", 1024u64)?
+            .set_default("object_cache_ttl", 60 * 60u64)?
+            .set_default("dedup_capacity", 100_000u64)?
+            .set_default("dedup_window", 60 * 60 * 24u64)?
+            .set_default("key_rotation_grace", 60 * 60 * 24 * 7u64)?
+            .set_default("inbox_rate_limit", 20u64)?
+            .set_default("inbox_rate_burst", 200u64)?
+            .set_default("deliver_authority_concurrency", 4u64)?
+            .set_default("deliver_authority_rate", 10u64)?
+            .set_default("delivery_batch_window", 0u64)?
+            .set_default("delivery_batch_size", 20u64)?
+            .set_default("dead_letter_capacity", 10_000u64)?
+            .set_default("pending_follow_capacity", 1000u64)?
             .add_source(Environment::default())
             .build()?;
 
@@ -266,8 +364,15 @@ impl Config {
             port: config.port,
             debug: config.debug,
             restricted_mode: config.restricted_mode,
+            follow_approval: config.follow_approval,
             validate_signatures: config.validate_signatures,
+            verify_integrity_proofs: config.verify_integrity_proofs,
+            ed25519_signatures: config.ed25519_signatures,
+            key_rotation_grace: Duration::from_secs(config.key_rotation_grace),
             publish_blocks: config.publish_blocks,
+            hide_followers: config.hide_followers,
+            hide_following: config.hide_following,
+            hide_outbox: config.hide_outbox,
             base_uri,
             sled_path: config.sled_path,
             source_repo: source_url,
@@ -284,6 +389,33 @@ impl Config {
             client_timeout: config.client_timeout,
             proxy_config,
             signature_threads: config.signature_threads,
+            breaker_policy: BreakerPolicy {
+                failure_threshold: config.breaker_failure_threshold,
+                failure_wait: Duration::from_secs(config.breaker_failure_wait),
+                max_failure_wait: Duration::from_secs(config.breaker_max_failure_wait),
+            },
+            object_cache_policy: config.cache_objects.then(|| ObjectCachePolicy {
+                capacity: config.object_cache_size,
+                ttl: Duration::from_secs(config.object_cache_ttl),
+            }),
+            dedup_policy: DedupPolicy {
+                capacity: config.dedup_capacity,
+                window: Duration::from_secs(config.dedup_window),
+            },
+            rate_limit_policy: RateLimitPolicy {
+                rate: config.inbox_rate_limit,
+                burst: config.inbox_rate_burst,
+            },
+            delivery_policy: DeliveryPolicy {
+                concurrency: config.deliver_authority_concurrency,
+                rate: config.deliver_authority_rate,
+            },
+            batch_policy: (config.delivery_batch_window > 0).then(|| BatchPolicy {
+                window: Duration::from_millis(config.delivery_batch_window),
+                size: config.delivery_batch_size,
+            }),
+            dead_letter_capacity: config.dead_letter_capacity,
+            pending_follow_capacity: config.pending_follow_capacity,
         })
     }
 
@@ -302,6 +434,38 @@ impl Config {
         self.client_timeout
     }
 
+    pub(crate) fn breaker_policy(&self) -> BreakerPolicy {
+        self.breaker_policy
+    }
+
+    pub(crate) fn object_cache_policy(&self) -> Option<ObjectCachePolicy> {
+        self.object_cache_policy
+    }
+
+    pub(crate) fn dedup_policy(&self) -> DedupPolicy {
+        self.dedup_policy
+    }
+
+    pub(crate) fn rate_limit_policy(&self) -> RateLimitPolicy {
+        self.rate_limit_policy
+    }
+
+    pub(crate) fn delivery_policy(&self) -> DeliveryPolicy {
+        self.delivery_policy
+    }
+
+    pub(crate) fn batch_policy(&self) -> Option<BatchPolicy> {
+        self.batch_policy
+    }
+
+    pub(crate) fn dead_letter_capacity(&self) -> usize {
+        self.dead_letter_capacity
+    }
+
+    pub(crate) fn pending_follow_capacity(&self) -> usize {
+        self.pending_follow_capacity
+    }
+
     pub(crate) fn deliver_concurrency(&self) -> u64 {
         self.deliver_concurrency
     }
@@ -399,12 +563,22 @@ impl Config {
         self.validate_signatures
     }
 
+    pub(crate) fn verify_integrity_proofs(&self) -> bool {
+        self.verify_integrity_proofs
+    }
+
+    pub(crate) fn ed25519_signatures(&self) -> bool {
+        self.ed25519_signatures
+    }
+
+    pub(crate) fn key_rotation_grace(&self) -> Duration {
+        self.key_rotation_grace
+    }
+
+    // Optional here, since RFC 9421 requests carry a Content-Digest instead of a Digest header.
+    // MessageSignature requires a Digest header on other requests when signatures are validated
     pub(crate) fn digest_middleware(&self) -> VerifyDigest<Sha256> {
-        if self.validate_signatures {
-            VerifyDigest::new(Sha256::new())
-        } else {
-            VerifyDigest::new(Sha256::new()).optional()
-        }
+        VerifyDigest::new(Sha256::new()).optional()
     }
 
     pub(crate) fn x_api_token(&self) -> Option<XApiToken> {
@@ -437,10 +611,26 @@ impl Config {
         self.publish_blocks
     }
 
+    pub(crate) fn hide_followers(&self) -> bool {
+        self.hide_followers
+    }
+
+    pub(crate) fn hide_following(&self) -> bool {
+        self.hide_following
+    }
+
+    pub(crate) fn hide_outbox(&self) -> bool {
+        self.hide_outbox
+    }
+
     pub(crate) fn restricted_mode(&self) -> bool {
         self.restricted_mode
     }
 
+    pub(crate) fn follow_approval(&self) -> bool {
+        self.follow_approval
+    }
+
     pub(crate) fn hostname(&self) -> &str {
         &self.hostname
     }
@@ -534,12 +724,29 @@ impl Config {
             UrlKind::Followers => FixedBaseResolver::new(self.base_uri.as_ref())
                 .resolve(IriRelativeStr::new("followers")?.as_ref())
                 .try_to_dedicated_string()?,
+            UrlKind::FollowersPage(page) => FixedBaseResolver::new(self.base_uri.as_ref())
+                .resolve(IriRelativeStr::new(&format!("followers?page={page}"))?.as_ref())
+                .try_to_dedicated_string()?,
             UrlKind::Following => FixedBaseResolver::new(self.base_uri.as_ref())
                 .resolve(IriRelativeStr::new("following")?.as_ref())
                 .try_to_dedicated_string()?,
+            UrlKind::FollowingPage(page) => FixedBaseResolver::new(self.base_uri.as_ref())
+                .resolve(IriRelativeStr::new(&format!("following?page={page}"))?.as_ref())
+                .try_to_dedicated_string()?,
             UrlKind::Inbox => FixedBaseResolver::new(self.base_uri.as_ref())
                 .resolve(IriRelativeStr::new("inbox")?.as_ref())
                 .try_to_dedicated_string()?,
+            UrlKind::Ed25519Key => {
+                let actor = IriRelativeStr::new("actor")?;
+                let fragment = IriFragmentStr::new("ed25519-key")?;
+
+                let mut resolved = FixedBaseResolver::new(self.base_uri.as_ref())
+                    .resolve(actor.as_ref())
+                    .try_to_dedicated_string()?;
+
+                resolved.set_fragment(Some(fragment));
+                resolved
+            }
             UrlKind::Index => self.base_uri.clone().into(),
             UrlKind::MainKey => {
                 let actor = IriRelativeStr::new("actor")?;
@@ -561,6 +768,24 @@ impl Config {
             UrlKind::Outbox => FixedBaseResolver::new(self.base_uri.as_ref())
                 .resolve(IriRelativeStr::new("outbox")?.as_ref())
                 .try_to_dedicated_string()?,
+            UrlKind::OutboxPage(page) => FixedBaseResolver::new(self.base_uri.as_ref())
+                .resolve(IriRelativeStr::new(&format!("outbox?page={page}"))?.as_ref())
+                .try_to_dedicated_string()?,
+            UrlKind::RotatedKey(generation) => {
+                let actor = IriRelativeStr::new("actor")?;
+                let fragment = format!("key-{generation}");
+                let fragment = IriFragmentStr::new(&fragment)?;
+
+                let mut resolved = FixedBaseResolver::new(self.base_uri.as_ref())
+                    .resolve(actor.as_ref())
+                    .try_to_dedicated_string()?;
+
+                resolved.set_fragment(Some(fragment));
+                resolved
+            }
+            UrlKind::Tag(tag) => FixedBaseResolver::new(self.base_uri.as_ref())
+                .resolve(IriRelativeStr::new(&format!("tags/{tag}"))?.as_ref())
+                .try_to_dedicated_string()?,
         };
 
         Ok(iri)
@@ -572,6 +797,7 @@ impl Config {
     }
 
     fn do_generate_admin_url(&self, kind: AdminUrlKind) -> Result<IriString, Error> {
+        let breaker_path;
         let path = match kind {
             AdminUrlKind::Allow => "api/v1/admin/allow",
             AdminUrlKind::Disallow => "api/v1/admin/disallow",
@@ -581,7 +807,22 @@ impl Config {
             AdminUrlKind::Blocked => "api/v1/admin/blocked",
             AdminUrlKind::Connected => "api/v1/admin/connected",
             AdminUrlKind::Stats => "api/v1/admin/stats",
+            AdminUrlKind::Jobs => "api/v1/admin/jobs",
             AdminUrlKind::LastSeen => "api/v1/admin/last_seen",
+            AdminUrlKind::Breakers => "api/v1/admin/breakers",
+            AdminUrlKind::PendingFollows => "api/v1/admin/pending_follows",
+            AdminUrlKind::ApproveFollows => "api/v1/admin/pending_follows/approve",
+            AdminUrlKind::DenyFollows => "api/v1/admin/pending_follows/deny",
+            AdminUrlKind::Upstreams => "api/v1/admin/upstreams",
+            AdminUrlKind::FollowUpstreams => "api/v1/admin/upstreams/follow",
+            AdminUrlKind::UnfollowUpstreams => "api/v1/admin/upstreams/unfollow",
+            AdminUrlKind::RotateKey => "api/v1/admin/rotate_key",
+            AdminUrlKind::DeadLetters => "api/v1/admin/dead_letters",
+            AdminUrlKind::ReplayDeadLetters => "api/v1/admin/dead_letters/replay",
+            AdminUrlKind::Breaker(domain) => {
+                breaker_path = format!("api/v1/admin/breakers/{domain}");
+                &breaker_path
+            }
         };
 
         let iri = FixedBaseResolver::new(self.base_uri.as_ref())
@@ -1,11 +1,17 @@
 mod actor;
+mod filter;
 mod last_online;
 mod media;
 mod node;
+mod object;
+mod rate_limit;
 mod state;
 
 pub(crate) use actor::ActorCache;
+pub(crate) use filter::{FilterRule, Filters, Verdict};
 pub(crate) use last_online::LastOnline;
 pub(crate) use media::MediaCache;
-pub(crate) use node::{Node, NodeCache, NodeConfig};
-pub(crate) use state::State;
+pub(crate) use node::{DeliveryMode, Node, NodeCache, NodeConfig};
+pub(crate) use object::{ObjectCache, ObjectCachePolicy};
+pub(crate) use rate_limit::{RateLimitPolicy, RateLimiter};
+pub(crate) use state::{DedupPolicy, State};
@@ -2,6 +2,7 @@ use crate::{
     apub::AcceptedActors,
     db::{Actor, Db},
     error::{Error, ErrorKind},
+    integrity,
     requests::{BreakerStrategy, Requests},
 };
 use activitystreams::{iri_string::types::IriString, prelude::*};
@@ -71,16 +72,28 @@ impl ActorCache {
         id: &IriString,
         requests: &Requests,
     ) -> Result<Actor, Error> {
-        let accepted_actor = requests
-            .fetch::<AcceptedActors>(id, BreakerStrategy::Require2XX)
+        let document = requests
+            .fetch::<serde_json::Value>(id, BreakerStrategy::Require2XX)
             .await?;
 
+        self.save_document(id, document).await
+    }
+
+    /// Saves the actor from a document that was already fetched from id
+    pub(crate) async fn save_document(
+        &self,
+        id: &IriString,
+        document: serde_json::Value,
+    ) -> Result<Actor, Error> {
+        let accepted_actor: AcceptedActors = serde_json::from_value(document.clone())?;
+
         let input_authority = id.authority_components().ok_or(ErrorKind::MissingDomain)?;
         let accepted_actor_id = accepted_actor
             .id(input_authority.host(), input_authority.port())?
             .ok_or(ErrorKind::MissingId)?;
 
         let inbox = get_inbox(&accepted_actor)?.clone();
+        let ed25519_keys = integrity::assertion_keys(&document, accepted_actor_id);
 
         let actor = Actor {
             id: accepted_actor_id.clone(),
@@ -88,6 +101,7 @@ impl ActorCache {
             public_key_id: accepted_actor.ext_one.public_key.id,
             inbox,
             saved_at: SystemTime::now(),
+            ed25519_keys,
         };
 
         self.db.save_actor(actor.clone()).await?;
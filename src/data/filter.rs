@@ -0,0 +1,304 @@
+use crate::error::Error;
+use regex::{Regex, RegexBuilder};
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) struct FilterRule {
+    /// Assigned by the relay when the rule is added
+    #[serde(default = "Uuid::new_v4")]
+    pub(crate) id: Uuid,
+    /// A keyword or a regular expression, depending on kind
+    pub(crate) pattern: String,
+    #[serde(default)]
+    pub(crate) kind: MatchKind,
+    #[serde(default)]
+    pub(crate) action: FilterAction,
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MatchKind {
+    /// Case-insensitive substring match
+    #[default]
+    Keyword,
+    /// Case-insensitive regular expression
+    Regex,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub(crate) enum FilterAction {
+    /// Don't relay the post at all
+    #[default]
+    Drop,
+    /// Only relay the post to subscribers on these domains
+    Restrict { domains: BTreeSet<String> },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Relay,
+    Drop,
+    Restrict(BTreeSet<String>),
+}
+
+impl Verdict {
+    pub(crate) fn allows(&self, authority: &str) -> bool {
+        match self {
+            Verdict::Relay => true,
+            Verdict::Drop => false,
+            Verdict::Restrict(domains) => domains.iter().any(|domain| {
+                authority == domain
+                    || authority
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|rest| rest.ends_with('.'))
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Filters {
+    rules: Arc<RwLock<Vec<CompiledRule>>>,
+}
+
+struct CompiledRule {
+    rule: FilterRule,
+    matcher: Matcher,
+}
+
+enum Matcher {
+    Keyword(String),
+    Regex(Regex),
+}
+
+impl std::fmt::Debug for Filters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Filters")
+            .field("rules", &self.rules.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl CompiledRule {
+    fn compile(rule: FilterRule) -> Result<Self, Error> {
+        let matcher = match rule.kind {
+            MatchKind::Keyword => Matcher::Keyword(rule.pattern.to_lowercase()),
+            MatchKind::Regex => Matcher::Regex(
+                RegexBuilder::new(&rule.pattern)
+                    .case_insensitive(true)
+                    .build()?,
+            ),
+        };
+
+        Ok(CompiledRule { rule, matcher })
+    }
+
+    fn is_match(&self, text: &str, lowercase: &str) -> bool {
+        match &self.matcher {
+            Matcher::Keyword(keyword) => lowercase.contains(keyword.as_str()),
+            Matcher::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+impl Filters {
+    pub(crate) fn new(rules: Vec<FilterRule>) -> Result<Self, Error> {
+        let rules = rules
+            .into_iter()
+            .map(CompiledRule::compile)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Filters {
+            rules: Arc::new(RwLock::new(rules)),
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.read().unwrap().is_empty()
+    }
+
+    pub(crate) fn rules(&self) -> Vec<FilterRule> {
+        self.rules
+            .read()
+            .unwrap()
+            .iter()
+            .map(|compiled| compiled.rule.clone())
+            .collect()
+    }
+
+    /// Makes sure the rule's pattern is usable before anything stores it
+    pub(crate) fn validate(rule: &FilterRule) -> Result<(), Error> {
+        CompiledRule::compile(rule.clone()).map(|_| ())
+    }
+
+    pub(crate) fn insert(&self, rule: FilterRule) -> Result<(), Error> {
+        let compiled = CompiledRule::compile(rule)?;
+
+        self.rules.write().unwrap().push(compiled);
+
+        Ok(())
+    }
+
+    /// Returns whether a rule with the given id existed
+    pub(crate) fn remove(&self, id: Uuid) -> bool {
+        let mut rules = self.rules.write().unwrap();
+        let len = rules.len();
+
+        rules.retain(|compiled| compiled.rule.id != id);
+
+        rules.len() != len
+    }
+
+    /// Checks an object's text against every rule. Drop wins over Restrict, and
+    /// several Restrict rules only relay to domains they all name
+    pub(crate) fn check(&self, object: &serde_json::Value) -> Verdict {
+        let text = searchable_text(object);
+        let lowercase = text.to_lowercase();
+
+        let mut verdict = Verdict::Relay;
+
+        for compiled in self.rules.read().unwrap().iter() {
+            if !compiled.is_match(&text, &lowercase) {
+                continue;
+            }
+
+            let action = match &compiled.rule.action {
+                FilterAction::Drop => "drop",
+                FilterAction::Restrict { .. } => "restrict",
+            };
+
+            metrics::counter!(
+                "relay.filter.hits",
+                "rule" => compiled.rule.id.to_string(),
+                "action" => action
+            )
+            .increment(1);
+
+            verdict = match (verdict, &compiled.rule.action) {
+                (_, FilterAction::Drop) | (Verdict::Drop, _) => Verdict::Drop,
+                (Verdict::Relay, FilterAction::Restrict { domains }) => {
+                    Verdict::Restrict(domains.clone())
+                }
+                (Verdict::Restrict(current), FilterAction::Restrict { domains }) => {
+                    Verdict::Restrict(current.intersection(domains).cloned().collect())
+                }
+            };
+        }
+
+        verdict
+    }
+
+    /// Like check, for an object that might not have been read. Without it no rule can be
+    /// ruled out, so it's dropped whenever there are rules
+    pub(crate) fn check_object(&self, object: Option<&serde_json::Value>) -> Verdict {
+        match object {
+            Some(object) => self.check(object),
+            None if self.is_empty() => Verdict::Relay,
+            None => Verdict::Drop,
+        }
+    }
+}
+
+// Gather the human-readable parts of a post, including every translation
+fn searchable_text(object: &serde_json::Value) -> String {
+    let mut parts = Vec::new();
+
+    for field in ["name", "summary", "content"] {
+        if let Some(s) = object.get(field).and_then(|v| v.as_str()) {
+            parts.push(s);
+        }
+
+        if let Some(map) = object
+            .get(format!("{field}Map"))
+            .and_then(|v| v.as_object())
+        {
+            parts.extend(map.values().filter_map(|v| v.as_str()));
+        }
+    }
+
+    parts.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilterAction, FilterRule, Filters, MatchKind, Verdict};
+    use uuid::Uuid;
+
+    fn rule(pattern: &str, kind: MatchKind, action: FilterAction) -> FilterRule {
+        FilterRule {
+            id: Uuid::new_v4(),
+            pattern: pattern.to_string(),
+            kind,
+            action,
+        }
+    }
+
+    fn restrict(domains: &[&str]) -> FilterAction {
+        FilterAction::Restrict {
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn keywords_match_any_case_and_translation() {
+        let filters =
+            Filters::new(vec![rule("Spam", MatchKind::Keyword, FilterAction::Drop)]).unwrap();
+
+        let post = serde_json::json!({
+            "content": "hello",
+            "contentMap": { "de": "Hallo, SPAM" },
+        });
+
+        assert_eq!(filters.check(&post), Verdict::Drop);
+        assert_eq!(
+            filters.check(&serde_json::json!({ "content": "hello" })),
+            Verdict::Relay
+        );
+    }
+
+    #[test]
+    fn restrictions_narrow_and_drop_wins() {
+        let filters = Filters::new(vec![
+            rule("cats?", MatchKind::Regex, restrict(&["a.com", "b.com"])),
+            rule("kittens", MatchKind::Keyword, restrict(&["b.com"])),
+        ])
+        .unwrap();
+
+        let post = serde_json::json!({ "summary": "Cat pics", "content": "kittens" });
+        let verdict = filters.check(&post);
+
+        assert_eq!(verdict, Verdict::Restrict(["b.com".to_string()].into()));
+        assert!(verdict.allows("b.com"));
+        assert!(verdict.allows("social.b.com"));
+        assert!(!verdict.allows("a.com"));
+        assert!(!verdict.allows("notb.com"));
+
+        filters
+            .insert(rule("pics", MatchKind::Keyword, FilterAction::Drop))
+            .unwrap();
+        assert_eq!(filters.check(&post), Verdict::Drop);
+    }
+
+    #[test]
+    fn unread_objects_are_dropped_while_there_are_rules() {
+        let filters = Filters::new(vec![]).unwrap();
+        assert_eq!(filters.check_object(None), Verdict::Relay);
+
+        filters
+            .insert(rule("cats", MatchKind::Keyword, restrict(&["a.com"])))
+            .unwrap();
+        assert_eq!(filters.check_object(None), Verdict::Drop);
+    }
+
+    #[test]
+    fn invalid_regex_is_rejected() {
+        let bad = rule("(unclosed", MatchKind::Regex, FilterAction::Drop);
+
+        assert!(Filters::validate(&bad).is_err());
+    }
+}
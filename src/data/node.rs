@@ -1,6 +1,8 @@
 use crate::{
+    data::RateLimitPolicy,
     db::{Contact, Db, Info, Instance},
     error::{Error, ErrorKind},
+    requests::{BreakerPolicy, DeliveryPolicy},
 };
 use activitystreams::{iri, iri_string::types::IriString};
 use std::time::{Duration, SystemTime};
@@ -23,6 +25,152 @@ pub struct NodeConfig {
     pub(crate) is_allowlist: bool,
     /// Receive only mode. Silently drop all incoming activities.
     pub(crate) receive_only: bool,
+    /// Failures before the circuit breaker opens, overriding BREAKER_FAILURE_THRESHOLD.
+    #[serde(default)]
+    pub(crate) breaker_failure_threshold: Option<usize>,
+    /// Seconds to wait once the breaker opens, overriding BREAKER_FAILURE_WAIT.
+    #[serde(default)]
+    pub(crate) breaker_failure_wait: Option<u64>,
+    /// Longest wait between retries, overriding BREAKER_MAX_FAILURE_WAIT.
+    #[serde(default)]
+    pub(crate) breaker_max_failure_wait: Option<u64>,
+    /// Languages to relay to this authority, matched on the primary subtag. Empty relays all.
+    #[serde(default)]
+    pub(crate) languages: HashSet<String>,
+    /// Relay posts in an unknown language when languages is set.
+    #[serde(default = "accept_unknown_language_default")]
+    pub(crate) accept_unknown_language: bool,
+    /// Don't relay posts marked sensitive to this authority.
+    #[serde(default)]
+    pub(crate) reject_sensitive: bool,
+    /// Don't relay posts with attachments to this authority.
+    #[serde(default)]
+    pub(crate) reject_media: bool,
+    /// Only relay posts with attachments when they have a content warning.
+    #[serde(default)]
+    pub(crate) require_cw_for_media: bool,
+    /// How posts are delivered to this authority, overriding the mode detected from nodeinfo.
+    #[serde(default)]
+    pub(crate) delivery_mode: Option<DeliveryMode>,
+    /// Inbox requests per second from this authority, overriding INBOX_RATE_LIMIT.
+    #[serde(default)]
+    pub(crate) inbox_rate_limit: Option<u32>,
+    /// Inbox requests allowed at once from this authority, overriding INBOX_RATE_BURST.
+    #[serde(default)]
+    pub(crate) inbox_rate_burst: Option<u32>,
+    /// Deliveries to this authority at once, overriding DELIVER_AUTHORITY_CONCURRENCY.
+    #[serde(default)]
+    pub(crate) deliver_concurrency: Option<usize>,
+    /// Deliveries per second to this authority, overriding DELIVER_AUTHORITY_RATE.
+    #[serde(default)]
+    pub(crate) deliver_rate_limit: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DeliveryMode {
+    /// Posts arrive as an Announce from the relay actor
+    #[default]
+    Mastodon,
+    /// Posts arrive as the original activity, unchanged
+    Litepub,
+}
+
+impl DeliveryMode {
+    /// The mode a server expects, going by the software name in its nodeinfo
+    pub(crate) fn detect(software: &str) -> Self {
+        match software.to_lowercase().as_str() {
+            "pleroma" | "akkoma" => DeliveryMode::Litepub,
+            _ => DeliveryMode::Mastodon,
+        }
+    }
+}
+
+fn accept_unknown_language_default() -> bool {
+    true
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            probability: 0,
+            enable_probability: false,
+            authority_set: HashSet::new(),
+            is_allowlist: false,
+            receive_only: false,
+            breaker_failure_threshold: None,
+            breaker_failure_wait: None,
+            breaker_max_failure_wait: None,
+            languages: HashSet::new(),
+            accept_unknown_language: accept_unknown_language_default(),
+            reject_sensitive: false,
+            reject_media: false,
+            require_cw_for_media: false,
+            delivery_mode: None,
+            inbox_rate_limit: None,
+            inbox_rate_burst: None,
+            deliver_concurrency: None,
+            deliver_rate_limit: None,
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Whether delivering to this authority depends on the relayed object's contents
+    pub(crate) fn wants_object(&self) -> bool {
+        !self.languages.is_empty()
+            || self.reject_sensitive
+            || self.reject_media
+            || self.require_cw_for_media
+    }
+
+    /// The breaker policy for this authority, if it overrides the default
+    pub(crate) fn breaker_policy(&self, default: BreakerPolicy) -> Option<BreakerPolicy> {
+        if self.breaker_failure_threshold.is_none()
+            && self.breaker_failure_wait.is_none()
+            && self.breaker_max_failure_wait.is_none()
+        {
+            return None;
+        }
+
+        Some(BreakerPolicy {
+            failure_threshold: self
+                .breaker_failure_threshold
+                .unwrap_or(default.failure_threshold),
+            failure_wait: self
+                .breaker_failure_wait
+                .map(Duration::from_secs)
+                .unwrap_or(default.failure_wait),
+            max_failure_wait: self
+                .breaker_max_failure_wait
+                .map(Duration::from_secs)
+                .unwrap_or(default.max_failure_wait),
+        })
+    }
+
+    /// The inbox rate limit for this authority, if it overrides the default
+    pub(crate) fn rate_limit_policy(&self, default: RateLimitPolicy) -> Option<RateLimitPolicy> {
+        if self.inbox_rate_limit.is_none() && self.inbox_rate_burst.is_none() {
+            return None;
+        }
+
+        Some(RateLimitPolicy {
+            rate: self.inbox_rate_limit.unwrap_or(default.rate),
+            burst: self.inbox_rate_burst.unwrap_or(default.burst),
+        })
+    }
+
+    /// The delivery limits for this authority, if it overrides the default
+    pub(crate) fn delivery_policy(&self, default: DeliveryPolicy) -> Option<DeliveryPolicy> {
+        if self.deliver_concurrency.is_none() && self.deliver_rate_limit.is_none() {
+            return None;
+        }
+
+        Some(DeliveryPolicy {
+            concurrency: self.deliver_concurrency.unwrap_or(default.concurrency),
+            rate: self.deliver_rate_limit.unwrap_or(default.rate),
+        })
+    }
 }
 
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
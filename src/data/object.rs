@@ -0,0 +1,76 @@
+use crate::{db::Db, error::Error};
+use activitystreams::iri_string::types::IriString;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ObjectCachePolicy {
+    /// Most objects kept at once, oldest are evicted first
+    pub(crate) capacity: usize,
+    /// How long a stored object stays fresh
+    pub(crate) ttl: Duration,
+}
+
+/// Keeps copies of relayed objects so subscribers don't all fetch them from the origin
+#[derive(Clone, Debug)]
+pub struct ObjectCache {
+    db: Db,
+    policy: ObjectCachePolicy,
+}
+
+impl ObjectCache {
+    pub(crate) fn new(db: Db, policy: ObjectCachePolicy) -> Self {
+        ObjectCache { db, policy }
+    }
+
+    #[tracing::instrument(level = "debug", name = "Get cached object", skip_all, fields(object_id = object_id.to_string().as_str()))]
+    pub(crate) async fn get(
+        &self,
+        object_id: &IriString,
+    ) -> Result<Option<serde_json::Value>, Error> {
+        match self.db.cached_object_id(object_id.clone()).await? {
+            Some(uuid) => self.get_by_uuid(uuid).await,
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(level = "debug", name = "Get cached object by uuid", skip(self))]
+    pub(crate) async fn get_by_uuid(&self, uuid: Uuid) -> Result<Option<serde_json::Value>, Error> {
+        let Some((object, saved_at)) = self.db.cached_object(uuid).await? else {
+            return Ok(None);
+        };
+
+        let expired = saved_at
+            .checked_add(self.policy.ttl)
+            .is_some_and(|expires_at| expires_at < SystemTime::now());
+
+        if expired {
+            return Ok(None);
+        }
+
+        Ok(Some(object))
+    }
+
+    #[tracing::instrument(name = "Store object", skip_all, fields(object_id = object_id.to_string().as_str()))]
+    pub(crate) async fn store(
+        &self,
+        object_id: IriString,
+        object: serde_json::Value,
+    ) -> Result<Uuid, Error> {
+        let uuid = Uuid::new_v4();
+
+        self.db
+            .save_cached_object(
+                uuid,
+                object_id,
+                object,
+                self.policy.capacity,
+                SystemTime::now()
+                    .checked_sub(self.policy.ttl)
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+            )
+            .await?;
+
+        Ok(uuid)
+    }
+}
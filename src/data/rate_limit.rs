@@ -0,0 +1,225 @@
+use dashmap::DashMap;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// How often buckets that have refilled are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RateLimitPolicy {
+    /// Requests allowed per second once the burst is spent, 0 turns the limit off
+    pub(crate) rate: u32,
+    /// Requests allowed at once
+    pub(crate) burst: u32,
+}
+
+/// Token buckets for inbox requests, keyed by the authority of the signing key
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    buckets: Arc<DashMap<String, Bucket>>,
+    policy: RateLimitPolicy,
+    overrides: Arc<DashMap<String, RateLimitPolicy>>,
+    last_prune: Arc<Mutex<Instant>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl RateLimitPolicy {
+    fn is_enabled(&self) -> bool {
+        self.rate > 0
+    }
+
+    // At least one token, so a burst of 0 still lets requests through at the rate
+    fn capacity(&self) -> f64 {
+        f64::from(self.burst.max(1))
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self, policy: &RateLimitPolicy, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * f64::from(policy.rate)).min(policy.capacity());
+        self.updated = now;
+    }
+
+    fn wait(&self, policy: &RateLimitPolicy) -> Duration {
+        Duration::from_secs_f64((1.0 - self.tokens) / f64::from(policy.rate))
+    }
+}
+
+impl RateLimiter {
+    pub(crate) fn new(policy: RateLimitPolicy) -> Self {
+        RateLimiter {
+            buckets: Arc::new(DashMap::new()),
+            policy,
+            overrides: Arc::new(DashMap::new()),
+            last_prune: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub(crate) fn policy(&self) -> RateLimitPolicy {
+        self.policy
+    }
+
+    pub(crate) fn set_override(&self, authority: &str, policy: Option<RateLimitPolicy>) {
+        if let Some(policy) = policy {
+            self.overrides.insert(authority.to_owned(), policy);
+        } else {
+            self.overrides.remove(authority);
+        }
+    }
+
+    fn policy_for(&self, authority: &str) -> RateLimitPolicy {
+        self.overrides
+            .get(authority)
+            .map(|policy| *policy)
+            .unwrap_or(self.policy)
+    }
+
+    /// Takes a token for the authority, or returns how long until one is available
+    pub(crate) fn check(&self, authority: &str, now: Instant) -> Result<(), Duration> {
+        let policy = self.policy_for(authority);
+
+        if !policy.is_enabled() {
+            return Ok(());
+        }
+
+        self.prune(now);
+
+        let mut bucket = self.buckets.entry(authority.to_owned()).or_insert(Bucket {
+            tokens: policy.capacity(),
+            updated: now,
+        });
+
+        bucket.refill(&policy, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(bucket.wait(&policy))
+    }
+
+    /// Returns how long until the authority has a token, without taking one
+    pub(crate) fn peek(&self, authority: &str, now: Instant) -> Result<(), Duration> {
+        let policy = self.policy_for(authority);
+
+        if !policy.is_enabled() {
+            return Ok(());
+        }
+
+        // a missing bucket is a full one
+        let Some(mut bucket) = self.buckets.get(authority).map(|bucket| *bucket) else {
+            return Ok(());
+        };
+
+        bucket.refill(&policy, now);
+
+        if bucket.tokens >= 1.0 {
+            return Ok(());
+        }
+
+        Err(bucket.wait(&policy))
+    }
+
+    // Full buckets behave the same as missing ones, so they don't need to be kept
+    fn prune(&self, now: Instant) {
+        {
+            let Ok(mut last_prune) = self.last_prune.try_lock() else {
+                return;
+            };
+
+            if now.saturating_duration_since(*last_prune) < PRUNE_INTERVAL {
+                return;
+            }
+
+            *last_prune = now;
+        }
+
+        self.buckets.retain(|authority, bucket| {
+            let policy = self.policy_for(authority);
+            let mut bucket = *bucket;
+            bucket.refill(&policy, now);
+
+            bucket.tokens < policy.capacity()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimitPolicy, RateLimiter};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn bursts_then_waits_for_the_rate() {
+        let limiter = RateLimiter::new(RateLimitPolicy { rate: 2, burst: 3 });
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check("example.com", now).is_ok());
+        }
+
+        let wait = limiter.check("example.com", now).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+
+        // other authorities have their own bucket
+        assert!(limiter.check("other.example", now).is_ok());
+
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.check("example.com", later).is_ok());
+        assert!(limiter.check("example.com", later).is_err());
+    }
+
+    #[test]
+    fn overrides_replace_the_default_policy() {
+        let limiter = RateLimiter::new(RateLimitPolicy { rate: 1, burst: 1 });
+        let now = Instant::now();
+
+        limiter.set_override("big.example", Some(RateLimitPolicy { rate: 0, burst: 0 }));
+
+        for _ in 0..10 {
+            assert!(limiter.check("big.example", now).is_ok());
+        }
+
+        assert!(limiter.check("example.com", now).is_ok());
+        assert!(limiter.check("example.com", now).is_err());
+
+        limiter.set_override("big.example", None);
+
+        assert!(limiter.check("big.example", now).is_ok());
+        assert!(limiter.check("big.example", now).is_err());
+    }
+
+    #[test]
+    fn peeking_takes_no_tokens() {
+        let limiter = RateLimiter::new(RateLimitPolicy { rate: 1, burst: 1 });
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.peek("example.com", now).is_ok());
+        }
+
+        assert!(limiter.check("example.com", now).is_ok());
+        assert_eq!(
+            limiter.peek("example.com", now).unwrap_err(),
+            Duration::from_secs(1)
+        );
+    }
+}
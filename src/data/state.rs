@@ -1,30 +1,58 @@
 use crate::{
-    data::NodeCache,
-    db::Db,
-    error::Error,
-    requests::{Breakers, Requests},
+    data::{
+        DeliveryMode, FilterRule, Filters, NodeCache, ObjectCache, ObjectCachePolicy,
+        RateLimitPolicy, RateLimiter,
+    },
+    db::{Db, PendingFollow},
+    error::{Error, ErrorKind},
+    jobs::{
+        apub::{Deny, Follow, FollowUpstream, Reject, UnfollowUpstream, UpdateActor},
+        Deliver, JobServer,
+    },
+    requests::{BreakerPolicy, Breakers, DeliveryLimits, DeliveryPolicy, Requests},
     spawner::Spawner,
 };
 use activitystreams::iri_string::types::IriString;
 use actix_web::web;
-use lru::LruCache;
 use rand::thread_rng;
 use reqwest_middleware::ClientWithMiddleware;
-use rsa::{RsaPrivateKey, RsaPublicKey};
-use std::sync::{Arc, RwLock};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use rsa::{pkcs8::DecodePrivateKey, RsaPrivateKey, RsaPublicKey};
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
 
 use super::LastOnline;
 use super::node::NodeConfig;
 
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DedupPolicy {
+    /// Most announced objects to remember, oldest are forgotten first
+    pub(crate) capacity: usize,
+    /// How long an announced object counts as a duplicate
+    pub(crate) window: Duration,
+}
+
 #[derive(Clone)]
 pub struct State {
     pub(crate) requests: Requests,
-    pub(crate) public_key: RsaPublicKey,
-    object_cache: Arc<RwLock<LruCache<IriString, IriString>>>,
+    public_key: Arc<RwLock<RsaPublicKey>>,
+    /// The key id, public key and expiry of the key from before the last rotation
+    retired_public_key: Arc<RwLock<Option<(String, RsaPublicKey, SystemTime)>>>,
+    /// Published in the actor's assertionMethod when the relay signs with Ed25519
+    pub(crate) ed25519_public_key: Option<Vec<u8>>,
+    dedup_policy: DedupPolicy,
     pub(crate) node_cache: NodeCache,
     pub(crate) node_config: Arc<RwLock<HashMap<String, NodeConfig>>>,
-    breakers: Breakers,
+    pub(crate) breakers: Breakers,
+    pub(crate) inbox_limiter: RateLimiter,
+    pub(crate) delivery_limits: DeliveryLimits,
+    pub(crate) filters: Filters,
+    pub(crate) objects: Option<ObjectCache>,
     pub(crate) last_online: Arc<LastOnline>,
     pub(crate) db: Db,
 }
@@ -34,6 +62,10 @@ impl std::fmt::Debug for State {
         f.debug_struct("State")
             .field("node_cache", &self.node_cache)
             .field("breakers", &self.breakers)
+            .field("inbox_limiter", &self.inbox_limiter)
+            .field("delivery_limits", &self.delivery_limits)
+            .field("filters", &self.filters)
+            .field("objects", &self.objects)
             .field("db", &self.db)
             .finish()
     }
@@ -71,12 +103,44 @@ impl State {
             .collect())
     }
 
-    pub(crate) async fn set_authority_cfg(&self, authority: &str, cfg: NodeConfig) {
-        self.node_config.write().unwrap().insert(authority.to_string(), cfg);
+    pub(crate) async fn set_authority_cfg(
+        &self,
+        authority: &str,
+        cfg: NodeConfig,
+    ) -> Result<(), Error> {
+        self.db
+            .save_node_config(authority.to_string(), cfg.clone())
+            .await?;
+
+        self.breakers
+            .set_override(authority, cfg.breaker_policy(self.breakers.policy()));
+        self.inbox_limiter.set_override(
+            authority,
+            cfg.rate_limit_policy(self.inbox_limiter.policy()),
+        );
+        self.delivery_limits.set_override(
+            authority,
+            cfg.delivery_policy(self.delivery_limits.policy()),
+        );
+
+        self.node_config
+            .write()
+            .unwrap()
+            .insert(authority.to_string(), cfg);
+
+        Ok(())
     }
 
-    pub(crate) async fn clear_authority_cfg(&self, authority: &str) {
+    pub(crate) async fn clear_authority_cfg(&self, authority: &str) -> Result<(), Error> {
+        self.db.remove_node_config(authority.to_string()).await?;
+
+        self.breakers.set_override(authority, None);
+        self.inbox_limiter.set_override(authority, None);
+        self.delivery_limits.set_override(authority, None);
+
         self.node_config.write().unwrap().remove(authority);
+
+        Ok(())
     }
 
     pub(crate) async fn get_authority_cfg(&self, authority: &str) -> Option<NodeConfig> {
@@ -87,65 +151,426 @@ impl State {
         self.node_config.read().unwrap().clone()
     }
 
-    pub(crate) fn is_cached(&self, object_id: &IriString) -> bool {
-        self.object_cache.read().unwrap().contains(object_id)
+    /// Returns whether the authority had a breaker to reset
+    pub(crate) async fn reset_breaker(&self, authority: &str) -> Result<bool, Error> {
+        self.db.remove_breaker(authority.to_string()).await?;
+
+        Ok(self.breakers.remove(authority))
+    }
+
+    pub(crate) async fn add_filter(&self, mut rule: FilterRule) -> Result<FilterRule, Error> {
+        rule.id = Uuid::new_v4();
+
+        Filters::validate(&rule)?;
+
+        self.db.save_filter_rule(rule.clone()).await?;
+        self.filters.insert(rule.clone())?;
+
+        Ok(rule)
+    }
+
+    /// Returns whether there was a filter with the given id
+    pub(crate) async fn remove_filter(&self, id: Uuid) -> Result<bool, Error> {
+        self.db.remove_filter_rule(id).await?;
+
+        Ok(self.filters.remove(id))
+    }
+
+    /// Blocks the given domains and tells their connected actors they've been removed
+    pub(crate) async fn block_domains(
+        &self,
+        jobs: &JobServer,
+        domains: Vec<String>,
+    ) -> Result<(), Error> {
+        for actor in self.db.add_blocks(domains).await? {
+            jobs.queue(Reject(actor)).await?;
+        }
+
+        Ok(())
     }
 
-    pub(crate) fn cache(&self, object_id: IriString, actor_id: IriString) {
-        self.object_cache.write().unwrap().put(object_id, actor_id);
+    /// Disallows the given domains, and in restricted mode removes their connected actors
+    pub(crate) async fn disallow_domains(
+        &self,
+        jobs: &JobServer,
+        domains: Vec<String>,
+    ) -> Result<(), Error> {
+        for actor in self.db.remove_allows(domains).await? {
+            jobs.queue(Reject(actor)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Allows the given domains and accepts their pending follows
+    pub(crate) async fn approve_follows(
+        &self,
+        jobs: &JobServer,
+        domains: Vec<String>,
+    ) -> Result<Vec<PendingFollow>, Error> {
+        self.db.add_allows(domains.clone()).await?;
+
+        let pending = self.db.take_pending_follows(domains).await?;
+
+        for PendingFollow {
+            input, actor, tag, ..
+        } in pending.clone()
+        {
+            jobs.queue(Follow::new(input, actor).with_tag(tag)).await?;
+        }
+
+        Ok(pending)
+    }
+
+    /// Delivers the given dead letters again, returning how many were found
+    pub(crate) async fn replay_dead_letters(
+        &self,
+        jobs: &JobServer,
+        ids: Vec<Uuid>,
+    ) -> Result<usize, Error> {
+        let dead_letters = self.db.remove_dead_letters(ids).await?;
+
+        for dead_letter in &dead_letters {
+            jobs.queue(Deliver::new(
+                dead_letter.inbox.clone(),
+                dead_letter.activity.clone(),
+            )?)
+            .await?;
+        }
+
+        Ok(dead_letters.len())
+    }
+
+    /// Rejects the pending follows from the given domains
+    pub(crate) async fn deny_follows(
+        &self,
+        jobs: &JobServer,
+        domains: Vec<String>,
+    ) -> Result<Vec<PendingFollow>, Error> {
+        let pending = self.db.take_pending_follows(domains).await?;
+
+        for PendingFollow { input, actor, .. } in pending.clone() {
+            jobs.queue(Deny::new(input, actor)).await?;
+        }
+
+        Ok(pending)
+    }
+
+    /// Follows the given relay actors, allowing their domains so their posts are accepted
+    pub(crate) async fn follow_upstreams(
+        &self,
+        jobs: &JobServer,
+        actor_ids: Vec<IriString>,
+    ) -> Result<(), Error> {
+        for actor_id in actor_ids {
+            if self.db.is_blocked(actor_id.clone()).await? {
+                return Err(ErrorKind::NotAllowed(actor_id.to_string()).into());
+            }
+
+            let authority = actor_id.authority_str().ok_or(ErrorKind::MissingDomain)?;
+            let added_allow = !self.db.is_allowed(actor_id.clone()).await?;
+            if added_allow {
+                self.db.add_allows(vec![authority.to_string()]).await?;
+            }
+
+            jobs.queue(FollowUpstream {
+                actor_id,
+                added_allow,
+            })
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops following the given relay actors
+    pub(crate) async fn unfollow_upstreams(
+        &self,
+        jobs: &JobServer,
+        actor_ids: Vec<IriString>,
+    ) -> Result<(), Error> {
+        for actor_id in actor_ids {
+            let Some(upstream) = self.db.remove_upstream(actor_id).await? else {
+                continue;
+            };
+
+            let authority = upstream.actor_id.authority_str().map(String::from);
+            let added_allow = upstream.added_allow;
+
+            jobs.queue(UnfollowUpstream(upstream)).await?;
+
+            // only undo an allow that following added, once no other upstream shares the domain.
+            // In a mesh the upstream relay often subscribes too, and it keeps its subscription
+            if let Some(authority) = authority.filter(|_| added_allow) {
+                let upstreams = self.db.upstreams().await?;
+                let still_followed = upstreams
+                    .iter()
+                    .any(|upstream| upstream.actor_id.authority_str() == Some(&*authority));
+
+                if !still_followed && !self.db.is_domain_connected(authority.clone()).await? {
+                    self.db.remove_allows(vec![authority]).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether relaying needs the object itself, for filters or per-subscriber routing
+    pub(crate) async fn needs_object(&self) -> Result<bool, Error> {
+        if self.objects.is_some() || !self.filters.is_empty() {
+            return Ok(true);
+        }
+
+        let node_configs_want_object = self
+            .node_config
+            .read()
+            .map(|node_config| node_config.values().any(NodeConfig::wants_object))
+            .unwrap_or(false);
+
+        if node_configs_want_object {
+            return Ok(true);
+        }
+
+        self.db.has_tag_subscriptions().await
+    }
+
+    /// Delivery modes by authority, detected from nodeinfo unless a node config overrides them
+    pub(crate) async fn delivery_modes(&self) -> Result<HashMap<String, DeliveryMode>, Error> {
+        let mut modes: HashMap<String, DeliveryMode> = self
+            .db
+            .connected_info()
+            .await?
+            .into_iter()
+            .filter_map(|(actor_id, info)| {
+                let authority = actor_id.authority_str()?.to_string();
+
+                Some((authority, DeliveryMode::detect(&info.software)))
+            })
+            .collect();
+
+        if let Ok(node_config) = self.node_config.read() {
+            for (authority, cfg) in node_config.iter() {
+                if let Some(mode) = cfg.delivery_mode {
+                    modes.insert(authority.clone(), mode);
+                }
+            }
+        }
+
+        Ok(modes)
+    }
+
+    /// Whether the object was already announced within the dedup window
+    pub(crate) async fn is_duplicate(&self, object_id: &IriString) -> Result<bool, Error> {
+        let relayed_at = self.db.relayed_at(object_id.clone()).await?;
+
+        let duplicate = relayed_at.is_some_and(|relayed_at| {
+            relayed_at
+                .checked_add(self.dedup_policy.window)
+                .map_or(true, |until| until > SystemTime::now())
+        });
+
+        if duplicate {
+            metrics::counter!("relay.dedup.hit").increment(1);
+        } else {
+            metrics::counter!("relay.dedup.miss").increment(1);
+        }
+
+        Ok(duplicate)
+    }
+
+    pub(crate) async fn record_relayed(
+        &self,
+        object_id: IriString,
+        activity_id: IriString,
+    ) -> Result<(), Error> {
+        self.db
+            .record_relayed(object_id, activity_id, self.dedup_policy.capacity)
+            .await
+    }
+
+    /// Forgets objects announced before the dedup window
+    pub(crate) async fn expire_relayed(&self) -> Result<usize, Error> {
+        self.db
+            .expire_relayed(
+                SystemTime::now()
+                    .checked_sub(self.dedup_policy.window)
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+            )
+            .await
+    }
+
+    pub(crate) fn public_key(&self) -> RsaPublicKey {
+        self.public_key.read().unwrap().clone()
+    }
+
+    /// The key id and public key of the retired key, published until it expires
+    pub(crate) fn retired_public_key(&self) -> Option<(String, RsaPublicKey)> {
+        self.retired_public_key
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|(_, _, expires_at)| *expires_at > SystemTime::now())
+            .map(|(key_id, public_key, _)| (key_id.clone(), public_key.clone()))
+    }
+
+    /// Signs with a new key from now on, offering the old one to servers that still have it
+    /// cached until the grace period ends, and tells subscribers to refresh the relay's actor
+    pub(crate) async fn rotate_key(
+        &self,
+        jobs: &JobServer,
+        key_id: String,
+        grace: Duration,
+    ) -> Result<(), Error> {
+        let private_key = generate_private_key().await?;
+        let expires_at = SystemTime::now() + grace;
+
+        self.db
+            .rotate_private_key(
+                &private_key,
+                key_id.clone(),
+                self.requests.key_id(),
+                expires_at,
+            )
+            .await?;
+
+        let retired_key_id = self.requests.key_id();
+        self.requests.rotate_key(key_id, &private_key, expires_at);
+
+        let retired_public_key = std::mem::replace(
+            &mut *self.public_key.write().unwrap(),
+            private_key.to_public_key(),
+        );
+        *self.retired_public_key.write().unwrap() =
+            Some((retired_key_id, retired_public_key, expires_at));
+
+        jobs.queue(UpdateActor).await?;
+
+        Ok(())
     }
 
     pub(crate) fn is_connected(&self, iri: &IriString) -> bool {
         self.breakers.should_try(iri)
     }
 
+    async fn ed25519_key_pair(db: &Db) -> Result<Ed25519KeyPair, Error> {
+        let pkcs8 = if let Some(pkcs8) = db.ed25519_private_key().await? {
+            tracing::debug!("Using existing Ed25519 key");
+            pkcs8
+        } else {
+            tracing::info!("Generating new Ed25519 key");
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                .map_err(|_| ErrorKind::GenerateKey)?
+                .as_ref()
+                .to_vec();
+
+            db.update_ed25519_private_key(pkcs8.clone()).await?;
+
+            pkcs8
+        };
+
+        Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|_| ErrorKind::GenerateKey.into())
+    }
+
     #[tracing::instrument(level = "debug", name = "Building state", skip_all)]
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn build(
         db: Db,
         key_id: String,
         spawner: Spawner,
         client: ClientWithMiddleware,
-        node_config: HashMap<String, NodeConfig>,
+        breaker_policy: BreakerPolicy,
+        object_cache_policy: Option<ObjectCachePolicy>,
+        dedup_policy: DedupPolicy,
+        rate_limit_policy: RateLimitPolicy,
+        delivery_policy: DeliveryPolicy,
+        ed25519_key_id: Option<String>,
     ) -> Result<Self, Error> {
         let private_key = if let Ok(Some(key)) = db.private_key().await {
             tracing::debug!("Using existing key");
             key
         } else {
             tracing::info!("Generating new keys");
-            let key = web::block(move || {
-                let mut rng = thread_rng();
-                RsaPrivateKey::new(&mut rng, 4096)
-            })
-            .await??;
+            let key = generate_private_key().await?;
 
             db.update_private_key(&key).await?;
 
             key
         };
 
+        // a rotated key has its own id
+        let key_id = db.key_id().await?.unwrap_or(key_id);
+
         let public_key = private_key.to_public_key();
 
-        let breakers = Breakers::default();
+        let node_config = db.node_configs().await?;
+        tracing::debug!("Loaded {} node configs", node_config.len());
+
+        let breakers = Breakers::restore(db.breakers().await?, breaker_policy);
+
+        let inbox_limiter = RateLimiter::new(rate_limit_policy);
+        let delivery_limits = DeliveryLimits::new(delivery_policy);
+
+        for (authority, cfg) in &node_config {
+            breakers.set_override(authority, cfg.breaker_policy(breaker_policy));
+            inbox_limiter.set_override(authority, cfg.rate_limit_policy(rate_limit_policy));
+            delivery_limits.set_override(authority, cfg.delivery_policy(delivery_policy));
+        }
+        let filters = Filters::new(db.filter_rules().await?)?;
+        tracing::debug!("Loaded {} filter rules", filters.rules().len());
+
         let last_online = Arc::new(LastOnline::empty());
 
-        let requests = Requests::new(
+        let mut requests = Requests::new(
             key_id,
             private_key,
             breakers.clone(),
+            delivery_limits.clone(),
             last_online.clone(),
             spawner,
             client,
         );
 
+        let mut retired_public_key = None;
+
+        if let Some(retired) = db.retired_key().await? {
+            if retired.expires_at > SystemTime::now() {
+                let private_key = RsaPrivateKey::from_pkcs8_pem(&retired.private_key)?;
+
+                retired_public_key = Some((
+                    retired.key_id.clone(),
+                    private_key.to_public_key(),
+                    retired.expires_at,
+                ));
+                requests =
+                    requests.with_retired_key(retired.key_id, &private_key, retired.expires_at);
+            }
+        }
+
+        let ed25519_public_key = if let Some(ed25519_key_id) = ed25519_key_id {
+            let key_pair = Self::ed25519_key_pair(&db).await?;
+            let public_key = key_pair.public_key().as_ref().to_vec();
+
+            requests = requests.with_ed25519_key(ed25519_key_id, key_pair);
+
+            Some(public_key)
+        } else {
+            None
+        };
+
         let state = State {
             requests,
-            public_key,
-            object_cache: Arc::new(RwLock::new(LruCache::new(
-                (1024 * 8).try_into().expect("nonzero"),
-            ))),
+            public_key: Arc::new(RwLock::new(public_key)),
+            retired_public_key: Arc::new(RwLock::new(retired_public_key)),
+            ed25519_public_key,
+            dedup_policy,
             node_cache: NodeCache::new(db.clone()),
             node_config: Arc::new(RwLock::new(node_config)),
             breakers,
+            inbox_limiter,
+            delivery_limits,
+            filters,
+            objects: object_cache_policy.map(|policy| ObjectCache::new(db.clone(), policy)),
             db,
             last_online,
         };
@@ -153,3 +578,13 @@ impl State {
         Ok(state)
     }
 }
+
+async fn generate_private_key() -> Result<RsaPrivateKey, Error> {
+    let key = web::block(move || {
+        let mut rng = thread_rng();
+        RsaPrivateKey::new(&mut rng, 4096)
+    })
+    .await??;
+
+    Ok(key)
+}
@@ -1,24 +1,31 @@
 use crate::{
+    apub::AcceptedActivities,
     config::Config,
+    data::{FilterRule, NodeConfig},
     error::{Error, ErrorKind},
+    requests::Breaker,
 };
 use activitystreams::iri_string::types::IriString;
+use background_jobs::dev::{JobInfo, ReturnJobInfo};
 use rsa::{
     pkcs8::{DecodePrivateKey, EncodePrivateKey},
     RsaPrivateKey,
 };
-use sled::{Batch, Tree};
+use serde::Deserialize;
+use sled::{transaction::TransactionError, Batch, Tree};
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
     },
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 use time::OffsetDateTime;
 use uuid::Uuid;
 
+const OUTBOX_SIZE: usize = 100;
+
 #[derive(Clone, Debug)]
 pub(crate) struct Db {
     inner: Arc<Inner>,
@@ -27,6 +34,7 @@ pub(crate) struct Db {
 struct Inner {
     healthz: Tree,
     healthz_counter: Arc<AtomicU64>,
+    last_saved_at: AtomicU64,
     actor_id_actor: Tree,
     public_key_id_actor_id: Tree,
     connected_actor_ids: Tree,
@@ -39,6 +47,33 @@ struct Inner {
     actor_id_instance: Tree,
     actor_id_contact: Tree,
     last_seen: Tree,
+    authority_node_config: Tree,
+    job_id_job: Tree,
+    job_queue: Tree,
+    following_actor_ids: Tree,
+    outbox: Tree,
+    breakers: Tree,
+    pending_follows: Tree,
+    pending_follow_count: AtomicUsize,
+    actor_id_follow_id: Tree,
+    filter_rules: Tree,
+    actor_id_tags: Tree,
+    inbox_tags: Tree,
+    object_id_object_uuid: Tree,
+    object_uuid_object: Tree,
+    cached_object_count: AtomicUsize,
+    object_age: Tree,
+    object_id_relayed: Tree,
+    relayed_count: AtomicUsize,
+    relayed_age: Tree,
+    upstream_relays: Tree,
+    dead_letter_id_dead_letter: Tree,
+    dead_letter_count: AtomicUsize,
+    dead_letter_age: Tree,
+    inbox_batch: Tree,
+    batch_activities: Tree,
+    // keeps a batch's activities and its pending count in step
+    batch_lock: Mutex<()>,
     restricted_mode: bool,
 }
 
@@ -57,6 +92,29 @@ pub struct Actor {
     pub(crate) public_key_id: IriString,
     pub(crate) inbox: IriString,
     pub(crate) saved_at: SystemTime,
+    /// Ed25519 keys from the actor's assertionMethod (FEP-521a)
+    #[serde(default)]
+    pub(crate) ed25519_keys: Vec<Ed25519Key>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Ed25519Key {
+    pub(crate) id: IriString,
+    pub(crate) public_key: Vec<u8>,
+}
+
+impl Actor {
+    pub(crate) fn ed25519_key(&self, key_id: &str) -> Option<&[u8]> {
+        self.ed25519_keys
+            .iter()
+            .find(|key| key.id.as_str() == key_id)
+            .map(|key| key.public_key.as_slice())
+    }
+
+    /// Whether the actor signs with this key, either its RSA key or one of its Ed25519 keys
+    pub(crate) fn has_key(&self, key_id: &str) -> bool {
+        self.public_key_id.as_str() == key_id || self.ed25519_key(key_id).is_some()
+    }
 }
 
 impl std::fmt::Debug for Actor {
@@ -67,6 +125,78 @@ impl std::fmt::Debug for Actor {
             .field("public_key_id", &self.public_key_id.to_string())
             .field("inbox", &self.inbox.to_string())
             .field("saved_at", &self.saved_at)
+            .field("ed25519_keys", &self.ed25519_keys.len())
+            .finish()
+    }
+}
+
+/// A signing key replaced by a rotation, still offered to servers that cached it until it expires
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct RetiredKey {
+    pub(crate) key_id: String,
+    pub(crate) private_key: String,
+    pub(crate) expires_at: SystemTime,
+}
+
+impl std::fmt::Debug for RetiredKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetiredKey")
+            .field("key_id", &self.key_id)
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+// The batch an inbox's pending activities belong to. A delivery job only works on the batch
+// with its id, so a job left over from a batch that was replaced does nothing
+#[derive(serde::Deserialize, serde::Serialize)]
+struct InboxBatch {
+    id: Uuid,
+    pending: usize,
+}
+
+/// A follow from a domain that isn't allowed yet, waiting for an admin's decision
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct PendingFollow {
+    pub(crate) input: AcceptedActivities,
+    pub(crate) actor: Actor,
+    /// Set when the follow is for a single hashtag
+    #[serde(default)]
+    pub(crate) tag: Option<String>,
+    pub(crate) received_at: OffsetDateTime,
+}
+
+impl std::fmt::Debug for PendingFollow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingFollow")
+            .field("actor", &self.actor.id.to_string())
+            .field("tag", &self.tag)
+            .field("received_at", &self.received_at)
+            .finish()
+    }
+}
+
+/// A relay that this relay follows, whose posts are re-announced to subscribers
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct Upstream {
+    pub(crate) actor_id: IriString,
+    pub(crate) inbox: IriString,
+    pub(crate) follow_id: IriString,
+    /// Whether the upstream relay has accepted the follow
+    pub(crate) accepted: bool,
+    pub(crate) followed_at: OffsetDateTime,
+    /// Whether following allowed the upstream's domain, so unfollowing can disallow it again
+    #[serde(default)]
+    pub(crate) added_allow: bool,
+}
+
+impl std::fmt::Debug for Upstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Upstream")
+            .field("actor_id", &self.actor_id.to_string())
+            .field("follow_id", &self.follow_id.to_string())
+            .field("accepted", &self.accepted)
+            .field("followed_at", &self.followed_at)
             .finish()
     }
 }
@@ -110,6 +240,75 @@ impl std::fmt::Debug for Contact {
     }
 }
 
+#[derive(serde::Deserialize, serde::Serialize)]
+struct CachedObject {
+    object_id: IriString,
+    object: serde_json::Value,
+    saved_at: SystemTime,
+}
+
+/// A delivery that was given up on, kept so it can be looked into or sent again
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct DeadLetter {
+    pub(crate) id: Uuid,
+    pub(crate) inbox: IriString,
+    pub(crate) activity_id: Option<IriString>,
+    pub(crate) activity: serde_json::Value,
+    pub(crate) error: String,
+    /// Retries made before giving up
+    pub(crate) retries: u32,
+    pub(crate) queued_at: OffsetDateTime,
+    pub(crate) failed_at: OffsetDateTime,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Relayed {
+    activity_id: IriString,
+    relayed_at: SystemTime,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct QueuedJob {
+    job_id: Uuid,
+    heartbeat_interval: u64,
+    runner: Option<(Uuid, OffsetDateTime)>,
+    /// When the job was first queued, kept when it's put back for a retry
+    #[serde(default)]
+    queued_at: Option<OffsetDateTime>,
+}
+
+// Deliveries reschedule themselves as new jobs, carrying their retries and first queue time
+#[derive(serde::Deserialize)]
+struct RescheduledArgs {
+    #[serde(default)]
+    retries: u32,
+    queued_at: Option<SystemTime>,
+}
+
+/// How much work is waiting in one job queue
+#[derive(Clone, Debug, Default)]
+pub(crate) struct JobQueueStats {
+    /// Every job in the queue, including those running now
+    pub(crate) depth: usize,
+    pub(crate) in_flight: usize,
+    /// Jobs that have failed before and are waiting to try again
+    pub(crate) retrying: usize,
+    pub(crate) oldest_age: Option<Duration>,
+}
+
+impl QueuedJob {
+    // A job is free to run if nobody holds it, or if its runner stopped sending heartbeats
+    fn is_claimable(&self, now: OffsetDateTime) -> bool {
+        match self.runner {
+            Some((_, heartbeat)) => {
+                let interval = time::Duration::milliseconds(self.heartbeat_interval as i64);
+                heartbeat + interval * 5 < now
+            }
+            None => true,
+        }
+    }
+}
+
 impl Inner {
     fn connected_by_domain(
         &self,
@@ -136,6 +335,116 @@ impl Inner {
             })
     }
 
+    fn disconnect_by_domain(&self, domains: &[String]) -> Result<Vec<Actor>, Error> {
+        let connected: Vec<_> = self.connected_by_domain(domains).collect();
+        let mut actors = Vec::new();
+
+        for actor_id in connected {
+            let key = actor_id.as_str().as_bytes();
+
+            self.connected_actor_ids.remove(key)?;
+            self.following_actor_ids.remove(key)?;
+            self.actor_id_tags.remove(key)?;
+
+            if let Some(ivec) = self.actor_id_actor.get(key)? {
+                actors.push(serde_json::from_slice(&ivec)?);
+            }
+        }
+
+        Ok(actors)
+    }
+
+    fn remove_cached_object(&self, uuid: &[u8]) -> Result<(), Error> {
+        if let Some(ivec) = self.object_uuid_object.remove(uuid)? {
+            self.cached_object_count.fetch_sub(1, Ordering::Relaxed);
+
+            let cached: CachedObject = serde_json::from_slice(&ivec)?;
+            let object_key = cached.object_id.as_str().as_bytes();
+
+            // the id might already point at a newer copy
+            if self.object_id_object_uuid.get(object_key)?.as_deref() == Some(uuid) {
+                self.object_id_object_uuid.remove(object_key)?;
+            }
+
+            self.object_age.remove(age_key(cached.saved_at, uuid))?;
+        }
+
+        Ok(())
+    }
+
+    // Drops the record once its age entry is already gone
+    fn forget_relayed(&self, object_key: &[u8]) -> Result<(), Error> {
+        if self.object_id_relayed.remove(object_key)?.is_some() {
+            self.relayed_count.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    fn remove_relayed(&self, object_key: &[u8]) -> Result<(), Error> {
+        if let Some(ivec) = self.object_id_relayed.remove(object_key)? {
+            self.relayed_count.fetch_sub(1, Ordering::Relaxed);
+
+            let relayed: Relayed = serde_json::from_slice(&ivec)?;
+
+            self.relayed_age
+                .remove(age_key(relayed.relayed_at, object_key))?;
+        }
+
+        Ok(())
+    }
+
+    // Age keys sort by time, so keep saves in the same instant from sharing one
+    fn lock_batches(&self) -> MutexGuard<'_, ()> {
+        self.batch_lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn inbox_batch(&self, inbox: &str) -> Result<Option<InboxBatch>, Error> {
+        match self.inbox_batch.get(inbox)? {
+            Some(ivec) => Ok(Some(serde_json::from_slice(&ivec)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save_inbox_batch(&self, inbox: &str, batch: &InboxBatch) -> Result<(), Error> {
+        self.inbox_batch.insert(inbox, serde_json::to_vec(batch)?)?;
+        Ok(())
+    }
+
+    // Starts a new batch for whatever the inbox has pending, or marks it idle if that's nothing
+    fn restart_batch(&self, inbox: &str) -> Result<Option<Uuid>, Error> {
+        let pending = self
+            .batch_activities
+            .scan_prefix(batch_prefix(inbox))
+            .keys()
+            .count();
+
+        if pending == 0 {
+            self.inbox_batch.remove(inbox)?;
+            return Ok(None);
+        }
+
+        let id = Uuid::new_v4();
+        self.save_inbox_batch(inbox, &InboxBatch { id, pending })?;
+
+        Ok(Some(id))
+    }
+
+    fn next_saved_at(&self) -> SystemTime {
+        let now = nanos_since_epoch(SystemTime::now());
+
+        let previous = self
+            .last_saved_at
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or(now);
+
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(now.max(previous + 1))
+    }
+
     fn blocks(&self) -> impl DoubleEndedIterator<Item = String> {
         self.blocked_domains
             .iter()
@@ -160,6 +469,80 @@ impl Inner {
             .filter_map(url_from_ivec)
     }
 
+    fn saved_inbox(&self, actor_id: &[u8]) -> Result<Option<IriString>, Error> {
+        match self.actor_id_actor.get(actor_id)? {
+            Some(ivec) => Ok(Some(serde_json::from_slice::<Actor>(&ivec)?.inbox)),
+            None => Ok(None),
+        }
+    }
+
+    // Keeps the tags of an inbox's tag-scoped subscribers in step with actor_id_tags
+    fn index_tags(
+        &self,
+        inbox: &IriString,
+        actor_id: &IriString,
+        tags: Option<&BTreeSet<String>>,
+    ) -> Result<(), Error> {
+        self.inbox_tags
+            .update_and_fetch(inbox.as_str().as_bytes(), |ivec| {
+                let mut subscribers: BTreeMap<String, BTreeSet<String>> = ivec
+                    .and_then(|ivec| serde_json::from_slice(ivec).ok())
+                    .unwrap_or_default();
+
+                match tags {
+                    Some(tags) => subscribers.insert(actor_id.to_string(), tags.clone()),
+                    None => subscribers.remove(actor_id.as_str()),
+                };
+
+                if subscribers.is_empty() {
+                    None
+                } else {
+                    serde_json::to_vec(&subscribers).ok()
+                }
+            })?;
+
+        Ok(())
+    }
+
+    fn forget_tags(&self, actor_id: &IriString) -> Result<(), Error> {
+        let key = actor_id.as_str().as_bytes();
+
+        if self.actor_id_tags.remove(key)?.is_some() {
+            if let Some(inbox) = self.saved_inbox(key)? {
+                self.index_tags(&inbox, actor_id, None)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // A shared inbox belongs to the server its actors are on, so only that server's subscribers
+    // are checked
+    fn has_full_subscriber(&self, inbox: &IriString) -> Result<bool, Error> {
+        let Some(authority) = inbox.authority_str() else {
+            return Ok(false);
+        };
+        let prefix = format!("{}://{authority}", inbox.scheme_str());
+
+        for res in self
+            .connected_actor_ids
+            .scan_prefix(prefix.as_bytes())
+            .keys()
+        {
+            let actor_id = res?;
+
+            if self.actor_id_tags.contains_key(&actor_id)? {
+                continue;
+            }
+
+            if self.saved_inbox(&actor_id)?.as_ref() == Some(inbox) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
     fn connected_actors(&self) -> impl DoubleEndedIterator<Item = Actor> + '_ {
         self.connected_actor_ids
             .iter()
@@ -214,6 +597,44 @@ impl Inner {
             })
     }
 
+    fn insert_job(&self, job: &JobInfo, queued_at: OffsetDateTime) -> Result<(), Error> {
+        let queued = QueuedJob {
+            job_id: job.id,
+            heartbeat_interval: job.heartbeat_interval,
+            runner: None,
+            queued_at: Some(queued_at),
+        };
+
+        // write the job before its queue entry so a crash never leaves an entry without a job
+        self.job_id_job
+            .insert(job.id.as_bytes(), serde_json::to_vec(job)?)?;
+        self.job_queue.insert(
+            job_queue_key(&job.queue, job.next_queue, job.id),
+            serde_json::to_vec(&queued)?,
+        )?;
+
+        Ok(())
+    }
+
+    // Returns the job along with when it was first queued
+    fn remove_job(&self, job_id: Uuid) -> Result<Option<(JobInfo, OffsetDateTime)>, Error> {
+        let Some(ivec) = self.job_id_job.get(job_id.as_bytes())? else {
+            return Ok(None);
+        };
+
+        let job: JobInfo = serde_json::from_slice(&ivec)?;
+
+        let queued_at = self
+            .job_queue
+            .remove(job_queue_key(&job.queue, job.next_queue, job.id))?
+            .and_then(|ivec| serde_json::from_slice::<QueuedJob>(&ivec).ok())
+            .and_then(|queued| queued.queued_at)
+            .unwrap_or(job.next_queue);
+        self.job_id_job.remove(job_id.as_bytes())?;
+
+        Ok(Some((job, queued_at)))
+    }
+
     fn is_allowed(&self, authority: &str) -> bool {
         let prefix = domain_prefix(authority);
         let reverse_domain = domain_key(authority);
@@ -228,14 +649,20 @@ impl Inner {
                     reverse_domain.starts_with(rdnn_string.as_ref())
                 })
         } else {
-            !self
-                .blocked_domains
-                .scan_prefix(prefix)
-                .keys()
-                .filter_map(|res| res.ok())
-                .any(|rdnn| reverse_domain.starts_with(String::from_utf8_lossy(&rdnn).as_ref()))
+            !self.is_blocked(authority)
         }
     }
+
+    fn is_blocked(&self, authority: &str) -> bool {
+        let prefix = domain_prefix(authority);
+        let reverse_domain = domain_key(authority);
+
+        self.blocked_domains
+            .scan_prefix(prefix)
+            .keys()
+            .filter_map(|res| res.ok())
+            .any(|rdnn| reverse_domain.starts_with(String::from_utf8_lossy(&rdnn).as_ref()))
+    }
 }
 
 impl Db {
@@ -245,10 +672,21 @@ impl Db {
     }
 
     fn build_inner(restricted_mode: bool, db: sled::Db) -> Result<Self, Error> {
+        // counted once here, since sled has to walk a whole tree to count it
+        let pending_follows = db.open_tree("pending-follows")?;
+        let pending_follow_count = AtomicUsize::new(pending_follows.len());
+        let object_uuid_object = db.open_tree("object-uuid-object")?;
+        let cached_object_count = AtomicUsize::new(object_uuid_object.len());
+        let object_id_relayed = db.open_tree("object-id-relayed")?;
+        let relayed_count = AtomicUsize::new(object_id_relayed.len());
+        let dead_letter_id_dead_letter = db.open_tree("dead-letter-id-dead-letter")?;
+        let dead_letter_count = AtomicUsize::new(dead_letter_id_dead_letter.len());
+
         Ok(Db {
             inner: Arc::new(Inner {
                 healthz: db.open_tree("healthz")?,
                 healthz_counter: Arc::new(AtomicU64::new(0)),
+                last_saved_at: AtomicU64::new(0),
                 actor_id_actor: db.open_tree("actor-id-actor")?,
                 public_key_id_actor_id: db.open_tree("public-key-id-actor-id")?,
                 connected_actor_ids: db.open_tree("connected-actor-ids")?,
@@ -261,6 +699,32 @@ impl Db {
                 actor_id_instance: db.open_tree("actor-id-instance")?,
                 actor_id_contact: db.open_tree("actor-id-contact")?,
                 last_seen: db.open_tree("last-seen")?,
+                authority_node_config: db.open_tree("authority-node-config")?,
+                job_id_job: db.open_tree("job-id-job")?,
+                job_queue: db.open_tree("job-queue")?,
+                following_actor_ids: db.open_tree("following-actor-ids")?,
+                outbox: db.open_tree("outbox")?,
+                breakers: db.open_tree("breakers")?,
+                pending_follows,
+                pending_follow_count,
+                actor_id_follow_id: db.open_tree("actor-id-follow-id")?,
+                filter_rules: db.open_tree("filter-rules")?,
+                actor_id_tags: db.open_tree("actor-id-tags")?,
+                inbox_tags: db.open_tree("inbox-tags")?,
+                object_id_object_uuid: db.open_tree("object-id-object-uuid")?,
+                object_uuid_object,
+                cached_object_count,
+                object_age: db.open_tree("object-age")?,
+                object_id_relayed,
+                relayed_count,
+                relayed_age: db.open_tree("relayed-age")?,
+                upstream_relays: db.open_tree("upstream-relays")?,
+                dead_letter_id_dead_letter,
+                dead_letter_count,
+                dead_letter_age: db.open_tree("dead-letter-age")?,
+                inbox_batch: db.open_tree("inbox-batch")?,
+                batch_activities: db.open_tree("batch-activities")?,
+                batch_lock: Mutex::new(()),
                 restricted_mode,
             }),
         })
@@ -311,6 +775,58 @@ impl Db {
             .await
     }
 
+    /// Replaces the saved breakers, so ones that were reset or removed aren't loaded again
+    pub(crate) async fn save_breakers(
+        &self,
+        breakers: HashMap<String, Breaker>,
+    ) -> Result<(), Error> {
+        let mut batch = Batch::default();
+
+        for (authority, breaker) in &breakers {
+            batch.insert(authority.as_bytes(), serde_json::to_vec(breaker)?);
+        }
+
+        self.unblock(move |inner| {
+            for key in inner.breakers.iter().keys() {
+                let key = key?;
+
+                if !breakers.contains_key(String::from_utf8_lossy(&key).as_ref()) {
+                    batch.remove(key);
+                }
+            }
+
+            inner.breakers.apply_batch(batch).map_err(Error::from)
+        })
+        .await
+    }
+
+    pub(crate) async fn remove_breaker(&self, authority: String) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            inner.breakers.remove(authority.as_bytes())?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn breakers(&self) -> Result<HashMap<String, Breaker>, Error> {
+        self.unblock(|inner| {
+            let mut breakers = HashMap::new();
+
+            for res in inner.breakers.iter() {
+                let (authority, breaker) = res?;
+
+                breakers.insert(
+                    String::from_utf8_lossy(&authority).to_string(),
+                    serde_json::from_slice(&breaker)?,
+                );
+            }
+
+            Ok(breakers)
+        })
+        .await
+    }
+
     pub(crate) async fn last_seen(
         &self,
     ) -> Result<BTreeMap<String, Option<OffsetDateTime>>, Error> {
@@ -337,96 +853,83 @@ impl Db {
         .await
     }
 
-    pub(crate) async fn connected_ids(&self) -> Result<Vec<IriString>, Error> {
-        self.unblock(|inner| Ok(inner.connected().collect())).await
-    }
-
-    pub(crate) async fn save_info(&self, actor_id: IriString, info: Info) -> Result<(), Error> {
-        self.unblock(move |inner| {
-            let vec = serde_json::to_vec(&info)?;
+    pub(crate) async fn node_configs(&self) -> Result<HashMap<String, NodeConfig>, Error> {
+        self.unblock(|inner| {
+            let mut map = HashMap::new();
 
-            inner
-                .actor_id_info
-                .insert(actor_id.as_str().as_bytes(), vec)?;
+            for res in inner.authority_node_config.iter() {
+                let (authority, ivec) = res?;
 
-            Ok(())
-        })
-        .await
-    }
+                map.insert(
+                    String::from_utf8_lossy(&authority).to_string(),
+                    serde_json::from_slice(&ivec)?,
+                );
+            }
 
-    pub(crate) async fn info(&self, actor_id: IriString) -> Result<Option<Info>, Error> {
-        self.unblock(move |inner| {
-            inner
-                .actor_id_info
-                .get(actor_id.as_str().as_bytes())?
-                .map(|ivec| serde_json::from_slice(&ivec))
-                .transpose()
-                .map_err(Error::from)
+            Ok(map)
         })
         .await
     }
 
-    pub(crate) async fn connected_info(&self) -> Result<HashMap<IriString, Info>, Error> {
-        self.unblock(|inner| Ok(inner.connected_info().collect()))
-            .await
-    }
-
-    pub(crate) async fn save_instance(
+    pub(crate) async fn save_node_config(
         &self,
-        actor_id: IriString,
-        instance: Instance,
+        authority: String,
+        node_config: NodeConfig,
     ) -> Result<(), Error> {
         self.unblock(move |inner| {
-            let vec = serde_json::to_vec(&instance)?;
+            let vec = serde_json::to_vec(&node_config)?;
 
             inner
-                .actor_id_instance
-                .insert(actor_id.as_str().as_bytes(), vec)?;
+                .authority_node_config
+                .insert(authority.as_bytes(), vec)?;
 
             Ok(())
         })
         .await
     }
 
-    pub(crate) async fn instance(&self, actor_id: IriString) -> Result<Option<Instance>, Error> {
+    pub(crate) async fn remove_node_config(&self, authority: String) -> Result<(), Error> {
         self.unblock(move |inner| {
-            inner
-                .actor_id_instance
-                .get(actor_id.as_str().as_bytes())?
-                .map(|ivec| serde_json::from_slice(&ivec))
-                .transpose()
-                .map_err(Error::from)
+            inner.authority_node_config.remove(authority.as_bytes())?;
+
+            Ok(())
         })
         .await
     }
 
-    pub(crate) async fn connected_instance(&self) -> Result<HashMap<IriString, Instance>, Error> {
-        self.unblock(|inner| Ok(inner.connected_instance().collect()))
-            .await
+    pub(crate) async fn filter_rules(&self) -> Result<Vec<FilterRule>, Error> {
+        self.unblock(|inner| {
+            inner
+                .filter_rules
+                .iter()
+                .values()
+                .map(|res| Ok(serde_json::from_slice(&res?)?))
+                .collect()
+        })
+        .await
     }
 
-    pub(crate) async fn save_contact(
-        &self,
-        actor_id: IriString,
-        contact: Contact,
-    ) -> Result<(), Error> {
+    pub(crate) async fn save_filter_rule(&self, rule: FilterRule) -> Result<(), Error> {
         self.unblock(move |inner| {
-            let vec = serde_json::to_vec(&contact)?;
+            let vec = serde_json::to_vec(&rule)?;
 
-            inner
-                .actor_id_contact
-                .insert(actor_id.as_str().as_bytes(), vec)?;
+            inner.filter_rules.insert(rule.id.as_bytes(), vec)?;
 
             Ok(())
         })
         .await
     }
 
-    pub(crate) async fn contact(&self, actor_id: IriString) -> Result<Option<Contact>, Error> {
+    pub(crate) async fn remove_filter_rule(&self, id: Uuid) -> Result<bool, Error> {
+        self.unblock(move |inner| Ok(inner.filter_rules.remove(id.as_bytes())?.is_some()))
+            .await
+    }
+
+    pub(crate) async fn job(&self, job_id: Uuid) -> Result<Option<JobInfo>, Error> {
         self.unblock(move |inner| {
             inner
-                .actor_id_contact
-                .get(actor_id.as_str().as_bytes())?
+                .job_id_job
+                .get(job_id.as_bytes())?
                 .map(|ivec| serde_json::from_slice(&ivec))
                 .transpose()
                 .map_err(Error::from)
@@ -434,146 +937,1353 @@ impl Db {
         .await
     }
 
-    pub(crate) async fn connected_contact(&self) -> Result<HashMap<IriString, Contact>, Error> {
-        self.unblock(|inner| Ok(inner.connected_contact().collect()))
-            .await
-    }
-
-    pub(crate) async fn save_url(&self, url: IriString, id: Uuid) -> Result<(), Error> {
+    pub(crate) async fn insert_job(&self, job: JobInfo) -> Result<Uuid, Error> {
+        self.unblock(move |inner| {
+            inner.insert_job(&job, OffsetDateTime::now_utc())?;
+
+            Ok(job.id)
+        })
+        .await
+    }
+
+    pub(crate) async fn claim_job(
+        &self,
+        queue: String,
+        runner_id: Uuid,
+    ) -> Result<Option<JobInfo>, Error> {
+        self.unblock(move |inner| {
+            let now = OffsetDateTime::now_utc();
+            let start = job_queue_prefix(&queue);
+            let end = job_queue_key(&queue, now, Uuid::from_bytes([0xff; 16]));
+
+            for res in inner.job_queue.range(start..=end) {
+                let (key, ivec) = res?;
+                let queued: QueuedJob = serde_json::from_slice(&ivec)?;
+
+                if !queued.is_claimable(now) {
+                    continue;
+                }
+
+                let claimed = QueuedJob {
+                    runner: Some((runner_id, now)),
+                    ..queued
+                };
+
+                // another worker may have claimed this job since we read it
+                if inner
+                    .job_queue
+                    .compare_and_swap(&key, Some(ivec), Some(serde_json::to_vec(&claimed)?))?
+                    .is_err()
+                {
+                    continue;
+                }
+
+                if let Some(job) = inner.job_id_job.get(claimed.job_id.as_bytes())? {
+                    return Ok(Some(serde_json::from_slice(&job)?));
+                }
+
+                tracing::warn!("Removing queue entry for missing job {}", claimed.job_id);
+                inner.job_queue.remove(key)?;
+            }
+
+            Ok(None)
+        })
+        .await
+    }
+
+    pub(crate) async fn next_job_in(&self, queue: String) -> Result<Option<Duration>, Error> {
+        self.unblock(move |inner| {
+            let prefix = job_queue_prefix(&queue);
+            let now = OffsetDateTime::now_utc();
+
+            for res in inner.job_queue.scan_prefix(&prefix) {
+                let (key, ivec) = res?;
+                let queued: QueuedJob = serde_json::from_slice(&ivec)?;
+
+                if queued.runner.is_some() {
+                    continue;
+                }
+
+                let next_queue = job_queue_time(&key[prefix.len()..]);
+
+                return Ok(Some(
+                    (next_queue - now).try_into().unwrap_or(Duration::ZERO),
+                ));
+            }
+
+            Ok(None)
+        })
+        .await
+    }
+
+    pub(crate) async fn heartbeat_job(&self, job_id: Uuid, runner_id: Uuid) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let Some(ivec) = inner.job_id_job.get(job_id.as_bytes())? else {
+                return Ok(());
+            };
+
+            let job: JobInfo = serde_json::from_slice(&ivec)?;
+            let key = job_queue_key(&job.queue, job.next_queue, job.id);
+
+            inner.job_queue.fetch_and_update(key, |ivec| {
+                let mut queued: QueuedJob = serde_json::from_slice(ivec?).ok()?;
+                queued.runner = Some((runner_id, OffsetDateTime::now_utc()));
+                serde_json::to_vec(&queued).ok()
+            })?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns the queue the job was put back into, if it is going to run again
+    pub(crate) async fn complete_job(
+        &self,
+        ReturnJobInfo { id, result }: ReturnJobInfo,
+    ) -> Result<Option<String>, Error> {
+        self.unblock(move |inner| {
+            let Some((mut job, queued_at)) = inner.remove_job(id)? else {
+                return Ok(None);
+            };
+
+            let requeue = if result.is_success() {
+                false
+            } else if result.is_failure() {
+                job.prepare_retry()
+            } else {
+                // unregistered and unexecuted jobs are restored as-is
+                true
+            };
+
+            if requeue {
+                inner.insert_job(&job, queued_at)?;
+                Ok(Some(job.queue))
+            } else {
+                Ok(None)
+            }
+        })
+        .await
+    }
+
+    pub(crate) async fn job_stats(&self) -> Result<BTreeMap<String, JobQueueStats>, Error> {
+        self.unblock(|inner| {
+            let now = OffsetDateTime::now_utc();
+            let mut queues: BTreeMap<String, JobQueueStats> = BTreeMap::new();
+
+            for res in inner.job_queue.iter() {
+                let (_, ivec) = res?;
+                let queued: QueuedJob = serde_json::from_slice(&ivec)?;
+
+                let Some(job) = inner.job_id_job.get(queued.job_id.as_bytes())? else {
+                    continue;
+                };
+                let job: JobInfo = serde_json::from_slice(&job)?;
+                let args = RescheduledArgs::deserialize(&job.args).ok();
+
+                let mut queued_at = queued.queued_at.unwrap_or(job.next_queue);
+                if let Some(first) = args.as_ref().and_then(|args| args.queued_at) {
+                    queued_at = queued_at.min(first.into());
+                }
+                let age = (now - queued_at).try_into().unwrap_or(Duration::ZERO);
+
+                let stats = queues.entry(job.queue).or_default();
+                stats.depth += 1;
+                if !queued.is_claimable(now) {
+                    stats.in_flight += 1;
+                }
+                if job.retry_count > 0 || args.map(|args| args.retries > 0).unwrap_or(false) {
+                    stats.retrying += 1;
+                }
+                stats.oldest_age = stats.oldest_age.max(Some(age));
+            }
+
+            Ok(queues)
+        })
+        .await
+    }
+
+    /// Release jobs held by runners from a previous run of the relay
+    pub(crate) async fn release_jobs(&self) -> Result<usize, Error> {
+        self.unblock(|inner| {
+            let mut released = 0;
+
+            for res in inner.job_queue.iter() {
+                let (key, ivec) = res?;
+                let mut queued: QueuedJob = serde_json::from_slice(&ivec)?;
+
+                if queued.runner.take().is_some() {
+                    inner.job_queue.insert(key, serde_json::to_vec(&queued)?)?;
+                    released += 1;
+                }
+            }
+
+            Ok(released)
+        })
+        .await
+    }
+
+    pub(crate) async fn connected_ids(&self) -> Result<Vec<IriString>, Error> {
+        self.unblock(|inner| Ok(inner.connected().collect())).await
+    }
+
+    pub(crate) async fn following_ids(&self) -> Result<Vec<IriString>, Error> {
+        self.unblock(|inner| {
+            let mut following = Vec::new();
+
+            for res in inner.following_actor_ids.iter().values() {
+                let ivec = res?;
+
+                // actors can be disconnected by blocks and rejects as well as undos
+                if !inner.connected_actor_ids.contains_key(&ivec)? {
+                    continue;
+                }
+
+                following.extend(url_from_ivec(ivec));
+            }
+
+            Ok(following)
+        })
+        .await
+    }
+
+    pub(crate) async fn add_following(&self, actor_id: IriString) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            inner
+                .following_actor_ids
+                .insert(actor_id.as_str().as_bytes(), actor_id.as_str().as_bytes())?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns the most recently relayed activities, newest first
+    pub(crate) async fn outbox(&self) -> Result<Vec<serde_json::Value>, Error> {
+        self.unblock(|inner| {
+            inner
+                .outbox
+                .iter()
+                .values()
+                .rev()
+                .map(|res| Ok(serde_json::from_slice(&res?)?))
+                .collect()
+        })
+        .await
+    }
+
+    pub(crate) async fn add_to_outbox(&self, activity: serde_json::Value) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let now = u64::try_from(OffsetDateTime::now_utc().unix_timestamp_nanos()).unwrap_or(0);
+
+            // keep keys increasing even if the clock doesn't move between activities
+            let key = match inner.outbox.last()? {
+                Some((last, _)) => {
+                    let last = last
+                        .as_ref()
+                        .try_into()
+                        .map(u64::from_be_bytes)
+                        .unwrap_or(0);
+                    now.max(last + 1)
+                }
+                None => now,
+            };
+
+            inner
+                .outbox
+                .insert(key.to_be_bytes(), serde_json::to_vec(&activity)?)?;
+
+            while inner.outbox.len() > OUTBOX_SIZE {
+                if inner.outbox.pop_min()?.is_none() {
+                    break;
+                }
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn save_info(&self, actor_id: IriString, info: Info) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let vec = serde_json::to_vec(&info)?;
+
+            inner
+                .actor_id_info
+                .insert(actor_id.as_str().as_bytes(), vec)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn info(&self, actor_id: IriString) -> Result<Option<Info>, Error> {
+        self.unblock(move |inner| {
+            inner
+                .actor_id_info
+                .get(actor_id.as_str().as_bytes())?
+                .map(|ivec| serde_json::from_slice(&ivec))
+                .transpose()
+                .map_err(Error::from)
+        })
+        .await
+    }
+
+    pub(crate) async fn connected_info(&self) -> Result<HashMap<IriString, Info>, Error> {
+        self.unblock(|inner| Ok(inner.connected_info().collect()))
+            .await
+    }
+
+    pub(crate) async fn save_instance(
+        &self,
+        actor_id: IriString,
+        instance: Instance,
+    ) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let vec = serde_json::to_vec(&instance)?;
+
+            inner
+                .actor_id_instance
+                .insert(actor_id.as_str().as_bytes(), vec)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn instance(&self, actor_id: IriString) -> Result<Option<Instance>, Error> {
+        self.unblock(move |inner| {
+            inner
+                .actor_id_instance
+                .get(actor_id.as_str().as_bytes())?
+                .map(|ivec| serde_json::from_slice(&ivec))
+                .transpose()
+                .map_err(Error::from)
+        })
+        .await
+    }
+
+    pub(crate) async fn connected_instance(&self) -> Result<HashMap<IriString, Instance>, Error> {
+        self.unblock(|inner| Ok(inner.connected_instance().collect()))
+            .await
+    }
+
+    pub(crate) async fn save_contact(
+        &self,
+        actor_id: IriString,
+        contact: Contact,
+    ) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let vec = serde_json::to_vec(&contact)?;
+
+            inner
+                .actor_id_contact
+                .insert(actor_id.as_str().as_bytes(), vec)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn contact(&self, actor_id: IriString) -> Result<Option<Contact>, Error> {
+        self.unblock(move |inner| {
+            inner
+                .actor_id_contact
+                .get(actor_id.as_str().as_bytes())?
+                .map(|ivec| serde_json::from_slice(&ivec))
+                .transpose()
+                .map_err(Error::from)
+        })
+        .await
+    }
+
+    pub(crate) async fn connected_contact(&self) -> Result<HashMap<IriString, Contact>, Error> {
+        self.unblock(|inner| Ok(inner.connected_contact().collect()))
+            .await
+    }
+
+    pub(crate) async fn save_url(&self, url: IriString, id: Uuid) -> Result<(), Error> {
         self.unblock(move |inner| {
             inner
                 .media_id_media_url
                 .insert(id.as_bytes(), url.as_str().as_bytes())?;
             inner
-                .media_url_media_id
-                .insert(url.as_str().as_bytes(), id.as_bytes())?;
+                .media_url_media_id
+                .insert(url.as_str().as_bytes(), id.as_bytes())?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn cached_object_id(
+        &self,
+        object_id: IriString,
+    ) -> Result<Option<Uuid>, Error> {
+        self.unblock(move |inner| {
+            Ok(inner
+                .object_id_object_uuid
+                .get(object_id.as_str().as_bytes())?
+                .and_then(uuid_from_ivec))
+        })
+        .await
+    }
+
+    pub(crate) async fn cached_object(
+        &self,
+        uuid: Uuid,
+    ) -> Result<Option<(serde_json::Value, SystemTime)>, Error> {
+        self.unblock(move |inner| {
+            let Some(ivec) = inner.object_uuid_object.get(uuid.as_bytes())? else {
+                return Ok(None);
+            };
+
+            let cached: CachedObject = serde_json::from_slice(&ivec)?;
+
+            Ok(Some((cached.object, cached.saved_at)))
+        })
+        .await
+    }
+
+    /// Stores the object, then drops anything saved before expired_before and the oldest
+    /// objects past capacity
+    pub(crate) async fn save_cached_object(
+        &self,
+        uuid: Uuid,
+        object_id: IriString,
+        object: serde_json::Value,
+        capacity: usize,
+        expired_before: SystemTime,
+    ) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let object_key = object_id.as_str().as_bytes().to_vec();
+
+            if let Some(old) = inner.object_id_object_uuid.get(&object_key)? {
+                inner.remove_cached_object(&old)?;
+            }
+
+            let saved_at = inner.next_saved_at();
+            let cached = CachedObject {
+                object_id,
+                object,
+                saved_at,
+            };
+
+            if inner
+                .object_uuid_object
+                .insert(uuid.as_bytes(), serde_json::to_vec(&cached)?)?
+                .is_none()
+            {
+                inner.cached_object_count.fetch_add(1, Ordering::Relaxed);
+            }
+            inner
+                .object_id_object_uuid
+                .insert(object_key, uuid.as_bytes())?;
+            inner
+                .object_age
+                .insert(age_key(saved_at, uuid.as_bytes()), uuid.as_bytes())?;
+
+            let expired_key = age_key(expired_before, &[]);
+
+            while let Some((age_key, uuid)) = inner.object_age.first()? {
+                let expired = age_key.as_ref() < expired_key.as_slice();
+
+                if inner.cached_object_count.load(Ordering::Relaxed) <= capacity && !expired {
+                    break;
+                }
+
+                inner.object_age.remove(&age_key)?;
+                inner.remove_cached_object(&uuid)?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// When the object was last announced, if the relay still remembers it
+    pub(crate) async fn relayed_at(
+        &self,
+        object_id: IriString,
+    ) -> Result<Option<SystemTime>, Error> {
+        self.unblock(move |inner| {
+            let Some(ivec) = inner.object_id_relayed.get(object_id.as_str().as_bytes())? else {
+                return Ok(None);
+            };
+
+            let relayed: Relayed = serde_json::from_slice(&ivec)?;
+
+            Ok(Some(relayed.relayed_at))
+        })
+        .await
+    }
+
+    /// Remembers that the object was announced, forgetting the oldest objects past capacity
+    pub(crate) async fn record_relayed(
+        &self,
+        object_id: IriString,
+        activity_id: IriString,
+        capacity: usize,
+    ) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let object_key = object_id.as_str().as_bytes();
+
+            inner.remove_relayed(object_key)?;
+
+            let relayed_at = inner.next_saved_at();
+            let relayed = Relayed {
+                activity_id,
+                relayed_at,
+            };
+
+            if inner
+                .object_id_relayed
+                .insert(object_key, serde_json::to_vec(&relayed)?)?
+                .is_none()
+            {
+                inner.relayed_count.fetch_add(1, Ordering::Relaxed);
+            }
+            inner
+                .relayed_age
+                .insert(age_key(relayed_at, object_key), object_key)?;
+
+            while inner.relayed_count.load(Ordering::Relaxed) > capacity {
+                let Some((age_key, object_key)) = inner.relayed_age.first()? else {
+                    break;
+                };
+
+                inner.relayed_age.remove(&age_key)?;
+                inner.forget_relayed(&object_key)?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Forgets objects announced before the given time, returning how many there were
+    pub(crate) async fn expire_relayed(&self, before: SystemTime) -> Result<usize, Error> {
+        self.unblock(move |inner| {
+            let cutoff = age_key(before, &[]);
+            let mut expired = 0;
+
+            while let Some((age_key, object_key)) = inner.relayed_age.first()? {
+                if age_key.as_ref() >= cutoff.as_slice() {
+                    break;
+                }
+
+                inner.relayed_age.remove(&age_key)?;
+                inner.forget_relayed(&object_key)?;
+                expired += 1;
+            }
+
+            Ok(expired)
+        })
+        .await
+    }
+
+    /// Records a failed delivery, forgetting the oldest past capacity
+    pub(crate) async fn add_dead_letter(
+        &self,
+        mut dead_letter: DeadLetter,
+        capacity: usize,
+    ) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let failed_at = inner.next_saved_at();
+            dead_letter.failed_at = failed_at.into();
+
+            let id = dead_letter.id;
+
+            if inner
+                .dead_letter_id_dead_letter
+                .insert(id.as_bytes(), serde_json::to_vec(&dead_letter)?)?
+                .is_none()
+            {
+                inner.dead_letter_count.fetch_add(1, Ordering::Relaxed);
+            }
+            inner
+                .dead_letter_age
+                .insert(age_key(failed_at, id.as_bytes()), id.as_bytes())?;
+
+            while inner.dead_letter_count.load(Ordering::Relaxed) > capacity {
+                let Some((age_key, id)) = inner.dead_letter_age.first()? else {
+                    break;
+                };
+
+                inner.dead_letter_age.remove(&age_key)?;
+                if inner.dead_letter_id_dead_letter.remove(&id)?.is_some() {
+                    inner.dead_letter_count.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Failed deliveries, oldest first
+    pub(crate) async fn dead_letters(&self) -> Result<Vec<DeadLetter>, Error> {
+        self.unblock(|inner| {
+            let mut dead_letters = Vec::new();
+
+            for id in inner.dead_letter_age.iter().values() {
+                if let Some(ivec) = inner.dead_letter_id_dead_letter.get(id?)? {
+                    dead_letters.push(serde_json::from_slice(&ivec)?);
+                }
+            }
+
+            Ok(dead_letters)
+        })
+        .await
+    }
+
+    /// Removes the given failed deliveries, returning the ones that were recorded
+    pub(crate) async fn remove_dead_letters(
+        &self,
+        ids: Vec<Uuid>,
+    ) -> Result<Vec<DeadLetter>, Error> {
+        self.unblock(move |inner| {
+            let mut removed = Vec::new();
+
+            for id in ids {
+                let Some(ivec) = inner.dead_letter_id_dead_letter.remove(id.as_bytes())? else {
+                    continue;
+                };
+                inner.dead_letter_count.fetch_sub(1, Ordering::Relaxed);
+
+                let dead_letter: DeadLetter = serde_json::from_slice(&ivec)?;
+
+                inner.dead_letter_age.remove(age_key(
+                    dead_letter.failed_at.into(),
+                    dead_letter.id.as_bytes(),
+                ))?;
+
+                removed.push(dead_letter);
+            }
+
+            Ok(removed)
+        })
+        .await
+    }
+
+    /// Forgets every failed delivery, returning how many there were
+    pub(crate) async fn clear_dead_letters(&self) -> Result<usize, Error> {
+        self.unblock(|inner| {
+            inner.dead_letter_id_dead_letter.clear()?;
+            inner.dead_letter_age.clear()?;
+
+            let count = inner.dead_letter_count.swap(0, Ordering::Relaxed);
+
+            Ok(count)
+        })
+        .await
+    }
+
+    /// Adds an activity to the inbox's pending batch, forgetting the oldest past max_pending.
+    /// Returns the id of a new batch when the inbox was idle, so its delivery needs scheduling
+    pub(crate) async fn add_batch_activity(
+        &self,
+        inbox: IriString,
+        activity: serde_json::Value,
+        max_pending: usize,
+    ) -> Result<Option<Uuid>, Error> {
+        self.unblock(move |inner| {
+            let _guard = inner.lock_batches();
+
+            let inbox = inbox.as_str();
+            let prefix = batch_prefix(inbox);
+
+            let mut key = prefix.clone();
+            key.extend_from_slice(&nanos_since_epoch(inner.next_saved_at()).to_be_bytes());
+            inner
+                .batch_activities
+                .insert(key, serde_json::to_vec(&activity)?)?;
+
+            let (mut batch, started) = match inner.inbox_batch(inbox)? {
+                Some(mut batch) => {
+                    batch.pending += 1;
+                    (batch, false)
+                }
+                None => {
+                    // counted once here, since the inbox's earlier batch may have been abandoned
+                    let pending = inner.batch_activities.scan_prefix(&prefix).keys().count();
+                    (
+                        InboxBatch {
+                            id: Uuid::new_v4(),
+                            pending,
+                        },
+                        true,
+                    )
+                }
+            };
+
+            while batch.pending > max_pending {
+                let Some(res) = inner.batch_activities.scan_prefix(&prefix).keys().next() else {
+                    break;
+                };
+
+                inner.batch_activities.remove(res?)?;
+                batch.pending -= 1;
+            }
+
+            inner.save_inbox_batch(inbox, &batch)?;
+
+            Ok(started.then_some(batch.id))
+        })
+        .await
+    }
+
+    /// The first activities of the inbox's batch, oldest first, with the keys to remove them by.
+    /// Returns None when the batch was replaced or finished
+    pub(crate) async fn batch_activities(
+        &self,
+        inbox: IriString,
+        id: Uuid,
+        limit: usize,
+    ) -> Result<Option<Vec<(Vec<u8>, serde_json::Value)>>, Error> {
+        self.unblock(move |inner| {
+            let inbox = inbox.as_str();
+
+            if !inner
+                .inbox_batch(inbox)?
+                .is_some_and(|batch| batch.id == id)
+            {
+                return Ok(None);
+            }
+
+            let mut activities = Vec::new();
+
+            for res in inner
+                .batch_activities
+                .scan_prefix(batch_prefix(inbox))
+                .take(limit)
+            {
+                let (key, ivec) = res?;
+                activities.push((key.to_vec(), serde_json::from_slice(&ivec)?));
+            }
+
+            Ok(Some(activities))
+        })
+        .await
+    }
+
+    /// Removes an activity that was delivered or given up on
+    pub(crate) async fn remove_batch_activity(
+        &self,
+        inbox: IriString,
+        key: Vec<u8>,
+    ) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let _guard = inner.lock_batches();
+
+            let inbox = inbox.as_str();
+
+            if inner.batch_activities.remove(key)?.is_none() {
+                return Ok(());
+            }
+
+            if let Some(mut batch) = inner.inbox_batch(inbox)? {
+                batch.pending = batch.pending.saturating_sub(1);
+                inner.save_inbox_batch(inbox, &batch)?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Marks the inbox idle if its batch has nothing left. Returns whether the batch is done
+    pub(crate) async fn finish_batch(&self, inbox: IriString, id: Uuid) -> Result<bool, Error> {
+        self.unblock(move |inner| {
+            let _guard = inner.lock_batches();
+
+            let inbox = inbox.as_str();
+
+            match inner.inbox_batch(inbox)? {
+                Some(batch) if batch.id == id && batch.pending > 0 => Ok(false),
+                Some(batch) if batch.id == id => {
+                    inner.inbox_batch.remove(inbox)?;
+                    Ok(true)
+                }
+                _ => Ok(true),
+            }
+        })
+        .await
+    }
+
+    /// Leaves the inbox's activities waiting for the next one to arrive, when its batch can't be
+    /// delivered by anything
+    pub(crate) async fn abandon_batch(&self, inbox: IriString, id: Uuid) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let _guard = inner.lock_batches();
+
+            let inbox = inbox.as_str();
+
+            if inner
+                .inbox_batch(inbox)?
+                .is_some_and(|batch| batch.id == id)
+            {
+                inner.inbox_batch.remove(inbox)?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Starts a new batch for every inbox with pending activities, so a batch whose job was lost
+    /// is delivered again. Jobs for the batches they replace do nothing
+    pub(crate) async fn resume_batches(&self) -> Result<Vec<(IriString, Uuid)>, Error> {
+        self.unblock(|inner| {
+            let _guard = inner.lock_batches();
+
+            let mut inboxes = BTreeSet::new();
+
+            for key in inner.batch_activities.iter().keys() {
+                let key = key?;
+                let inbox = key.split(|byte| *byte == 0).next().unwrap_or_default();
+                inboxes.insert(String::from_utf8_lossy(inbox).to_string());
+            }
+
+            for inbox in inner.inbox_batch.iter().keys() {
+                inboxes.insert(String::from_utf8_lossy(&inbox?).to_string());
+            }
+
+            let mut resumed = Vec::new();
+
+            for inbox in inboxes {
+                if let Some(id) = inner.restart_batch(&inbox)? {
+                    if let Ok(inbox) = inbox.parse() {
+                        resumed.push((inbox, id));
+                    }
+                }
+            }
+
+            Ok(resumed)
+        })
+        .await
+    }
+
+    pub(crate) async fn media_id(&self, url: IriString) -> Result<Option<Uuid>, Error> {
+        self.unblock(move |inner| {
+            Ok(inner
+                .media_url_media_id
+                .get(url.as_str().as_bytes())?
+                .and_then(uuid_from_ivec))
+        })
+        .await
+    }
+
+    pub(crate) async fn media_url(&self, id: Uuid) -> Result<Option<IriString>, Error> {
+        self.unblock(move |inner| {
+            Ok(inner
+                .media_id_media_url
+                .get(id.as_bytes())?
+                .and_then(url_from_ivec))
+        })
+        .await
+    }
+
+    pub(crate) async fn blocks(&self) -> Result<Vec<String>, Error> {
+        self.unblock(|inner| Ok(inner.blocks().collect())).await
+    }
+
+    pub(crate) async fn allows(&self) -> Result<Vec<String>, Error> {
+        self.unblock(|inner| Ok(inner.allowed().collect())).await
+    }
+
+    pub(crate) async fn inboxes(&self) -> Result<Vec<IriString>, Error> {
+        self.unblock(|inner| Ok(inner.connected_actors().map(|actor| actor.inbox).collect()))
+            .await
+    }
+
+    pub(crate) async fn is_connected(&self, base_id: IriString) -> Result<bool, Error> {
+        let scheme = base_id.scheme_str();
+        let authority = base_id.authority_str().ok_or(ErrorKind::MissingDomain)?;
+        let prefix = format!("{scheme}://{authority}");
+
+        self.unblock(move |inner| {
+            let connected = inner
+                .connected_actor_ids
+                .scan_prefix(prefix.as_bytes())
+                .values()
+                .any(|res| res.is_ok());
+
+            Ok(connected)
+        })
+        .await
+    }
+
+    /// Whether an actor on the domain or its subdomains is connected
+    pub(crate) async fn is_domain_connected(&self, domain: String) -> Result<bool, Error> {
+        self.unblock(move |inner| Ok(inner.connected_by_domain(&[domain]).next().is_some()))
+            .await
+    }
+
+    pub(crate) async fn actor_id_from_public_key_id(
+        &self,
+        public_key_id: IriString,
+    ) -> Result<Option<IriString>, Error> {
+        self.unblock(move |inner| {
+            Ok(inner
+                .public_key_id_actor_id
+                .get(public_key_id.as_str().as_bytes())?
+                .and_then(url_from_ivec))
+        })
+        .await
+    }
+
+    pub(crate) async fn actor(&self, actor_id: IriString) -> Result<Option<Actor>, Error> {
+        self.unblock(move |inner| {
+            inner
+                .actor_id_actor
+                .get(actor_id.as_str().as_bytes())?
+                .map(|ivec| serde_json::from_slice(&ivec))
+                .transpose()
+                .map_err(Error::from)
+        })
+        .await
+    }
+
+    pub(crate) async fn save_actor(&self, actor: Actor) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let vec = serde_json::to_vec(&actor)?;
+            let key = actor.id.as_str().as_bytes();
+
+            // a tag-scoped subscriber that moved its inbox is indexed under the new one
+            if let Some(ivec) = inner.actor_id_tags.get(key)? {
+                let tags: BTreeSet<String> = serde_json::from_slice(&ivec)?;
+
+                if let Some(old) = inner.saved_inbox(key)?.filter(|old| *old != actor.inbox) {
+                    inner.index_tags(&old, &actor.id, None)?;
+                }
+                inner.index_tags(&actor.inbox, &actor.id, Some(&tags))?;
+            }
+
+            inner.public_key_id_actor_id.insert(
+                actor.public_key_id.as_str().as_bytes(),
+                actor.id.as_str().as_bytes(),
+            )?;
+            for key in &actor.ed25519_keys {
+                inner
+                    .public_key_id_actor_id
+                    .insert(key.id.as_str().as_bytes(), actor.id.as_str().as_bytes())?;
+            }
+            inner
+                .actor_id_actor
+                .insert(actor.id.as_str().as_bytes(), vec)?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn remove_connection(&self, actor_id: IriString) -> Result<(), Error> {
+        tracing::debug!("Removing Connection: {actor_id}");
+        self.unblock(move |inner| {
+            inner
+                .connected_actor_ids
+                .remove(actor_id.as_str().as_bytes())?;
+            inner
+                .following_actor_ids
+                .remove(actor_id.as_str().as_bytes())?;
+            inner
+                .actor_id_follow_id
+                .remove(actor_id.as_str().as_bytes())?;
+            inner.forget_tags(&actor_id)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn save_follow_id(
+        &self,
+        actor_id: IriString,
+        follow_id: IriString,
+    ) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            inner
+                .actor_id_follow_id
+                .insert(actor_id.as_str().as_bytes(), follow_id.as_str().as_bytes())?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// The id of the Follow the actor used to subscribe to the relay
+    pub(crate) async fn follow_id(&self, actor_id: IriString) -> Result<Option<IriString>, Error> {
+        self.unblock(move |inner| {
+            Ok(inner
+                .actor_id_follow_id
+                .get(actor_id.as_str().as_bytes())?
+                .and_then(url_from_ivec))
+        })
+        .await
+    }
+
+    /// Limits the actor to posts with the given tag, unless it already receives everything
+    pub(crate) async fn subscribe_tag(
+        &self,
+        actor_id: IriString,
+        inbox: IriString,
+        tag: String,
+    ) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            let key = actor_id.as_str().as_bytes();
+
+            let mut tags: BTreeSet<String> = match inner.actor_id_tags.get(key)? {
+                Some(ivec) => serde_json::from_slice(&ivec)?,
+                None if inner.connected_actor_ids.contains_key(key)? => return Ok(()),
+                None => BTreeSet::new(),
+            };
+
+            tags.insert(tag);
+            inner
+                .actor_id_tags
+                .insert(key, serde_json::to_vec(&tags)?)?;
+            inner.index_tags(&inbox, &actor_id, Some(&tags))?;
+
             Ok(())
         })
         .await
     }
 
-    pub(crate) async fn media_id(&self, url: IriString) -> Result<Option<Uuid>, Error> {
+    /// Returns whether the actor is still subscribed to anything
+    pub(crate) async fn unsubscribe_tag(
+        &self,
+        actor_id: IriString,
+        tag: String,
+    ) -> Result<bool, Error> {
         self.unblock(move |inner| {
-            Ok(inner
-                .media_url_media_id
-                .get(url.as_str().as_bytes())?
-                .and_then(uuid_from_ivec))
+            let key = actor_id.as_str().as_bytes();
+
+            let Some(ivec) = inner.actor_id_tags.get(key)? else {
+                return Ok(inner.connected_actor_ids.contains_key(key)?);
+            };
+
+            let mut tags: BTreeSet<String> = serde_json::from_slice(&ivec)?;
+            tags.remove(&tag);
+
+            if tags.is_empty() {
+                inner.forget_tags(&actor_id)?;
+                return Ok(false);
+            }
+
+            inner
+                .actor_id_tags
+                .insert(key, serde_json::to_vec(&tags)?)?;
+            if let Some(inbox) = inner.saved_inbox(key)? {
+                inner.index_tags(&inbox, &actor_id, Some(&tags))?;
+            }
+
+            Ok(true)
         })
         .await
     }
 
-    pub(crate) async fn media_url(&self, id: Uuid) -> Result<Option<IriString>, Error> {
-        self.unblock(move |inner| {
-            Ok(inner
-                .media_id_media_url
-                .get(id.as_bytes())?
-                .and_then(url_from_ivec))
+    /// Lets the actor receive every post again
+    pub(crate) async fn clear_tags(&self, actor_id: IriString) -> Result<(), Error> {
+        self.unblock(move |inner| inner.forget_tags(&actor_id))
+            .await
+    }
+
+    pub(crate) async fn has_tag_subscriptions(&self) -> Result<bool, Error> {
+        self.unblock(|inner| Ok(!inner.actor_id_tags.is_empty()))
+            .await
+    }
+
+    /// The tags each tag-scoped subscriber follows, keyed by inbox
+    ///
+    /// Inboxes shared with a subscriber that follows everything are left out, so that subscriber
+    /// still gets every post
+    pub(crate) async fn inbox_tags(&self) -> Result<HashMap<IriString, BTreeSet<String>>, Error> {
+        self.unblock(|inner| {
+            let mut map = HashMap::new();
+
+            for res in inner.inbox_tags.iter() {
+                let (inbox, ivec) = res?;
+
+                let Some(inbox) = url_from_ivec(inbox) else {
+                    continue;
+                };
+
+                if inner.has_full_subscriber(&inbox)? {
+                    continue;
+                }
+
+                let subscribers: BTreeMap<String, BTreeSet<String>> =
+                    serde_json::from_slice(&ivec)?;
+                map.insert(inbox, subscribers.into_values().flatten().collect());
+            }
+
+            Ok(map)
         })
         .await
     }
 
-    pub(crate) async fn blocks(&self) -> Result<Vec<String>, Error> {
-        self.unblock(|inner| Ok(inner.blocks().collect())).await
+    pub(crate) async fn add_connection(&self, actor_id: IriString) -> Result<(), Error> {
+        tracing::debug!("Adding Connection: {actor_id}");
+        self.unblock(move |inner| {
+            inner
+                .connected_actor_ids
+                .insert(actor_id.as_str().as_bytes(), actor_id.as_str().as_bytes())?;
+
+            Ok(())
+        })
+        .await
     }
 
-    pub(crate) async fn allows(&self) -> Result<Vec<String>, Error> {
-        self.unblock(|inner| Ok(inner.allowed().collect())).await
+    /// Moves an actor's subscription, its followed tags and stored node metadata over to the actor
+    /// it moved to
+    /// Returns whether the old actor was connected
+    pub(crate) async fn migrate_actor(
+        &self,
+        from: IriString,
+        to: IriString,
+    ) -> Result<bool, Error> {
+        tracing::debug!("Migrating Connection: {from} -> {to}");
+        self.unblock(move |inner| {
+            let from_key = from.as_str().as_bytes();
+            let to_key = to.as_str().as_bytes();
+
+            let was_connected = inner.connected_actor_ids.remove(from_key)?.is_some();
+            if was_connected {
+                inner.connected_actor_ids.insert(to_key, to_key)?;
+            }
+
+            if inner.following_actor_ids.remove(from_key)?.is_some() {
+                inner.following_actor_ids.insert(to_key, to_key)?;
+            }
+
+            if let Some(ivec) = inner.actor_id_tags.get(from_key)? {
+                let tags: BTreeSet<String> = serde_json::from_slice(&ivec)?;
+                let from_inbox = inner.saved_inbox(from_key)?;
+
+                if let Some(inbox) = &from_inbox {
+                    inner.index_tags(inbox, &from, None)?;
+                }
+                if let Some(inbox) = inner.saved_inbox(to_key)?.or(from_inbox) {
+                    inner.index_tags(&inbox, &to, Some(&tags))?;
+                }
+            }
+
+            for tree in [
+                &inner.actor_id_info,
+                &inner.actor_id_instance,
+                &inner.actor_id_contact,
+                &inner.actor_id_tags,
+                &inner.actor_id_follow_id,
+            ] {
+                if let Some(ivec) = tree.remove(from_key)? {
+                    tree.insert(to_key, ivec)?;
+                }
+            }
+
+            // last seen is tracked per authority, which the new actor may share with others
+            if let (Some(from_authority), Some(to_authority)) =
+                (from.authority_str(), to.authority_str())
+            {
+                if let Some(ivec) = inner.last_seen.get(from_authority)? {
+                    inner
+                        .last_seen
+                        .compare_and_swap(to_authority, None as Option<&[u8]>, Some(ivec))?
+                        .ok();
+                }
+            }
+
+            Ok(was_connected)
+        })
+        .await
     }
 
-    pub(crate) async fn inboxes(&self) -> Result<Vec<IriString>, Error> {
-        self.unblock(|inner| Ok(inner.connected_actors().map(|actor| actor.inbox).collect()))
-            .await
+    /// Returns false when capacity follows are already waiting. Following again while waiting
+    /// keeps the first follow, so it's only announced once
+    pub(crate) async fn add_pending_follow(
+        &self,
+        pending: PendingFollow,
+        capacity: usize,
+    ) -> Result<bool, Error> {
+        self.unblock(move |inner| {
+            let key = pending_follow_key(&pending.actor.id, pending.tag.as_deref());
+            let value = serde_json::to_vec(&pending)?;
+
+            // checked in the transaction, so two follows can't both take the last place
+            let res = inner.pending_follows.transaction(|tx| {
+                if tx.get(key.as_slice())?.is_some() {
+                    return Ok(false);
+                }
+
+                if inner.pending_follow_count.load(Ordering::Relaxed) >= capacity {
+                    return sled::transaction::abort(());
+                }
+
+                tx.insert(key.as_slice(), value.as_slice())?;
+                Ok(true)
+            });
+
+            match res {
+                Ok(inserted) => {
+                    if inserted {
+                        inner.pending_follow_count.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(true)
+                }
+                Err(TransactionError::Abort(())) => Ok(false),
+                Err(TransactionError::Storage(e)) => Err(e.into()),
+            }
+        })
+        .await
     }
 
-    pub(crate) async fn is_connected(&self, base_id: IriString) -> Result<bool, Error> {
-        let scheme = base_id.scheme_str();
-        let authority = base_id.authority_str().ok_or(ErrorKind::MissingDomain)?;
-        let prefix = format!("{scheme}://{authority}");
+    pub(crate) async fn pending_follows(&self) -> Result<Vec<PendingFollow>, Error> {
+        self.unblock(|inner| {
+            inner
+                .pending_follows
+                .iter()
+                .values()
+                .map(|res| Ok(serde_json::from_slice(&res?)?))
+                .collect()
+        })
+        .await
+    }
 
+    /// Removes and returns the pending follows from the given domains and their subdomains
+    pub(crate) async fn take_pending_follows(
+        &self,
+        domains: Vec<String>,
+    ) -> Result<Vec<PendingFollow>, Error> {
         self.unblock(move |inner| {
-            let connected = inner
-                .connected_actor_ids
-                .scan_prefix(prefix.as_bytes())
-                .values()
-                .any(|res| res.is_ok());
+            let reversed: Vec<_> = domains.iter().map(|s| domain_key(s.as_str())).collect();
+            let mut taken = Vec::new();
 
-            Ok(connected)
+            for res in inner.pending_follows.iter() {
+                let (key, ivec) = res?;
+                let pending: PendingFollow = serde_json::from_slice(&ivec)?;
+
+                let Some(authority) = pending.actor.id.authority_str() else {
+                    continue;
+                };
+                let rdnn = domain_key(authority);
+
+                if reversed.iter().any(|domain| rdnn.starts_with(domain)) {
+                    if inner.pending_follows.remove(key)?.is_some() {
+                        inner.pending_follow_count.fetch_sub(1, Ordering::Relaxed);
+                    }
+                    taken.push(pending);
+                }
+            }
+
+            Ok(taken)
         })
         .await
     }
 
-    pub(crate) async fn actor_id_from_public_key_id(
-        &self,
-        public_key_id: IriString,
-    ) -> Result<Option<IriString>, Error> {
+    pub(crate) async fn add_upstream(&self, upstream: Upstream) -> Result<(), Error> {
         self.unblock(move |inner| {
-            Ok(inner
-                .public_key_id_actor_id
-                .get(public_key_id.as_str().as_bytes())?
-                .and_then(url_from_ivec))
+            inner.upstream_relays.insert(
+                upstream.actor_id.as_str().as_bytes(),
+                serde_json::to_vec(&upstream)?,
+            )?;
+
+            Ok(())
         })
         .await
     }
 
-    pub(crate) async fn actor(&self, actor_id: IriString) -> Result<Option<Actor>, Error> {
+    pub(crate) async fn upstreams(&self) -> Result<Vec<Upstream>, Error> {
+        self.unblock(|inner| {
+            inner
+                .upstream_relays
+                .iter()
+                .values()
+                .map(|res| Ok(serde_json::from_slice(&res?)?))
+                .collect()
+        })
+        .await
+    }
+
+    pub(crate) async fn upstream(&self, actor_id: IriString) -> Result<Option<Upstream>, Error> {
         self.unblock(move |inner| {
             inner
-                .actor_id_actor
+                .upstream_relays
                 .get(actor_id.as_str().as_bytes())?
-                .map(|ivec| serde_json::from_slice(&ivec))
+                .map(|ivec| Ok(serde_json::from_slice(&ivec)?))
                 .transpose()
-                .map_err(Error::from)
         })
         .await
     }
 
-    pub(crate) async fn save_actor(&self, actor: Actor) -> Result<(), Error> {
+    /// Returns whether the accepted follow is the one this relay sent to the upstream relay
+    pub(crate) async fn accept_upstream(
+        &self,
+        actor_id: IriString,
+        follow_id: IriString,
+    ) -> Result<bool, Error> {
         self.unblock(move |inner| {
-            let vec = serde_json::to_vec(&actor)?;
+            let key = actor_id.as_str().as_bytes();
 
-            inner.public_key_id_actor_id.insert(
-                actor.public_key_id.as_str().as_bytes(),
-                actor.id.as_str().as_bytes(),
-            )?;
+            let Some(ivec) = inner.upstream_relays.get(key)? else {
+                return Ok(false);
+            };
+            let mut upstream: Upstream = serde_json::from_slice(&ivec)?;
+
+            if upstream.follow_id != follow_id {
+                return Ok(false);
+            }
+
+            upstream.accepted = true;
             inner
-                .actor_id_actor
-                .insert(actor.id.as_str().as_bytes(), vec)?;
-            Ok(())
+                .upstream_relays
+                .insert(key, serde_json::to_vec(&upstream)?)?;
+
+            Ok(true)
         })
         .await
     }
 
-    pub(crate) async fn remove_connection(&self, actor_id: IriString) -> Result<(), Error> {
-        tracing::debug!("Removing Connection: {actor_id}");
+    pub(crate) async fn remove_upstream(
+        &self,
+        actor_id: IriString,
+    ) -> Result<Option<Upstream>, Error> {
         self.unblock(move |inner| {
             inner
-                .connected_actor_ids
-                .remove(actor_id.as_str().as_bytes())?;
+                .upstream_relays
+                .remove(actor_id.as_str().as_bytes())?
+                .map(|ivec| Ok(serde_json::from_slice(&ivec)?))
+                .transpose()
+        })
+        .await
+    }
 
-            Ok(())
+    pub(crate) fn watch_pending_follows(&self) -> sled::Subscriber {
+        self.inner.pending_follows.watch_prefix(vec![])
+    }
+
+    pub(crate) async fn telegram_chat(&self) -> Result<Option<i64>, Error> {
+        self.unblock(|inner| {
+            Ok(inner
+                .settings
+                .get("telegram-chat")?
+                .and_then(|ivec| ivec.as_ref().try_into().ok())
+                .map(i64::from_be_bytes))
         })
         .await
     }
 
-    pub(crate) async fn add_connection(&self, actor_id: IriString) -> Result<(), Error> {
-        tracing::debug!("Adding Connection: {actor_id}");
+    pub(crate) async fn set_telegram_chat(&self, chat_id: i64) -> Result<(), Error> {
         self.unblock(move |inner| {
             inner
-                .connected_actor_ids
-                .insert(actor_id.as_str().as_bytes(), actor_id.as_str().as_bytes())?;
+                .settings
+                .insert("telegram-chat", &chat_id.to_be_bytes())?;
 
             Ok(())
         })
         .await
     }
 
-    pub(crate) async fn add_blocks(&self, domains: Vec<String>) -> Result<(), Error> {
+    /// Returns the connected actors that were disconnected by the block
+    pub(crate) async fn add_blocks(&self, domains: Vec<String>) -> Result<Vec<Actor>, Error> {
         self.unblock(move |inner| {
-            for connected in inner.connected_by_domain(&domains) {
-                inner
-                    .connected_actor_ids
-                    .remove(connected.as_str().as_bytes())?;
-            }
+            let disconnected = inner.disconnect_by_domain(&domains)?;
 
             for authority in &domains {
                 inner
@@ -582,7 +2292,7 @@ impl Db {
                 inner.allowed_domains.remove(domain_key(authority))?;
             }
 
-            Ok(())
+            Ok(disconnected)
         })
         .await
     }
@@ -598,59 +2308,145 @@ impl Db {
         .await
     }
 
-    pub(crate) async fn add_allows(&self, domains: Vec<String>) -> Result<(), Error> {
+    pub(crate) async fn add_allows(&self, domains: Vec<String>) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            for authority in &domains {
+                inner
+                    .allowed_domains
+                    .insert(domain_key(authority), authority.as_bytes())?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns the connected actors that were disconnected, which only happens in restricted mode
+    pub(crate) async fn remove_allows(&self, domains: Vec<String>) -> Result<Vec<Actor>, Error> {
+        self.unblock(move |inner| {
+            let disconnected = if inner.restricted_mode {
+                inner.disconnect_by_domain(&domains)?
+            } else {
+                Vec::new()
+            };
+
+            for authority in &domains {
+                inner.allowed_domains.remove(domain_key(authority))?;
+            }
+
+            Ok(disconnected)
+        })
+        .await
+    }
+
+    pub(crate) async fn is_allowed(&self, url: IriString) -> Result<bool, Error> {
+        self.unblock(move |inner| {
+            if let Some(authority) = url.authority_str() {
+                Ok(inner.is_allowed(authority))
+            } else {
+                Ok(false)
+            }
+        })
+        .await
+    }
+
+    pub(crate) async fn is_blocked(&self, url: IriString) -> Result<bool, Error> {
+        self.unblock(move |inner| {
+            if let Some(authority) = url.authority_str() {
+                Ok(inner.is_blocked(authority))
+            } else {
+                Ok(false)
+            }
+        })
+        .await
+    }
+
+    pub(crate) async fn private_key(&self) -> Result<Option<RsaPrivateKey>, Error> {
+        self.unblock(|inner| {
+            if let Some(ivec) = inner.settings.get("private-key")? {
+                let key_str = String::from_utf8_lossy(&ivec);
+                let key = RsaPrivateKey::from_pkcs8_pem(&key_str)?;
+
+                Ok(Some(key))
+            } else {
+                Ok(None)
+            }
+        })
+        .await
+    }
+
+    /// The id of the relay's current key, if it has been rotated
+    pub(crate) async fn key_id(&self) -> Result<Option<String>, Error> {
+        self.unblock(|inner| {
+            Ok(inner
+                .settings
+                .get("key-id")?
+                .map(|ivec| String::from_utf8_lossy(&ivec).to_string()))
+        })
+        .await
+    }
+
+    pub(crate) async fn retired_key(&self) -> Result<Option<RetiredKey>, Error> {
+        self.unblock(|inner| {
+            inner
+                .settings
+                .get("retired-key")?
+                .map(|ivec| serde_json::from_slice(&ivec))
+                .transpose()
+                .map_err(Error::from)
+        })
+        .await
+    }
+
+    /// Replaces the relay's key, keeping the current one as the retired key until expires_at
+    pub(crate) async fn rotate_private_key(
+        &self,
+        private_key: &RsaPrivateKey,
+        key_id: String,
+        retired_key_id: String,
+        expires_at: SystemTime,
+    ) -> Result<(), Error> {
+        let pem_pkcs8 = private_key.to_pkcs8_pem(rsa::pkcs8::LineEnding::default())?;
+
         self.unblock(move |inner| {
-            for authority in &domains {
-                inner
-                    .allowed_domains
-                    .insert(domain_key(authority), authority.as_bytes())?;
-            }
+            let mut batch = Batch::default();
 
-            Ok(())
-        })
-        .await
-    }
+            if let Some(current) = inner.settings.get("private-key")? {
+                let retired = RetiredKey {
+                    key_id: retired_key_id,
+                    private_key: String::from_utf8_lossy(&current).to_string(),
+                    expires_at,
+                };
 
-    pub(crate) async fn remove_allows(&self, domains: Vec<String>) -> Result<(), Error> {
-        self.unblock(move |inner| {
-            if inner.restricted_mode {
-                for connected in inner.connected_by_domain(&domains) {
-                    inner
-                        .connected_actor_ids
-                        .remove(connected.as_str().as_bytes())?;
-                }
+                batch.insert("retired-key", serde_json::to_vec(&retired)?);
             }
 
-            for authority in &domains {
-                inner.allowed_domains.remove(domain_key(authority))?;
-            }
+            batch.insert("private-key", pem_pkcs8.as_bytes());
+            batch.insert("key-id", key_id.as_bytes());
 
+            inner.settings.apply_batch(batch)?;
             Ok(())
         })
         .await
     }
 
-    pub(crate) async fn is_allowed(&self, url: IriString) -> Result<bool, Error> {
-        self.unblock(move |inner| {
-            if let Some(authority) = url.authority_str() {
-                Ok(inner.is_allowed(authority))
-            } else {
-                Ok(false)
-            }
+    /// The relay's Ed25519 key as a PKCS#8 document, if it has one
+    pub(crate) async fn ed25519_private_key(&self) -> Result<Option<Vec<u8>>, Error> {
+        self.unblock(|inner| {
+            Ok(inner
+                .settings
+                .get("ed25519-private-key")?
+                .map(|ivec| ivec.to_vec()))
         })
         .await
     }
 
-    pub(crate) async fn private_key(&self) -> Result<Option<RsaPrivateKey>, Error> {
-        self.unblock(|inner| {
-            if let Some(ivec) = inner.settings.get("private-key")? {
-                let key_str = String::from_utf8_lossy(&ivec);
-                let key = RsaPrivateKey::from_pkcs8_pem(&key_str)?;
-
-                Ok(Some(key))
-            } else {
-                Ok(None)
-            }
+    pub(crate) async fn update_ed25519_private_key(&self, pkcs8: Vec<u8>) -> Result<(), Error> {
+        self.unblock(move |inner| {
+            inner
+                .settings
+                .insert("ed25519-private-key".as_bytes(), pkcs8)?;
+            Ok(())
         })
         .await
     }
@@ -685,19 +2481,87 @@ fn domain_prefix(authority: &str) -> String {
         + "."
 }
 
+fn job_queue_prefix(queue: &str) -> Vec<u8> {
+    let mut key = queue.as_bytes().to_vec();
+    key.push(0);
+    key
+}
+
+fn job_queue_key(queue: &str, next_queue: OffsetDateTime, job_id: Uuid) -> Vec<u8> {
+    let nanos = u64::try_from(next_queue.unix_timestamp_nanos()).unwrap_or(0);
+
+    let mut key = job_queue_prefix(queue);
+    key.extend_from_slice(&nanos.to_be_bytes());
+    key.extend_from_slice(job_id.as_bytes());
+    key
+}
+
+fn job_queue_time(key_suffix: &[u8]) -> OffsetDateTime {
+    let nanos = key_suffix
+        .get(..8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_be_bytes)
+        .unwrap_or(0);
+
+    OffsetDateTime::from_unix_timestamp_nanos(i128::from(nanos))
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
 fn url_from_ivec(ivec: sled::IVec) -> Option<IriString> {
     String::from_utf8_lossy(&ivec).parse::<IriString>().ok()
 }
 
+fn nanos_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+// Sorts entries oldest first
+fn age_key(saved_at: SystemTime, uuid: &[u8]) -> Vec<u8> {
+    let mut key = nanos_since_epoch(saved_at).to_be_bytes().to_vec();
+    key.extend_from_slice(uuid);
+    key
+}
+
+// A follow of the whole relay keeps the actor id alone as its key, like before follows of a
+// single tag could wait
+fn pending_follow_key(actor_id: &IriString, tag: Option<&str>) -> Vec<u8> {
+    let mut key = actor_id.as_str().as_bytes().to_vec();
+    if let Some(tag) = tag {
+        key.push(0);
+        key.extend_from_slice(tag.as_bytes());
+    }
+    key
+}
+
+// An inbox's pending activities sort by when they were added, and never match a longer inbox
+fn batch_prefix(inbox: &str) -> Vec<u8> {
+    let mut prefix = inbox.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
 fn uuid_from_ivec(ivec: sled::IVec) -> Option<Uuid> {
     Uuid::from_slice(&ivec).ok()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Db;
+    use super::{Actor, Db, DeadLetter, Ed25519Key, PendingFollow, Upstream};
+    use crate::{
+        data::{FilterRule, NodeConfig},
+        jobs::QueryNodeinfo,
+        requests::Breaker,
+    };
     use activitystreams::iri_string::types::IriString;
-    use std::future::Future;
+    use background_jobs::dev::{new_job, JobInfo, ReturnJobInfo};
+    use rsa::{pkcs8::DecodePrivateKey, RsaPrivateKey};
+    use std::{
+        future::Future,
+        time::{Duration, SystemTime},
+    };
+    use uuid::Uuid;
 
     #[test]
     fn connect_and_verify() {
@@ -743,6 +2607,873 @@ mod tests {
         })
     }
 
+    #[test]
+    fn dead_letters_forget_the_oldest_past_capacity() {
+        run(|db| async move {
+            let inbox: IriString = "https://example.com/inbox".parse().unwrap();
+
+            let mut ids = Vec::new();
+
+            for i in 0..3 {
+                let dead_letter = DeadLetter {
+                    id: Uuid::new_v4(),
+                    inbox: inbox.clone(),
+                    activity_id: None,
+                    activity: serde_json::json!({ "n": i }),
+                    error: "Breaker".to_owned(),
+                    retries: 0,
+                    queued_at: SystemTime::now().into(),
+                    failed_at: SystemTime::now().into(),
+                };
+
+                ids.push(dead_letter.id);
+                db.add_dead_letter(dead_letter, 2).await.unwrap();
+            }
+
+            let dead_letters = db.dead_letters().await.unwrap();
+            let listed: Vec<_> = dead_letters.iter().map(|d| d.id).collect();
+            assert_eq!(listed, ids[1..]);
+
+            let removed = db.remove_dead_letters(vec![ids[0], ids[1]]).await.unwrap();
+            assert_eq!(removed.len(), 1);
+            assert_eq!(removed[0].activity["n"], 1);
+
+            assert_eq!(db.dead_letters().await.unwrap().len(), 1);
+            assert_eq!(db.clear_dead_letters().await.unwrap(), 1);
+            assert!(db.dead_letters().await.unwrap().is_empty());
+        })
+    }
+
+    #[test]
+    fn saved_node_config_is_loaded() {
+        run(|db| async move {
+            let node_config = NodeConfig {
+                probability: 128,
+                enable_probability: true,
+                authority_set: ["example.com".to_string()].into_iter().collect(),
+                is_allowlist: true,
+                receive_only: false,
+                ..Default::default()
+            };
+
+            db.save_node_config("relay.example.com".to_string(), node_config)
+                .await
+                .unwrap();
+
+            let node_configs = db.node_configs().await.unwrap();
+            let loaded = node_configs.get("relay.example.com").unwrap();
+
+            assert_eq!(loaded.probability, 128);
+            assert!(loaded.enable_probability);
+            assert!(loaded.authority_set.contains("example.com"));
+            assert!(loaded.is_allowlist);
+            assert!(!loaded.receive_only);
+        })
+    }
+
+    #[test]
+    fn removed_node_config_is_not_loaded() {
+        run(|db| async move {
+            let node_config = NodeConfig {
+                probability: 0,
+                enable_probability: false,
+                authority_set: Default::default(),
+                is_allowlist: false,
+                receive_only: true,
+                ..Default::default()
+            };
+
+            db.save_node_config("relay.example.com".to_string(), node_config)
+                .await
+                .unwrap();
+            db.remove_node_config("relay.example.com".to_string())
+                .await
+                .unwrap();
+
+            assert!(db.node_configs().await.unwrap().is_empty());
+        })
+    }
+
+    #[test]
+    fn disconnected_actor_not_in_following_list() {
+        run(|db| async move {
+            let example_actor: IriString = "http://example.com/actor".parse().unwrap();
+            db.add_connection(example_actor.clone()).await.unwrap();
+            db.add_following(example_actor.clone()).await.unwrap();
+            assert!(db.following_ids().await.unwrap().contains(&example_actor));
+
+            db.remove_connection(example_actor.clone()).await.unwrap();
+            assert!(!db.following_ids().await.unwrap().contains(&example_actor));
+        })
+    }
+
+    #[test]
+    fn migrated_actor_replaces_connection() {
+        run(|db| async move {
+            let old_actor: IriString = "http://old.example.com/actor".parse().unwrap();
+            let new_actor: IriString = "http://new.example.com/actor".parse().unwrap();
+            db.add_connection(old_actor.clone()).await.unwrap();
+            db.add_following(old_actor.clone()).await.unwrap();
+            db.mark_last_seen(
+                [(
+                    "old.example.com".to_string(),
+                    time::OffsetDateTime::now_utc(),
+                )]
+                .into(),
+            )
+            .await
+            .unwrap();
+
+            assert!(db
+                .migrate_actor(old_actor.clone(), new_actor.clone())
+                .await
+                .unwrap());
+
+            assert!(!db.connected_ids().await.unwrap().contains(&old_actor));
+            assert!(db.connected_ids().await.unwrap().contains(&new_actor));
+            assert!(db.following_ids().await.unwrap().contains(&new_actor));
+            assert!(db.last_seen().await.unwrap()["new.example.com"].is_some());
+        })
+    }
+
+    #[test]
+    fn migrating_unconnected_actor_connects_nothing() {
+        run(|db| async move {
+            let old_actor: IriString = "http://old.example.com/actor".parse().unwrap();
+            let new_actor: IriString = "http://new.example.com/actor".parse().unwrap();
+
+            assert!(!db
+                .migrate_actor(old_actor.clone(), new_actor.clone())
+                .await
+                .unwrap());
+
+            assert!(!db.connected_ids().await.unwrap().contains(&new_actor));
+        })
+    }
+
+    #[test]
+    fn pending_follows_are_taken_by_domain() {
+        run(|db| async move {
+            for actor in [
+                "http://example.com/actor",
+                "http://sub.example.com/actor",
+                "http://other.com/actor",
+            ] {
+                assert!(db
+                    .add_pending_follow(pending_follow(actor), 3)
+                    .await
+                    .unwrap());
+            }
+
+            // full, but following again keeps the waiting follow
+            assert!(!db
+                .add_pending_follow(pending_follow("http://new.com/actor"), 3)
+                .await
+                .unwrap());
+            assert!(db
+                .add_pending_follow(pending_follow("http://other.com/actor"), 3)
+                .await
+                .unwrap());
+
+            let taken = db
+                .take_pending_follows(vec!["example.com".to_string()])
+                .await
+                .unwrap();
+            assert_eq!(taken.len(), 2);
+
+            let remaining = db.pending_follows().await.unwrap();
+            assert_eq!(remaining.len(), 1);
+            assert_eq!(remaining[0].actor.id.as_str(), "http://other.com/actor");
+
+            assert!(db
+                .add_pending_follow(pending_follow("http://new.com/actor"), 3)
+                .await
+                .unwrap());
+        })
+    }
+
+    #[test]
+    fn pending_follows_are_kept_per_tag() {
+        run(|db| async move {
+            let mut first = pending_follow("http://example.com/actor");
+            first.tag = Some("rust".to_string());
+            let mut second = first.clone();
+            second.tag = Some("linux".to_string());
+            let mut again = first.clone();
+            again.received_at += time::Duration::seconds(1);
+
+            assert!(db.add_pending_follow(first.clone(), 3).await.unwrap());
+            assert!(db.add_pending_follow(second, 3).await.unwrap());
+            assert!(db.add_pending_follow(again, 3).await.unwrap());
+
+            let pending = db.pending_follows().await.unwrap();
+            assert_eq!(pending.len(), 2);
+            assert!(pending
+                .iter()
+                .any(|p| p.tag == first.tag && p.received_at == first.received_at));
+        })
+    }
+
+    #[test]
+    fn upstreams_are_accepted_by_their_follow() {
+        run(|db| async move {
+            let actor_id: IriString = "http://relay.example.com/actor".parse().unwrap();
+            let follow_id: IriString = "http://my.relay/activity/1".parse().unwrap();
+
+            db.add_upstream(Upstream {
+                actor_id: actor_id.clone(),
+                inbox: "http://relay.example.com/inbox".parse().unwrap(),
+                follow_id: follow_id.clone(),
+                accepted: false,
+                followed_at: time::OffsetDateTime::now_utc(),
+                added_allow: false,
+            })
+            .await
+            .unwrap();
+
+            let other_follow: IriString = "http://my.relay/activity/2".parse().unwrap();
+            assert!(!db
+                .accept_upstream(actor_id.clone(), other_follow)
+                .await
+                .unwrap());
+            assert!(db
+                .accept_upstream(actor_id.clone(), follow_id)
+                .await
+                .unwrap());
+            assert!(
+                db.upstream(actor_id.clone())
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .accepted
+            );
+
+            assert!(db
+                .remove_upstream(actor_id.clone())
+                .await
+                .unwrap()
+                .is_some());
+            assert!(db.upstreams().await.unwrap().is_empty());
+        })
+    }
+
+    #[test]
+    fn blocked_actors_are_disconnected() {
+        run(|db| async move {
+            let actor = pending_follow("http://example.com/actor").actor;
+            let follow_id: IriString = "http://example.com/actor/follow".parse().unwrap();
+
+            db.save_actor(actor.clone()).await.unwrap();
+            db.add_connection(actor.id.clone()).await.unwrap();
+            db.save_follow_id(actor.id.clone(), follow_id.clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                db.follow_id(actor.id.clone()).await.unwrap(),
+                Some(follow_id)
+            );
+
+            let disconnected = db
+                .add_blocks(vec!["example.com".to_string()])
+                .await
+                .unwrap();
+            assert_eq!(disconnected.len(), 1);
+            assert_eq!(disconnected[0].id, actor.id);
+            assert!(!db.connected_ids().await.unwrap().contains(&actor.id));
+
+            db.remove_connection(actor.id.clone()).await.unwrap();
+            assert!(db.follow_id(actor.id).await.unwrap().is_none());
+        })
+    }
+
+    #[test]
+    fn filter_rules_are_saved_and_removed() {
+        run(|db| async move {
+            let rule: FilterRule =
+                serde_json::from_value(serde_json::json!({ "pattern": "spam" })).unwrap();
+
+            db.save_filter_rule(rule.clone()).await.unwrap();
+
+            let rules = db.filter_rules().await.unwrap();
+            assert_eq!(rules.len(), 1);
+            assert_eq!(rules[0].id, rule.id);
+            assert_eq!(rules[0].pattern, "spam");
+
+            assert!(db.remove_filter_rule(rule.id).await.unwrap());
+            assert!(!db.remove_filter_rule(rule.id).await.unwrap());
+            assert!(db.filter_rules().await.unwrap().is_empty());
+        })
+    }
+
+    #[test]
+    fn tag_subscriptions_are_scoped_to_their_tags() {
+        run(|db| async move {
+            let actor = pending_follow("http://example.com/actor").actor;
+            db.save_actor(actor.clone()).await.unwrap();
+
+            db.subscribe_tag(actor.id.clone(), actor.inbox.clone(), "rust".to_string())
+                .await
+                .unwrap();
+            db.subscribe_tag(actor.id.clone(), actor.inbox.clone(), "sled".to_string())
+                .await
+                .unwrap();
+            db.add_connection(actor.id.clone()).await.unwrap();
+
+            let inbox_tags = db.inbox_tags().await.unwrap();
+            assert_eq!(inbox_tags[&actor.inbox].len(), 2);
+
+            assert!(db
+                .unsubscribe_tag(actor.id.clone(), "rust".to_string())
+                .await
+                .unwrap());
+            assert!(!db
+                .unsubscribe_tag(actor.id.clone(), "sled".to_string())
+                .await
+                .unwrap());
+            assert!(!db.has_tag_subscriptions().await.unwrap());
+
+            // an actor that gets every post isn't narrowed by following a tag
+            db.subscribe_tag(actor.id.clone(), actor.inbox.clone(), "rust".to_string())
+                .await
+                .unwrap();
+            assert!(!db.has_tag_subscriptions().await.unwrap());
+        })
+    }
+
+    #[test]
+    fn tag_subscribers_are_indexed_by_their_current_inbox() {
+        run(|db| async move {
+            let mut actor = pending_follow("http://example.com/actor").actor;
+            db.save_actor(actor.clone()).await.unwrap();
+            db.subscribe_tag(actor.id.clone(), actor.inbox.clone(), "rust".to_string())
+                .await
+                .unwrap();
+            db.add_connection(actor.id.clone()).await.unwrap();
+
+            let old_inbox = actor.inbox.clone();
+            actor.inbox = "http://example.com/inbox".parse().unwrap();
+            db.save_actor(actor.clone()).await.unwrap();
+
+            let inbox_tags = db.inbox_tags().await.unwrap();
+            assert!(!inbox_tags.contains_key(&old_inbox));
+            assert!(inbox_tags[&actor.inbox].contains("rust"));
+
+            let mut moved = pending_follow("http://new.example.com/actor").actor;
+            moved.inbox = "http://new.example.com/inbox".parse().unwrap();
+            db.save_actor(moved.clone()).await.unwrap();
+            db.migrate_actor(actor.id.clone(), moved.id.clone())
+                .await
+                .unwrap();
+
+            let inbox_tags = db.inbox_tags().await.unwrap();
+            assert!(!inbox_tags.contains_key(&actor.inbox));
+            assert!(inbox_tags[&moved.inbox].contains("rust"));
+
+            db.remove_connection(moved.id.clone()).await.unwrap();
+            assert!(db.inbox_tags().await.unwrap().is_empty());
+        })
+    }
+
+    #[test]
+    fn shared_inbox_with_a_full_subscriber_is_unfiltered() {
+        run(|db| async move {
+            let tagged = pending_follow("http://example.com/tagged").actor;
+            let mut full = pending_follow("http://example.com/full").actor;
+            full.inbox = tagged.inbox.clone();
+            db.save_actor(tagged.clone()).await.unwrap();
+            db.save_actor(full.clone()).await.unwrap();
+
+            db.subscribe_tag(tagged.id.clone(), tagged.inbox.clone(), "rust".to_string())
+                .await
+                .unwrap();
+            db.add_connection(tagged.id.clone()).await.unwrap();
+            assert!(db.inbox_tags().await.unwrap().contains_key(&tagged.inbox));
+
+            db.add_connection(full.id.clone()).await.unwrap();
+            assert!(db.inbox_tags().await.unwrap().is_empty());
+
+            // a Move keeps the tags the actor followed
+            let moved: IriString = "http://new.example.com/tagged".parse().unwrap();
+            db.migrate_actor(tagged.id.clone(), moved.clone())
+                .await
+                .unwrap();
+            assert!(db.has_tag_subscriptions().await.unwrap());
+            // dropping its only tag leaves the moved actor with no subscription
+            assert!(!db.unsubscribe_tag(moved, "rust".to_string()).await.unwrap());
+        })
+    }
+
+    #[test]
+    fn cached_objects_are_bounded() {
+        run(|db| async move {
+            let mut uuids = Vec::new();
+
+            for i in 0..3 {
+                let uuid = Uuid::new_v4();
+                let object_id: IriString = format!("http://example.com/notes/{i}").parse().unwrap();
+
+                db.save_cached_object(
+                    uuid,
+                    object_id,
+                    serde_json::json!({ "id": i }),
+                    2,
+                    SystemTime::UNIX_EPOCH,
+                )
+                .await
+                .unwrap();
+
+                uuids.push(uuid);
+            }
+
+            assert!(db.cached_object(uuids[0]).await.unwrap().is_none());
+            assert!(db.cached_object(uuids[2]).await.unwrap().is_some());
+
+            // storing an object again replaces the older copy
+            let object_id: IriString = "http://example.com/notes/2".parse().unwrap();
+            let uuid = Uuid::new_v4();
+            db.save_cached_object(
+                uuid,
+                object_id.clone(),
+                serde_json::json!({ "id": 2 }),
+                2,
+                SystemTime::UNIX_EPOCH,
+            )
+            .await
+            .unwrap();
+
+            assert!(db.cached_object(uuids[2]).await.unwrap().is_none());
+            assert_eq!(db.cached_object_id(object_id).await.unwrap(), Some(uuid));
+            assert!(db.cached_object(uuids[1]).await.unwrap().is_some());
+        })
+    }
+
+    #[test]
+    fn relayed_objects_are_bounded_and_expire() {
+        run(|db| async move {
+            let activity_id: IriString = "http://relay.example.com/activity/1".parse().unwrap();
+            let object_ids = (0..3)
+                .map(|i| format!("http://example.com/notes/{i}").parse().unwrap())
+                .collect::<Vec<IriString>>();
+
+            for object_id in &object_ids {
+                db.record_relayed(object_id.clone(), activity_id.clone(), 2)
+                    .await
+                    .unwrap();
+            }
+
+            assert!(db
+                .relayed_at(object_ids[0].clone())
+                .await
+                .unwrap()
+                .is_none());
+            assert!(db
+                .relayed_at(object_ids[2].clone())
+                .await
+                .unwrap()
+                .is_some());
+
+            assert_eq!(db.expire_relayed(SystemTime::UNIX_EPOCH).await.unwrap(), 0);
+            assert_eq!(
+                db.expire_relayed(SystemTime::now() + Duration::from_secs(1))
+                    .await
+                    .unwrap(),
+                2
+            );
+            assert!(db
+                .relayed_at(object_ids[1].clone())
+                .await
+                .unwrap()
+                .is_none());
+        })
+    }
+
+    #[test]
+    fn outbox_keeps_newest_activities() {
+        run(|db| async move {
+            for i in 0..=super::OUTBOX_SIZE {
+                db.add_to_outbox(serde_json::json!({ "id": i }))
+                    .await
+                    .unwrap();
+            }
+
+            let outbox = db.outbox().await.unwrap();
+            assert_eq!(outbox.len(), super::OUTBOX_SIZE);
+            assert_eq!(outbox[0]["id"], super::OUTBOX_SIZE);
+            assert_eq!(outbox[super::OUTBOX_SIZE - 1]["id"], 1);
+        })
+    }
+
+    #[test]
+    fn saved_breakers_are_loaded() {
+        run(|db| async move {
+            let breakers = [("example.com".to_string(), Breaker::default())].into();
+
+            db.save_breakers(breakers).await.unwrap();
+
+            assert!(db.breakers().await.unwrap().contains_key("example.com"));
+        })
+    }
+
+    #[test]
+    fn removed_breaker_is_not_loaded() {
+        run(|db| async move {
+            let breakers = [("example.com".to_string(), Breaker::default())].into();
+
+            db.save_breakers(breakers).await.unwrap();
+            db.remove_breaker("example.com".to_string()).await.unwrap();
+
+            assert!(db.breakers().await.unwrap().is_empty());
+        })
+    }
+
+    #[test]
+    fn breakers_missing_from_a_save_are_not_loaded() {
+        run(|db| async move {
+            let breakers = [
+                ("example.com".to_string(), Breaker::default()),
+                ("example.org".to_string(), Breaker::default()),
+            ]
+            .into();
+            db.save_breakers(breakers).await.unwrap();
+
+            let breakers = [("example.org".to_string(), Breaker::default())].into();
+            db.save_breakers(breakers).await.unwrap();
+
+            let loaded = db.breakers().await.unwrap();
+            assert!(!loaded.contains_key("example.com"));
+            assert!(loaded.contains_key("example.org"));
+        })
+    }
+
+    #[test]
+    fn claimed_job_resumes_after_restart() {
+        run_restartable(|sled| async move {
+            let db = Db::build_inner(true, sled.clone()).unwrap();
+            let job_id = db.insert_job(nodeinfo_job()).await.unwrap();
+
+            let claimed = db.claim_job("maintenance".into(), Uuid::new_v4()).await;
+            assert_eq!(claimed.unwrap().unwrap().id, job_id);
+            assert!(db
+                .claim_job("maintenance".into(), Uuid::new_v4())
+                .await
+                .unwrap()
+                .is_none());
+
+            // the relay went down while the job was running
+            drop(db);
+            let db = Db::build_inner(true, sled).unwrap();
+
+            assert_eq!(db.release_jobs().await.unwrap(), 1);
+            let claimed = db.claim_job("maintenance".into(), Uuid::new_v4()).await;
+            assert_eq!(claimed.unwrap().unwrap().id, job_id);
+        })
+    }
+
+    #[test]
+    fn failed_job_keeps_retry_state_after_restart() {
+        run_restartable(|sled| async move {
+            let db = Db::build_inner(true, sled.clone()).unwrap();
+            let job = nodeinfo_job();
+            let job_id = db.insert_job(job.clone()).await.unwrap();
+
+            db.claim_job("maintenance".into(), Uuid::new_v4())
+                .await
+                .unwrap()
+                .unwrap();
+            let requeued = db.complete_job(failure(job_id)).await.unwrap();
+            assert_eq!(requeued.as_deref(), Some("maintenance"));
+
+            drop(db);
+            let db = Db::build_inner(true, sled).unwrap();
+            db.release_jobs().await.unwrap();
+
+            let retried = db.job(job_id).await.unwrap().unwrap();
+            assert_eq!(retried.retry_count, 1);
+            assert!(retried.next_queue > job.next_queue);
+            assert!(db
+                .next_job_in("maintenance".into())
+                .await
+                .unwrap()
+                .is_some());
+        })
+    }
+
+    #[test]
+    fn completed_job_is_removed() {
+        run(|db| async move {
+            let job_id = db.insert_job(nodeinfo_job()).await.unwrap();
+
+            db.claim_job("maintenance".into(), Uuid::new_v4())
+                .await
+                .unwrap()
+                .unwrap();
+            let success = serde_json::json!({ "id": job_id, "result": "Success" });
+            let requeued = db
+                .complete_job(serde_json::from_value(success).unwrap())
+                .await
+                .unwrap();
+
+            assert!(requeued.is_none());
+            assert!(db.job(job_id).await.unwrap().is_none());
+            assert!(db
+                .next_job_in("maintenance".into())
+                .await
+                .unwrap()
+                .is_none());
+        })
+    }
+
+    #[test]
+    fn job_stats_count_running_and_retrying_jobs() {
+        run(|db| async move {
+            let first = db.insert_job(nodeinfo_job()).await.unwrap();
+            db.insert_job(nodeinfo_job()).await.unwrap();
+
+            db.claim_job("maintenance".into(), Uuid::new_v4())
+                .await
+                .unwrap()
+                .unwrap();
+            db.complete_job(failure(first)).await.unwrap();
+            db.claim_job("maintenance".into(), Uuid::new_v4())
+                .await
+                .unwrap()
+                .unwrap();
+
+            let stats = db.job_stats().await.unwrap();
+            let maintenance = &stats["maintenance"];
+
+            assert_eq!(stats.len(), 1);
+            assert_eq!(maintenance.depth, 2);
+            assert_eq!(maintenance.in_flight, 1);
+            assert_eq!(maintenance.retrying, 1);
+            assert!(maintenance.oldest_age.is_some());
+        })
+    }
+
+    #[test]
+    fn batches_keep_each_inbox_in_order() {
+        run(|db| async move {
+            let inbox: IriString = "https://example.com/inbox".parse().unwrap();
+            let longer: IriString = "https://example.com/inbox2".parse().unwrap();
+
+            let id = db
+                .add_batch_activity(inbox.clone(), 1.into(), 3)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(db
+                .add_batch_activity(inbox.clone(), 2.into(), 3)
+                .await
+                .unwrap()
+                .is_none());
+            assert!(db
+                .add_batch_activity(longer.clone(), 9.into(), 3)
+                .await
+                .unwrap()
+                .is_some());
+
+            let batch = db
+                .batch_activities(inbox.clone(), id, 10)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                batch.iter().map(|(_, a)| a.clone()).collect::<Vec<_>>(),
+                [serde_json::json!(1), serde_json::json!(2)]
+            );
+
+            // a job for another batch does nothing
+            let stale = uuid::Uuid::new_v4();
+            assert!(db
+                .batch_activities(inbox.clone(), stale, 10)
+                .await
+                .unwrap()
+                .is_none());
+
+            for (key, _) in batch {
+                db.remove_batch_activity(inbox.clone(), key).await.unwrap();
+            }
+
+            // still busy until the batch is finished
+            assert!(db
+                .add_batch_activity(inbox.clone(), 3.into(), 3)
+                .await
+                .unwrap()
+                .is_none());
+            assert!(!db.finish_batch(inbox.clone(), id).await.unwrap());
+
+            let (key, _) = db
+                .batch_activities(inbox.clone(), id, 10)
+                .await
+                .unwrap()
+                .unwrap()
+                .remove(0);
+            db.remove_batch_activity(inbox.clone(), key).await.unwrap();
+            assert!(db.finish_batch(inbox.clone(), id).await.unwrap());
+
+            assert!(db
+                .add_batch_activity(inbox.clone(), 4.into(), 3)
+                .await
+                .unwrap()
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn batches_drop_the_oldest_and_resume() {
+        run(|db| async move {
+            let inbox: IriString = "https://example.com/inbox".parse().unwrap();
+
+            let id = db
+                .add_batch_activity(inbox.clone(), 1.into(), 2)
+                .await
+                .unwrap()
+                .unwrap();
+            for n in 2..=3 {
+                db.add_batch_activity(inbox.clone(), n.into(), 2)
+                    .await
+                    .unwrap();
+            }
+
+            // a lost job leaves the inbox busy, until its batch is resumed under a new id
+            let resumed = db.resume_batches().await.unwrap();
+            assert_eq!(resumed.len(), 1);
+            let (resumed_inbox, resumed_id) = resumed[0].clone();
+            assert_eq!(resumed_inbox, inbox);
+            assert_ne!(resumed_id, id);
+            assert!(db
+                .batch_activities(inbox.clone(), id, 10)
+                .await
+                .unwrap()
+                .is_none());
+
+            let batch = db
+                .batch_activities(inbox.clone(), resumed_id, 10)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                batch.into_iter().map(|(_, a)| a).collect::<Vec<_>>(),
+                [serde_json::json!(2), serde_json::json!(3)]
+            );
+
+            db.abandon_batch(inbox.clone(), resumed_id).await.unwrap();
+            assert!(db
+                .add_batch_activity(inbox.clone(), 4.into(), 2)
+                .await
+                .unwrap()
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn ed25519_keys_resolve_to_their_actor() {
+        run(|db| async move {
+            let mut actor = pending_follow("https://example.com/actor").actor;
+            actor.ed25519_keys.push(Ed25519Key {
+                id: "https://example.com/actor#ed25519-key".parse().unwrap(),
+                public_key: vec![7; 32],
+            });
+            db.save_actor(actor).await.unwrap();
+
+            let actor_id = db
+                .actor_id_from_public_key_id(
+                    "https://example.com/actor#ed25519-key".parse().unwrap(),
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            let actor = db.actor(actor_id).await.unwrap().unwrap();
+
+            assert!(actor.has_key("https://example.com/actor#main-key"));
+            assert_eq!(
+                actor.ed25519_key("https://example.com/actor#ed25519-key"),
+                Some([7; 32].as_slice())
+            );
+            assert!(!actor.has_key("https://example.com/actor#other-key"));
+        })
+    }
+
+    #[test]
+    fn rotating_retires_the_current_key() {
+        run(|db| async move {
+            let mut rng = rand::thread_rng();
+            let first = RsaPrivateKey::new(&mut rng, 512).unwrap();
+            let second = RsaPrivateKey::new(&mut rng, 512).unwrap();
+
+            db.update_private_key(&first).await.unwrap();
+            assert!(db.key_id().await.unwrap().is_none());
+
+            let expires_at = SystemTime::now() + Duration::from_secs(60);
+            db.rotate_private_key(
+                &second,
+                "https://relay.example/actor#key-2".into(),
+                "https://relay.example/actor#main-key".into(),
+                expires_at,
+            )
+            .await
+            .unwrap();
+
+            assert!(db.private_key().await.unwrap() == Some(second));
+            assert_eq!(
+                db.key_id().await.unwrap().as_deref(),
+                Some("https://relay.example/actor#key-2")
+            );
+
+            let retired = db.retired_key().await.unwrap().unwrap();
+            assert_eq!(retired.key_id, "https://relay.example/actor#main-key");
+            assert_eq!(retired.expires_at, expires_at);
+            assert!(RsaPrivateKey::from_pkcs8_pem(&retired.private_key).unwrap() == first);
+        })
+    }
+
+    fn pending_follow(actor_id: &str) -> PendingFollow {
+        let input = serde_json::json!({
+            "type": "Follow",
+            "id": format!("{actor_id}/follow"),
+            "actor": actor_id,
+            "object": "https://relay.example.com/actor",
+        });
+
+        PendingFollow {
+            input: serde_json::from_value(input).unwrap(),
+            tag: None,
+            actor: Actor {
+                id: actor_id.parse().unwrap(),
+                public_key: String::new(),
+                public_key_id: format!("{actor_id}#main-key").parse().unwrap(),
+                inbox: format!("{actor_id}/inbox").parse().unwrap(),
+                saved_at: std::time::SystemTime::now(),
+                ed25519_keys: Vec::new(),
+            },
+            received_at: time::OffsetDateTime::now_utc(),
+        }
+    }
+
+    fn nodeinfo_job() -> JobInfo {
+        let actor_id: IriString = "http://example.com/actor".parse().unwrap();
+
+        new_job(QueryNodeinfo::new(actor_id)).unwrap().build()
+    }
+
+    fn failure(id: Uuid) -> ReturnJobInfo {
+        serde_json::from_value(serde_json::json!({ "id": id, "result": "Failure" })).unwrap()
+    }
+
+    fn run_restartable<F, Fut>(f: F)
+    where
+        F: Fn(sled::Db) -> Fut,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let sled = sled::Config::new().temporary(true).open().unwrap();
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on((f)(sled));
+    }
+
     fn run<F, Fut>(f: F)
     where
         F: Fn(Db) -> Fut,
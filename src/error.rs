@@ -5,7 +5,7 @@ use actix_web::{
     HttpResponse,
 };
 use http_signature_normalization_reqwest::SignError;
-use std::{convert::Infallible, fmt::Debug, io};
+use std::{convert::Infallible, fmt::Debug, io, time::Duration};
 use tokio::task::JoinError;
 use tracing_error::SpanTrace;
 
@@ -19,6 +19,20 @@ impl Error {
         matches!(self.kind, ErrorKind::Breaker)
     }
 
+    /// How long to wait before trying a deferred delivery again
+    pub(crate) fn deferred(&self) -> Option<Duration> {
+        match self.kind {
+            ErrorKind::Deferred(wait) | ErrorKind::RetryAfter(wait) => Some(wait),
+            _ => None,
+        }
+    }
+
+    /// Whether the server asked for the delivery to be retried later, rather than this relay's
+    /// own limits holding it back
+    pub(crate) fn is_retry_after(&self) -> bool {
+        matches!(self.kind, ErrorKind::RetryAfter(_))
+    }
+
     pub(crate) fn is_not_found(&self) -> bool {
         matches!(self.kind, ErrorKind::Status(_, StatusCode::NOT_FOUND))
     }
@@ -117,6 +131,12 @@ pub(crate) enum ErrorKind {
     #[error("Couldn't verify signature")]
     VerifySignature,
 
+    #[error("Couldn't load or generate the Ed25519 key")]
+    GenerateKey,
+
+    #[error("Couldn't parse the HTTP message signature, {0}")]
+    MessageSignature(String),
+
     #[error("Failed to encode key der")]
     DerEncode,
 
@@ -126,6 +146,15 @@ pub(crate) enum ErrorKind {
     #[error("Couldn't decode base64")]
     Base64(#[from] base64::DecodeError),
 
+    #[error("Couldn't compile filter pattern, {0}")]
+    Regex(#[from] regex::Error),
+
+    #[error("Couldn't decode base58")]
+    Base58(#[from] bs58::decode::Error),
+
+    #[error("Couldn't verify integrity proof, {0}")]
+    Proof(String),
+
     #[error("Actor ({0}), or Actor's server, is not subscribed")]
     NotSubscribed(String),
 
@@ -156,9 +185,6 @@ pub(crate) enum ErrorKind {
     #[error("Invalid algorithm provided to verifier, {0}")]
     Algorithm(String),
 
-    #[error("Object has already been relayed")]
-    Duplicate,
-
     #[error("Couldn't send request to {0}, {1}")]
     SendRequest(String, String),
 
@@ -192,6 +218,18 @@ pub(crate) enum ErrorKind {
     #[error("Not trying request due to failed breaker")]
     Breaker,
 
+    #[error("Delivery deferred for {0:?}")]
+    Deferred(Duration),
+
+    #[error("Server asked to retry in {0:?}")]
+    RetryAfter(Duration),
+
+    #[error("Delivery panicked")]
+    Panicked,
+
+    #[error("Too many requests, retry in {0} seconds")]
+    RateLimited(u64),
+
     #[error("Failed to extract fields from {0}")]
     Extract(&'static str),
 
@@ -205,24 +243,33 @@ pub(crate) enum ErrorKind {
 impl ResponseError for Error {
     fn status_code(&self) -> StatusCode {
         match self.kind {
-            ErrorKind::NotAllowed(_) | ErrorKind::WrongActor(_) | ErrorKind::BadActor(_, _) => {
-                StatusCode::FORBIDDEN
-            }
+            ErrorKind::NotAllowed(_)
+            | ErrorKind::WrongActor(_)
+            | ErrorKind::BadActor(_, _)
+            | ErrorKind::Proof(_) => StatusCode::FORBIDDEN,
             ErrorKind::NotSubscribed(_) => StatusCode::UNAUTHORIZED,
-            ErrorKind::Duplicate => StatusCode::ACCEPTED,
+            ErrorKind::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
             ErrorKind::NotFound => StatusCode::NOT_FOUND,
             ErrorKind::Kind(_)
             | ErrorKind::MissingKind
             | ErrorKind::MissingId
             | ErrorKind::ObjectCount
+            | ErrorKind::ObjectFormat
+            | ErrorKind::Regex(_)
+            | ErrorKind::MessageSignature(_)
             | ErrorKind::NoSignature(_) => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(("Content-Type", "application/activity+json"))
+        let mut res = HttpResponse::build(self.status_code());
+
+        if let ErrorKind::RateLimited(retry_after) = self.kind {
+            res.insert_header(("Retry-After", retry_after.to_string()));
+        }
+
+        res.insert_header(("Content-Type", "application/activity+json"))
             .body(
                 serde_json::to_string(&serde_json::json!({
                     "error": self.kind.to_string(),
@@ -0,0 +1,272 @@
+use crate::{
+    db::{Actor, Ed25519Key},
+    error::{Error, ErrorKind},
+};
+use activitystreams::iri_string::types::IriString;
+use ring::{
+    digest::{digest, SHA256},
+    signature::{UnparsedPublicKey, ED25519},
+};
+use serde_json::Value;
+
+// multicodec prefix for an ed25519 public key
+const ED25519_PUB: [u8; 2] = [0xed, 0x01];
+
+/// Checks the activity's Object Integrity Proof (FEP-8b32), if it has one this relay understands.
+///
+/// Returns whether a proof was verified. Only the eddsa-jcs-2022 cryptosuite is supported, other
+/// proofs and RsaSignature2017 signatures are left for the receiving servers to check. The key
+/// has to be one of the Ed25519 keys the actor lists, as cached with the actor.
+pub(crate) fn verify(actor: &Actor, activity: &Value) -> Result<bool, Error> {
+    let Some(proof) = supported_proof(activity) else {
+        return Ok(false);
+    };
+
+    let result = verify_proof(actor, activity, proof);
+
+    if result.is_ok() {
+        metrics::counter!("relay.integrity-proof.valid").increment(1);
+    } else {
+        metrics::counter!("relay.integrity-proof.invalid").increment(1);
+    }
+
+    result.map(|()| true)
+}
+
+fn verify_proof(actor: &Actor, activity: &Value, proof: &Value) -> Result<(), Error> {
+    let method = proof
+        .get("verificationMethod")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ErrorKind::Proof("missing verificationMethod".into()))?;
+
+    let public_key = actor
+        .ed25519_key(method)
+        .ok_or_else(|| ErrorKind::Proof(format!("{method} isn't a key of {}", actor.id)))?;
+
+    verify_with_key(activity, proof, public_key)
+}
+
+fn supported_proof(activity: &Value) -> Option<&Value> {
+    let is_supported = |proof: &&Value| {
+        proof.get("type").and_then(|v| v.as_str()) == Some("DataIntegrityProof")
+            && proof.get("cryptosuite").and_then(|v| v.as_str()) == Some("eddsa-jcs-2022")
+    };
+
+    match activity.get("proof")? {
+        Value::Array(proofs) => proofs.iter().find(is_supported),
+        proof => Some(proof).filter(is_supported),
+    }
+}
+
+/// The Ed25519 keys an actor lists in its assertionMethod (FEP-521a)
+pub(crate) fn assertion_keys(actor: &Value, actor_id: &IriString) -> Vec<Ed25519Key> {
+    let methods = match actor.get("assertionMethod") {
+        Some(Value::Array(methods)) => methods.iter().collect(),
+        Some(method) => vec![method],
+        None => vec![],
+    };
+
+    methods
+        .into_iter()
+        .filter(|key| key.get("controller").and_then(|v| v.as_str()) == Some(actor_id.as_str()))
+        .filter_map(|key| {
+            let id = key.get("id")?.as_str()?.parse().ok()?;
+            let multibase = key.get("publicKeyMultibase")?.as_str()?;
+            let public_key = decode_multikey(multibase).ok()??;
+
+            Some(Ed25519Key { id, public_key })
+        })
+        .collect()
+}
+
+/// Encodes an Ed25519 public key as a Multikey publicKeyMultibase
+pub(crate) fn encode_multikey(public_key: &[u8]) -> String {
+    let mut bytes = ED25519_PUB.to_vec();
+    bytes.extend_from_slice(public_key);
+
+    format!("z{}", bs58::encode(bytes).into_string())
+}
+
+// Returns None when the key isn't an ed25519 key
+fn decode_multikey(multibase: &str) -> Result<Option<Vec<u8>>, Error> {
+    let decoded = decode_multibase(multibase)?;
+
+    match decoded.strip_prefix(&ED25519_PUB) {
+        Some(public_key) if public_key.len() == 32 => Ok(Some(public_key.to_vec())),
+        _ => Ok(None),
+    }
+}
+
+fn verify_with_key(activity: &Value, proof: &Value, public_key: &[u8]) -> Result<(), Error> {
+    let proof_value = proof
+        .get("proofValue")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| ErrorKind::Proof("missing proofValue".into()))?;
+    let signature = decode_multibase(proof_value)?;
+
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&hash_data(activity, proof), &signature)
+        .map_err(|_| ErrorKind::Proof("signature doesn't match".into()).into())
+}
+
+// The proof signs the hash of its own options followed by the hash of the unsigned document
+fn hash_data(activity: &Value, proof: &Value) -> Vec<u8> {
+    let mut document = activity.clone();
+    let mut options = proof.clone();
+
+    if let Some(document) = document.as_object_mut() {
+        document.remove("proof");
+    }
+
+    if let Some(options) = options.as_object_mut() {
+        options.remove("proofValue");
+
+        if let Some(context) = activity.get("@context") {
+            options.insert("@context".into(), context.clone());
+        }
+    }
+
+    let mut hash = digest(&SHA256, canonicalize(&options).as_bytes())
+        .as_ref()
+        .to_vec();
+    hash.extend_from_slice(digest(&SHA256, canonicalize(&document).as_bytes()).as_ref());
+    hash
+}
+
+fn decode_multibase(s: &str) -> Result<Vec<u8>, Error> {
+    // z is the multibase prefix for base58btc
+    let encoded = s
+        .strip_prefix('z')
+        .ok_or_else(|| ErrorKind::Proof("expected a base58btc multibase value".into()))?;
+
+    Ok(bs58::decode(encoded).into_vec()?)
+}
+
+/// JSON Canonicalization Scheme (RFC 8785)
+fn canonicalize(value: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(value, out);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            // keys sort by their UTF-16 code units
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        value => out.push_str(&value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assertion_keys, canonicalize, encode_multikey, hash_data, verify_with_key};
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    #[test]
+    fn canonical_json_sorts_keys_and_drops_whitespace() {
+        let value = serde_json::json!({ "b": [1, "two", null], "a": { "d": true, "c": "é" } });
+
+        assert_eq!(
+            canonicalize(&value),
+            r#"{"a":{"c":"é","d":true},"b":[1,"two",null]}"#
+        );
+    }
+
+    #[test]
+    fn signed_activities_verify_until_changed() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        let mut activity = serde_json::json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": "https://example.com/activities/1",
+            "type": "Delete",
+            "actor": "https://example.com/users/alice",
+            "object": "https://example.com/notes/1",
+        });
+        let mut proof = serde_json::json!({
+            "type": "DataIntegrityProof",
+            "cryptosuite": "eddsa-jcs-2022",
+            "verificationMethod": "https://example.com/users/alice#ed25519-key",
+            "proofPurpose": "assertionMethod",
+        });
+
+        let signature = key_pair.sign(&hash_data(&activity, &proof));
+        proof["proofValue"] = format!("z{}", bs58::encode(signature.as_ref()).into_string()).into();
+        activity["proof"] = proof.clone();
+
+        let actor = serde_json::json!({
+            "id": "https://example.com/users/alice",
+            "assertionMethod": [{
+                "id": "https://example.com/users/alice#ed25519-key",
+                "type": "Multikey",
+                "controller": "https://example.com/users/alice",
+                "publicKeyMultibase": encode_multikey(key_pair.public_key().as_ref()),
+            }],
+        });
+
+        let actor_id = "https://example.com/users/alice".parse().unwrap();
+        let keys = assertion_keys(&actor, &actor_id);
+
+        assert!(verify_with_key(&activity, &proof, &keys[0].public_key).is_ok());
+
+        activity["object"] = "https://example.com/notes/2".into();
+        assert!(verify_with_key(&activity, &proof, &keys[0].public_key).is_err());
+    }
+
+    #[test]
+    fn assertion_keys_must_belong_to_the_actor() {
+        let multibase = encode_multikey(&[3; 32]);
+        let actor_id = "https://example.com/users/alice".parse().unwrap();
+        let actor = serde_json::json!({
+            "id": "https://example.com/users/alice",
+            "assertionMethod": [{
+                "id": "https://example.com/users/alice#ed25519-key",
+                "type": "Multikey",
+                "controller": "https://example.com/users/alice",
+                "publicKeyMultibase": multibase,
+            }, {
+                "id": "https://example.com/users/mallory#ed25519-key",
+                "type": "Multikey",
+                "controller": "https://example.com/users/mallory",
+                "publicKeyMultibase": multibase,
+            }],
+        });
+
+        let keys = assertion_keys(&actor, &actor_id);
+
+        assert_eq!(keys.len(), 1);
+        assert_eq!(
+            keys[0].id.as_str(),
+            "https://example.com/users/alice#ed25519-key"
+        );
+        assert_eq!(keys[0].public_key, vec![3; 32]);
+    }
+}
@@ -1,30 +1,41 @@
 pub mod apub;
 mod contact;
 mod deliver;
+mod deliver_batch;
 mod deliver_many;
+mod expire_relayed;
 mod instance;
 mod nodeinfo;
 mod process_listeners;
+mod record_breakers;
 mod record_last_online;
+mod storage;
 
 pub(crate) use self::{
-    contact::QueryContact, deliver::Deliver, deliver_many::DeliverMany, instance::QueryInstance,
+    contact::QueryContact,
+    deliver::Deliver,
+    deliver_batch::{resume_batches, BatchPolicy, BatchScheduler, DeliverBatch},
+    deliver_many::{DeliverMany, ObjectInfo},
+    instance::QueryInstance,
     nodeinfo::QueryNodeinfo,
+    storage::Storage,
 };
 
 use crate::{
     config::Config,
     data::{ActorCache, MediaCache, State},
     error::{Error, ErrorKind},
-    jobs::{process_listeners::Listeners, record_last_online::RecordLastOnline},
+    jobs::{
+        expire_relayed::ExpireRelayed, process_listeners::Listeners,
+        record_breakers::RecordBreakers, record_last_online::RecordLastOnline,
+    },
 };
 use background_jobs::{
-    memory_storage::{Storage, TokioTimer},
     metrics::MetricsStorage,
     tokio::{QueueHandle, WorkerConfig},
     Job,
 };
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 fn debug_object(activity: &serde_json::Value) -> &serde_json::Value {
     let mut object = &activity["object"]["type"];
@@ -41,37 +52,45 @@ fn debug_object(activity: &serde_json::Value) -> &serde_json::Value {
 }
 
 pub(crate) fn create_workers(
+    storage: Storage,
     state: State,
     actors: ActorCache,
     media: MediaCache,
     config: Config,
 ) -> std::io::Result<JobServer> {
     let deliver_concurrency = config.deliver_concurrency();
-
-    let queue_handle = WorkerConfig::new(
-        MetricsStorage::wrap(Storage::new(TokioTimer)),
-        move |queue_handle| {
-            JobState::new(
-                state.clone(),
-                actors.clone(),
-                JobServer::new(queue_handle),
-                media.clone(),
-                config.clone(),
-            )
-        },
-    )
+    let batches = config.batch_policy().map(BatchScheduler::new);
+
+    let queue_handle = WorkerConfig::new(MetricsStorage::wrap(storage), move |queue_handle| {
+        JobState::new(
+            state.clone(),
+            actors.clone(),
+            JobServer::new(queue_handle),
+            media.clone(),
+            config.clone(),
+            batches.clone(),
+        )
+    })
     .register::<Deliver>()
+    .register::<DeliverBatch>()
     .register::<DeliverMany>()
     .register::<QueryNodeinfo>()
     .register::<QueryInstance>()
     .register::<Listeners>()
     .register::<QueryContact>()
     .register::<RecordLastOnline>()
+    .register::<RecordBreakers>()
+    .register::<ExpireRelayed>()
     .register::<apub::Announce>()
+    .register::<apub::Deny>()
     .register::<apub::Follow>()
+    .register::<apub::FollowUpstream>()
     .register::<apub::Forward>()
+    .register::<apub::Move>()
     .register::<apub::Reject>()
     .register::<apub::Undo>()
+    .register::<apub::UnfollowUpstream>()
+    .register::<apub::UpdateActor>()
     .set_worker_count("maintenance", 2)
     .set_worker_count("apub", 2)
     .set_worker_count("deliver", deliver_concurrency)
@@ -79,6 +98,16 @@ pub(crate) fn create_workers(
 
     queue_handle.every(Duration::from_secs(60 * 5), Listeners)?;
     queue_handle.every(Duration::from_secs(60 * 10), RecordLastOnline)?;
+    queue_handle.every(Duration::from_secs(60 * 10), RecordBreakers)?;
+    queue_handle.every(Duration::from_secs(60 * 10), ExpireRelayed)?;
+
+    Ok(JobServer::new(queue_handle))
+}
+
+/// Queues jobs without running any, for work started outside the server threads. Their workers
+/// share the storage, so they pick these jobs up
+pub(crate) fn create_queue(storage: Storage) -> std::io::Result<JobServer> {
+    let queue_handle = WorkerConfig::new(MetricsStorage::wrap(storage), |_| ()).start()?;
 
     Ok(JobServer::new(queue_handle))
 }
@@ -90,6 +119,8 @@ pub(crate) struct JobState {
     config: Config,
     media: MediaCache,
     job_server: JobServer,
+    /// Set when deliveries to an inbox are collected into batches
+    batches: Option<BatchScheduler>,
 }
 
 #[derive(Clone)]
@@ -112,6 +143,7 @@ impl JobState {
         job_server: JobServer,
         media: MediaCache,
         config: Config,
+        batches: Option<BatchScheduler>,
     ) -> Self {
         JobState {
             state,
@@ -119,6 +151,7 @@ impl JobState {
             config,
             media,
             job_server,
+            batches,
         }
     }
 }
@@ -140,6 +173,17 @@ impl JobServer {
             .map_err(ErrorKind::Queue)
             .map_err(Into::into)
     }
+
+    pub(crate) async fn schedule<J>(&self, job: J, after: SystemTime) -> Result<(), Error>
+    where
+        J: Job,
+    {
+        self.remote
+            .schedule(job, after)
+            .await
+            .map_err(ErrorKind::Queue)
+            .map_err(Into::into)
+    }
 }
 
 struct Boolish {
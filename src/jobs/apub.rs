@@ -5,7 +5,7 @@ use crate::{
     error::{Error, ErrorKind},
 };
 use activitystreams::{
-    activity::{Follow as AsFollow, Undo as AsUndo},
+    activity::{Follow as AsFollow, Reject as AsReject, Undo as AsUndo},
     context,
     iri_string::types::IriString,
     prelude::*,
@@ -14,13 +14,20 @@ use activitystreams::{
 use std::convert::TryInto;
 
 mod announce;
+mod deny;
 mod follow;
+mod follow_upstream;
 mod forward;
+mod move_actor;
 mod reject;
 mod undo;
+mod unfollow_upstream;
+mod update_actor;
 
 pub(crate) use self::{
-    announce::Announce, follow::Follow, forward::Forward, reject::Reject, undo::Undo,
+    announce::Announce, deny::Deny, follow::Follow, follow_upstream::FollowUpstream,
+    forward::Forward, move_actor::Move, reject::Reject, undo::Undo,
+    unfollow_upstream::UnfollowUpstream, update_actor::UpdateActor,
 };
 
 async fn get_inboxes(
@@ -51,6 +58,21 @@ where
     Ok(t)
 }
 
+// Generate a type that says "I want to follow you"
+fn generate_follow(
+    config: &Config,
+    actor_id: &IriString,
+    my_id: &IriString,
+) -> Result<AsFollow, Error> {
+    let follow = AsFollow::new(my_id.clone(), actor_id.clone());
+
+    prepare_activity(
+        follow,
+        config.generate_url(UrlKind::Activity),
+        actor_id.clone(),
+    )
+}
+
 // Generate a type that says "I want to stop following you"
 fn generate_undo_follow(
     config: &Config,
@@ -65,3 +87,47 @@ fn generate_undo_follow(
 
     prepare_activity(undo, config.generate_url(UrlKind::Actor), actor_id.clone())
 }
+
+// Generate a type that says "I no longer want to follow you", naming the follow that was sent
+fn generate_undo_upstream(
+    config: &Config,
+    actor_id: &IriString,
+    follow_id: &IriString,
+    my_id: &IriString,
+) -> Result<AsUndo, Error> {
+    let mut follow = AsFollow::new(my_id.clone(), actor_id.clone());
+
+    follow.set_id(follow_id.clone());
+
+    let undo = AsUndo::new(my_id.clone(), follow.into_any_base()?);
+
+    prepare_activity(
+        undo,
+        config.generate_url(UrlKind::Activity),
+        actor_id.clone(),
+    )
+}
+
+// Generate a type that says "I reject your follow request"
+//
+// Without the follow's id, servers can still match the follow by its actor and object
+fn generate_reject_follow(
+    config: &Config,
+    actor_id: &IriString,
+    input_id: Option<&IriString>,
+    my_id: &IriString,
+) -> Result<AsReject, Error> {
+    let mut follow = AsFollow::new(actor_id.clone(), my_id.clone());
+
+    if let Some(input_id) = input_id {
+        follow.set_id(input_id.clone());
+    }
+
+    let reject = AsReject::new(my_id.clone(), follow.into_any_base()?);
+
+    prepare_activity(
+        reject,
+        config.generate_url(UrlKind::Activity),
+        actor_id.clone(),
+    )
+}
@@ -1,20 +1,31 @@
 use crate::{
+    apub::is_authored_by,
     config::{Config, UrlKind},
+    data::Verdict,
     db::Actor,
     error::{Error, ErrorKind},
     future::BoxFuture,
     jobs::{
         apub::{get_inboxes, prepare_activity},
-        DeliverMany, JobState,
+        DeliverMany, JobState, ObjectInfo,
     },
+    requests::BreakerStrategy,
+};
+use activitystreams::{
+    activity::Announce as AsAnnounce, base::AnyBase, iri_string::types::IriString,
 };
-use activitystreams::{activity::Announce as AsAnnounce, iri_string::types::IriString};
 use background_jobs::Job;
 
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub(crate) struct Announce {
     object_id: IriString,
+    /// The object embedded in its author's Create, so filters don't have to fetch it
+    #[serde(default)]
+    object: Option<serde_json::Value>,
     actor: Actor,
+    /// The author's Create as it was received, forwarded to litepub subscribers
+    #[serde(default)]
+    activity: Option<serde_json::Value>,
 }
 
 impl std::fmt::Debug for Announce {
@@ -27,8 +38,77 @@ impl std::fmt::Debug for Announce {
 }
 
 impl Announce {
-    pub fn new(object_id: IriString, actor: Actor) -> Self {
-        Announce { object_id, actor }
+    pub fn new(object_id: IriString, object: Option<serde_json::Value>, actor: Actor) -> Self {
+        Announce {
+            object_id,
+            object,
+            actor,
+            activity: None,
+        }
+    }
+
+    pub(crate) fn with_activity(mut self, activity: serde_json::Value) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+
+    // Only fetch the object when a filter, a subscriber's node config, or the object cache
+    // needs it
+    async fn object(&self, state: &JobState) -> Result<Option<serde_json::Value>, Error> {
+        let objects = state.state.objects.as_ref();
+
+        // only trust an embedded object from its author
+        let embedded = self
+            .object
+            .as_ref()
+            .filter(|object| is_authored_by(object, &self.actor.id));
+
+        if let Some(object) = embedded {
+            if let Some(objects) = objects {
+                objects
+                    .store(self.object_id.clone(), object.clone())
+                    .await?;
+            }
+
+            return Ok(Some(object.clone()));
+        }
+
+        if let Some(objects) = objects {
+            if let Some(object) = objects.get(&self.object_id).await? {
+                metrics::counter!("relay.object-cache.hit").increment(1);
+                return Ok(Some(object));
+            }
+
+            metrics::counter!("relay.object-cache.miss").increment(1);
+        } else if !state.state.needs_object().await? {
+            return Ok(None);
+        }
+
+        let object = match state
+            .state
+            .requests
+            .fetch::<serde_json::Value>(&self.object_id, BreakerStrategy::Require2XX)
+            .await
+        {
+            Ok(object) => object,
+            Err(e) => {
+                // relayed as a post in an unknown language, unless filters need to read it
+                tracing::warn!(
+                    "Failed to fetch {}, continuing without it: {e}",
+                    self.object_id
+                );
+                metrics::counter!("relay.object-cache.fetch-failure").increment(1);
+                return Ok(None);
+            }
+        };
+
+        if let Some(objects) = objects {
+            objects
+                .store(self.object_id.clone(), object.clone())
+                .await?;
+        }
+
+        Ok(Some(object))
     }
 
     #[tracing::instrument(name = "Announce", skip(state))]
@@ -51,14 +131,60 @@ impl Announce {
             tracing::warn!("Failed to read node config, skipping receive-only check");
         }
 
-        let announce = generate_announce(&state.config, &activity_id, &self.object_id)?;
-        let inboxes = get_inboxes(&state.state, &self.actor, &self.object_id).await?;
+        let object = self.object(&state).await?;
+
+        // only forward the original activity when its embedded object is from its author
+        let original = self.activity.filter(|_| {
+            self.object
+                .as_ref()
+                .is_some_and(|object| is_authored_by(object, &self.actor.id))
+        });
+
+        let verdict = state.state.filters.check_object(object.as_ref());
+        if verdict == Verdict::Drop {
+            if object.is_some() {
+                tracing::info!("{} matched a filter, dropping", self.object_id);
+            } else {
+                tracing::warn!(
+                    "{} couldn't be read to check filters, dropping",
+                    self.object_id
+                );
+            }
+            return Ok(());
+        }
+
+        // with the object cache on, subscribers get the object itself instead of fetching it
+        let embedded = object.clone().filter(|_| state.state.objects.is_some());
+        let announce = generate_announce(&state.config, &activity_id, &self.object_id, embedded)?;
+        let outbox_activity = serde_json::to_value(&announce)?;
+        let inboxes = get_inboxes(&state.state, &self.actor, &self.object_id)
+            .await?
+            .into_iter()
+            .filter(|inbox| inbox.authority_str().is_some_and(|a| verdict.allows(a)))
+            .collect();
         state
             .job_server
-            .queue(DeliverMany::new(inboxes, announce, authority.to_owned(), true)?)
+            .queue(
+                DeliverMany::new(inboxes, announce, authority.to_owned(), true)?
+                    .with_object(
+                        object
+                            .as_ref()
+                            .map(ObjectInfo::from_object)
+                            .unwrap_or_else(ObjectInfo::unknown),
+                    )
+                    .with_original(original),
+            )
             .await?;
 
-        state.state.cache(self.object_id, activity_id);
+        // restricted posts stay out of the public outbox
+        if verdict == Verdict::Relay {
+            state.state.db.add_to_outbox(outbox_activity).await?;
+        }
+
+        state
+            .state
+            .record_relayed(self.object_id, activity_id)
+            .await?;
         Ok(())
     }
 }
@@ -68,8 +194,15 @@ fn generate_announce(
     config: &Config,
     activity_id: &IriString,
     object_id: &IriString,
+    object: Option<serde_json::Value>,
 ) -> Result<AsAnnounce, Error> {
-    let announce = AsAnnounce::new(config.generate_url(UrlKind::Actor), object_id.clone());
+    let announce = match object {
+        Some(object) => AsAnnounce::new(
+            config.generate_url(UrlKind::Actor),
+            AnyBase::from_arbitrary_json(object)?,
+        ),
+        None => AsAnnounce::new(config.generate_url(UrlKind::Actor), object_id.clone()),
+    };
 
     prepare_activity(
         announce,
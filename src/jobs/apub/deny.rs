@@ -0,0 +1,62 @@
+use crate::{
+    apub::AcceptedActivities,
+    config::UrlKind,
+    db::Actor,
+    error::{Error, ErrorKind},
+    future::BoxFuture,
+    jobs::{apub::generate_reject_follow, Deliver, JobState},
+};
+use activitystreams::prelude::*;
+use background_jobs::Job;
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub(crate) struct Deny {
+    input: AcceptedActivities,
+    actor: Actor,
+}
+
+impl std::fmt::Debug for Deny {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Deny")
+            .field("input", &self.input.id_unchecked())
+            .field("actor", &self.actor.id)
+            .finish()
+    }
+}
+
+impl Deny {
+    pub(crate) fn new(input: AcceptedActivities, actor: Actor) -> Self {
+        Deny { input, actor }
+    }
+
+    #[tracing::instrument(name = "Deny", skip(state))]
+    async fn perform(self, state: JobState) -> Result<(), Error> {
+        let my_id = state.config.generate_url(UrlKind::Actor);
+
+        let reject = generate_reject_follow(
+            &state.config,
+            &self.actor.id,
+            Some(self.input.id_unchecked().ok_or(ErrorKind::MissingId)?),
+            &my_id,
+        )?;
+
+        state
+            .job_server
+            .queue(Deliver::new(self.actor.inbox, reject)?)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Job for Deny {
+    type State = JobState;
+    type Future = BoxFuture<'static, anyhow::Result<()>>;
+
+    const NAME: &'static str = "relay::jobs::apub::Deny";
+    const QUEUE: &'static str = "apub";
+
+    fn run(self, state: Self::State) -> Self::Future {
+        Box::pin(async move { self.perform(state).await.map_err(Into::into) })
+    }
+}
@@ -4,7 +4,10 @@ use crate::{
     db::Actor,
     error::{Error, ErrorKind},
     future::BoxFuture,
-    jobs::{apub::prepare_activity, Deliver, JobState, QueryInstance, QueryNodeinfo},
+    jobs::{
+        apub::{generate_follow, prepare_activity},
+        Deliver, JobState, QueryInstance, QueryNodeinfo,
+    },
 };
 use activitystreams::{
     activity::{Accept as AsAccept, Follow as AsFollow},
@@ -17,6 +20,9 @@ use background_jobs::Job;
 pub(crate) struct Follow {
     input: AcceptedActivities,
     actor: Actor,
+    /// Set when the actor only wants posts with this hashtag
+    #[serde(default)]
+    tag: Option<String>,
 }
 
 impl std::fmt::Debug for Follow {
@@ -24,13 +30,23 @@ impl std::fmt::Debug for Follow {
         f.debug_struct("Follow")
             .field("input", &self.input.id_unchecked())
             .field("actor", &self.actor.id)
+            .field("tag", &self.tag)
             .finish()
     }
 }
 
 impl Follow {
     pub fn new(input: AcceptedActivities, actor: Actor) -> Self {
-        Follow { input, actor }
+        Follow {
+            input,
+            actor,
+            tag: None,
+        }
+    }
+
+    pub(crate) fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
     }
 
     #[tracing::instrument(name = "Follow", skip(state))]
@@ -38,9 +54,10 @@ impl Follow {
         let my_id = state.config.generate_url(UrlKind::Actor);
 
         // if following relay directly, not just following 'public', followback
-        if self.input.object_is(&my_id)
-            && !state.state.db.is_connected(self.actor.id.clone()).await?
-        {
+        let follow_back = self.input.object_is(&my_id)
+            && !state.state.db.is_connected(self.actor.id.clone()).await?;
+
+        if follow_back {
             let follow = generate_follow(&state.config, &self.actor.id, &my_id)?;
             state
                 .job_server
@@ -48,14 +65,31 @@ impl Follow {
                 .await?;
         }
 
+        // checked before connecting, so an actor that already gets everything keeps it
+        if let Some(tag) = self.tag.clone() {
+            state
+                .state
+                .db
+                .subscribe_tag(self.actor.id.clone(), self.actor.inbox.clone(), tag)
+                .await?;
+        } else {
+            state.state.db.clear_tags(self.actor.id.clone()).await?;
+        }
+
         state.actors.add_connection(self.actor.clone()).await?;
 
-        let accept = generate_accept_follow(
-            &state.config,
-            &self.actor.id,
-            self.input.id_unchecked().ok_or(ErrorKind::MissingId)?,
-            &my_id,
-        )?;
+        let follow_id = self.input.id_unchecked().ok_or(ErrorKind::MissingId)?;
+        state
+            .state
+            .db
+            .save_follow_id(self.actor.id.clone(), follow_id.clone())
+            .await?;
+
+        if follow_back {
+            state.state.db.add_following(self.actor.id.clone()).await?;
+        }
+
+        let accept = generate_accept_follow(&state.config, &self.actor.id, follow_id, &my_id)?;
 
         state
             .job_server
@@ -76,21 +110,6 @@ impl Follow {
     }
 }
 
-// Generate a type that says "I want to follow you"
-fn generate_follow(
-    config: &Config,
-    actor_id: &IriString,
-    my_id: &IriString,
-) -> Result<AsFollow, Error> {
-    let follow = AsFollow::new(my_id.clone(), actor_id.clone());
-
-    prepare_activity(
-        follow,
-        config.generate_url(UrlKind::Activity),
-        actor_id.clone(),
-    )
-}
-
 // Generate a type that says "I accept your follow request"
 fn generate_accept_follow(
     config: &Config,
@@ -0,0 +1,73 @@
+use crate::{
+    config::UrlKind,
+    db::Upstream,
+    error::{Error, ErrorKind},
+    future::BoxFuture,
+    jobs::{apub::generate_follow, Deliver, JobState},
+};
+use activitystreams::{iri_string::types::IriString, prelude::*};
+use background_jobs::Job;
+use time::OffsetDateTime;
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub(crate) struct FollowUpstream {
+    pub(crate) actor_id: IriString,
+    pub(crate) added_allow: bool,
+}
+
+impl std::fmt::Debug for FollowUpstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FollowUpstream")
+            .field("actor_id", &self.actor_id.to_string())
+            .field("added_allow", &self.added_allow)
+            .finish()
+    }
+}
+
+impl FollowUpstream {
+    #[tracing::instrument(name = "Follow upstream", skip(state))]
+    async fn perform(self, state: JobState) -> Result<(), Error> {
+        let actor = state
+            .actors
+            .get(&self.actor_id, &state.state.requests)
+            .await?
+            .into_inner();
+
+        let my_id = state.config.generate_url(UrlKind::Actor);
+        let follow = generate_follow(&state.config, &actor.id, &my_id)?;
+        let follow_id = follow.id_unchecked().ok_or(ErrorKind::MissingId)?.clone();
+
+        // saved first, so the Accept can't arrive before the relay knows about the follow
+        state
+            .state
+            .db
+            .add_upstream(Upstream {
+                actor_id: actor.id.clone(),
+                inbox: actor.inbox.clone(),
+                follow_id,
+                accepted: false,
+                followed_at: OffsetDateTime::now_utc(),
+                added_allow: self.added_allow,
+            })
+            .await?;
+
+        state
+            .job_server
+            .queue(Deliver::new(actor.inbox, follow)?)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Job for FollowUpstream {
+    type State = JobState;
+    type Future = BoxFuture<'static, anyhow::Result<()>>;
+
+    const NAME: &'static str = "relay::jobs::apub::FollowUpstream";
+    const QUEUE: &'static str = "apub";
+
+    fn run(self, state: Self::State) -> Self::Future {
+        Box::pin(async move { self.perform(state).await.map_err(Into::into) })
+    }
+}
@@ -12,6 +12,9 @@ use background_jobs::Job;
 pub(crate) struct Forward {
     input: AcceptedActivities,
     actor: Actor,
+    /// The activity exactly as it was received, keeping the author's signatures intact
+    #[serde(default)]
+    raw: Option<serde_json::Value>,
 }
 
 impl std::fmt::Debug for Forward {
@@ -25,7 +28,16 @@ impl std::fmt::Debug for Forward {
 
 impl Forward {
     pub fn new(input: AcceptedActivities, actor: Actor) -> Self {
-        Forward { input, actor }
+        Forward {
+            input,
+            actor,
+            raw: None,
+        }
+    }
+
+    pub(crate) fn with_raw(mut self, raw: serde_json::Value) -> Self {
+        self.raw = Some(raw);
+        self
     }
 
     #[tracing::instrument(name = "Forward", skip(state))]
@@ -42,10 +54,12 @@ impl Forward {
 
         let inboxes = get_inboxes(&state.state, &self.actor, object_id).await?;
 
-        state
-            .job_server
-            .queue(DeliverMany::new(inboxes, self.input, authority.to_owned(), false)?)
-            .await?;
+        let deliver_many = match self.raw {
+            Some(raw) => DeliverMany::new(inboxes, raw, authority.to_owned(), false)?,
+            None => DeliverMany::new(inboxes, self.input, authority.to_owned(), false)?,
+        };
+
+        state.job_server.queue(deliver_many).await?;
 
         Ok(())
     }
@@ -0,0 +1,124 @@
+use crate::{
+    config::UrlKind,
+    db::Actor,
+    error::Error,
+    future::BoxFuture,
+    jobs::{apub::generate_follow, Deliver, JobState, QueryInstance, QueryNodeinfo},
+    requests::BreakerStrategy,
+};
+use activitystreams::{iri_string::types::IriString, primitives::OneOrMany};
+use background_jobs::Job;
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub(crate) struct Move {
+    actor: Actor,
+    target: IriString,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AlsoKnownAs {
+    also_known_as: Option<OneOrMany<IriString>>,
+}
+
+impl std::fmt::Debug for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Move")
+            .field("actor", &self.actor.id)
+            .field("target", &self.target.to_string())
+            .finish()
+    }
+}
+
+impl Move {
+    pub(crate) fn new(actor: Actor, target: IriString) -> Self {
+        Move { actor, target }
+    }
+
+    #[tracing::instrument(name = "Move", skip(state))]
+    async fn perform(self, state: JobState) -> Result<(), Error> {
+        // moving can't carry a subscription to a domain that couldn't subscribe itself
+        let db = &state.state.db;
+        if db.is_blocked(self.target.clone()).await? || !db.is_allowed(self.target.clone()).await? {
+            tracing::warn!("{} isn't allowed to subscribe, ignoring Move", self.target);
+            return Ok(());
+        }
+
+        let document = state
+            .state
+            .requests
+            .fetch::<serde_json::Value>(&self.target, BreakerStrategy::Require2XX)
+            .await?;
+        let AlsoKnownAs { also_known_as } = serde_json::from_value(document.clone())?;
+
+        // the new actor must claim the old one, otherwise anyone could steal a subscription
+        let is_alias = also_known_as
+            .map(|aliases| aliases.iter().any(|alias| *alias == self.actor.id))
+            .unwrap_or(false);
+
+        if !is_alias {
+            tracing::warn!(
+                "{} doesn't list {} in alsoKnownAs, ignoring Move",
+                self.target,
+                self.actor.id
+            );
+            return Ok(());
+        }
+
+        let was_following = state
+            .state
+            .db
+            .following_ids()
+            .await?
+            .contains(&self.actor.id);
+
+        let target = state.actors.save_document(&self.target, document).await?;
+
+        let was_connected = state
+            .state
+            .db
+            .migrate_actor(self.actor.id.clone(), target.id.clone())
+            .await?;
+
+        // upstream relays can send a Move too, but only a subscriber's carries a subscription
+        if !was_connected {
+            tracing::warn!("{} wasn't subscribed, ignoring Move", self.actor.id);
+            return Ok(());
+        }
+
+        state.actors.add_connection(target.clone()).await?;
+
+        if was_following {
+            let my_id = state.config.generate_url(UrlKind::Actor);
+            let follow = generate_follow(&state.config, &target.id, &my_id)?;
+            state
+                .job_server
+                .queue(Deliver::new(target.inbox.clone(), follow)?)
+                .await?;
+        }
+
+        state
+            .job_server
+            .queue(QueryInstance::new(target.id.clone()))
+            .await?;
+
+        state
+            .job_server
+            .queue(QueryNodeinfo::new(target.id))
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Job for Move {
+    type State = JobState;
+    type Future = BoxFuture<'static, anyhow::Result<()>>;
+
+    const NAME: &'static str = "relay::jobs::apub::Move";
+    const QUEUE: &'static str = "apub";
+
+    fn run(self, state: Self::State) -> Self::Future {
+        Box::pin(async move { self.perform(state).await.map_err(Into::into) })
+    }
+}
@@ -3,7 +3,10 @@ use crate::{
     db::Actor,
     error::Error,
     future::BoxFuture,
-    jobs::{apub::generate_undo_follow, Deliver, JobState},
+    jobs::{
+        apub::{generate_reject_follow, generate_undo_follow},
+        Deliver, JobState,
+    },
 };
 use background_jobs::Job;
 
@@ -19,9 +22,21 @@ impl std::fmt::Debug for Reject {
 impl Reject {
     #[tracing::instrument(name = "Reject", skip(state))]
     async fn perform(self, state: JobState) -> Result<(), Error> {
+        let follow_id = state.state.db.follow_id(self.0.id.clone()).await?;
+
         state.actors.remove_connection(&self.0).await?;
 
         let my_id = state.config.generate_url(UrlKind::Actor);
+
+        // let the remote server know its subscription is gone. Subscribers from before follow ids
+        // were stored have no id to name, so their follow is rebuilt from its actor and object
+        let reject = generate_reject_follow(&state.config, &self.0.id, follow_id.as_ref(), &my_id)?;
+
+        state
+            .job_server
+            .queue(Deliver::new(self.0.inbox.clone(), reject)?)
+            .await?;
+
         let undo = generate_undo_follow(&state.config, &self.0.id, &my_id)?;
 
         state
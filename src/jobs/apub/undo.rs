@@ -13,6 +13,9 @@ use background_jobs::Job;
 pub(crate) struct Undo {
     input: AcceptedActivities,
     actor: Actor,
+    /// Set when only a single hashtag subscription is undone
+    #[serde(default)]
+    tag: Option<String>,
 }
 
 impl std::fmt::Debug for Undo {
@@ -20,17 +23,39 @@ impl std::fmt::Debug for Undo {
         f.debug_struct("Undo")
             .field("input", &self.input.id_unchecked())
             .field("actor", &self.actor.id)
+            .field("tag", &self.tag)
             .finish()
     }
 }
 
 impl Undo {
     pub(crate) fn new(input: AcceptedActivities, actor: Actor) -> Self {
-        Undo { input, actor }
+        Undo {
+            input,
+            actor,
+            tag: None,
+        }
+    }
+
+    pub(crate) fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
     }
 
     #[tracing::instrument(name = "Undo", skip(state))]
     async fn perform(self, state: JobState) -> Result<(), Error> {
+        if let Some(tag) = self.tag.clone() {
+            let still_subscribed = state
+                .state
+                .db
+                .unsubscribe_tag(self.actor.id.clone(), tag)
+                .await?;
+
+            if still_subscribed {
+                return Ok(());
+            }
+        }
+
         let was_following = state.state.db.is_connected(self.actor.id.clone()).await?;
 
         state.actors.remove_connection(&self.actor).await?;
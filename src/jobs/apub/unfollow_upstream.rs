@@ -0,0 +1,47 @@
+use crate::{
+    config::UrlKind,
+    db::Upstream,
+    error::Error,
+    future::BoxFuture,
+    jobs::{apub::generate_undo_upstream, Deliver, JobState},
+};
+use background_jobs::Job;
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub(crate) struct UnfollowUpstream(pub(crate) Upstream);
+
+impl std::fmt::Debug for UnfollowUpstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnfollowUpstream")
+            .field("actor_id", &self.0.actor_id.to_string())
+            .finish()
+    }
+}
+
+impl UnfollowUpstream {
+    #[tracing::instrument(name = "Unfollow upstream", skip(state))]
+    async fn perform(self, state: JobState) -> Result<(), Error> {
+        let my_id = state.config.generate_url(UrlKind::Actor);
+        let undo =
+            generate_undo_upstream(&state.config, &self.0.actor_id, &self.0.follow_id, &my_id)?;
+
+        state
+            .job_server
+            .queue(Deliver::new(self.0.inbox, undo)?)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Job for UnfollowUpstream {
+    type State = JobState;
+    type Future = BoxFuture<'static, anyhow::Result<()>>;
+
+    const NAME: &'static str = "relay::jobs::apub::UnfollowUpstream";
+    const QUEUE: &'static str = "apub";
+
+    fn run(self, state: Self::State) -> Self::Future {
+        Box::pin(async move { self.perform(state).await.map_err(Into::into) })
+    }
+}
@@ -0,0 +1,55 @@
+use crate::{
+    config::UrlKind,
+    error::Error,
+    future::BoxFuture,
+    jobs::{apub::prepare_activity, DeliverMany, JobState},
+    routes::actor_document,
+};
+use activitystreams::{activity::Update, base::AnyBase};
+use background_jobs::Job;
+
+/// Tells every subscriber the relay's actor changed, so they refresh its key
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) struct UpdateActor;
+
+impl UpdateActor {
+    #[tracing::instrument(name = "Update actor", skip(state))]
+    async fn perform(self, state: JobState) -> Result<(), Error> {
+        let actor = actor_document(&state.config, &state.state)?;
+
+        let update = prepare_activity(
+            Update::new(
+                state.config.generate_url(UrlKind::Actor),
+                AnyBase::from_arbitrary_json(actor)?,
+            ),
+            state.config.generate_url(UrlKind::Activity),
+            state.config.generate_url(UrlKind::Followers),
+        )?;
+
+        let inboxes = state.state.db.inboxes().await?;
+
+        state
+            .job_server
+            .queue(DeliverMany::new(
+                inboxes,
+                update,
+                state.config.hostname().to_owned(),
+                false,
+            )?)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Job for UpdateActor {
+    type State = JobState;
+    type Future = BoxFuture<'static, anyhow::Result<()>>;
+
+    const NAME: &'static str = "relay::jobs::apub::UpdateActor";
+    const QUEUE: &'static str = "apub";
+
+    fn run(self, state: Self::State) -> Self::Future {
+        Box::pin(async move { self.perform(state).await.map_err(Into::into) })
+    }
+}
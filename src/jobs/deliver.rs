@@ -1,16 +1,31 @@
 use crate::{
+    db::DeadLetter,
     error::Error,
     future::BoxFuture,
     jobs::{debug_object, JobState},
     requests::BreakerStrategy,
 };
 use activitystreams::iri_string::types::IriString;
-use background_jobs::{Backoff, Job};
+use background_jobs::{Job, MaxRetries};
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+// Retries before a delivery is given up on and recorded as a dead letter
+pub(super) const MAX_RETRIES: u32 = 3;
+
+// Deferrals before a server that keeps asking for more time is treated as failing
+pub(super) const MAX_DEFERRALS: u32 = 10;
 
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub(crate) struct Deliver {
     to: IriString,
     data: serde_json::Value,
+    #[serde(default)]
+    retries: u32,
+    #[serde(default)]
+    deferrals: u32,
+    #[serde(default = "SystemTime::now")]
+    queued_at: SystemTime,
 }
 
 impl std::fmt::Debug for Deliver {
@@ -19,6 +34,8 @@ impl std::fmt::Debug for Deliver {
             .field("to", &self.to.to_string())
             .field("activity", &self.data["type"])
             .field("object", debug_object(&self.data))
+            .field("retries", &self.retries)
+            .field("deferrals", &self.deferrals)
             .finish()
     }
 }
@@ -31,29 +48,122 @@ impl Deliver {
         Ok(Deliver {
             to,
             data: serde_json::to_value(data)?,
+            retries: 0,
+            deferrals: 0,
+            queued_at: SystemTime::now(),
         })
     }
 
     #[tracing::instrument(name = "Deliver", skip(state))]
-    async fn permform(self, state: JobState) -> Result<(), Error> {
-        if let Err(e) = state
+    async fn permform(mut self, state: JobState) -> Result<(), Error> {
+        let Err(e) = state
             .state
             .requests
             .deliver(&self.to, &self.data, BreakerStrategy::Allow401AndBelow)
             .await
-        {
-            if e.is_breaker() {
-                tracing::debug!("Not trying due to failed breaker");
+        else {
+            return Ok(());
+        };
+
+        match deferral(&e, &mut self.deferrals) {
+            Some(Deferral::Wait(wait)) => {
+                tracing::debug!("Deferring delivery for {wait:?}");
+                metrics::counter!("relay.deliver.deferred").increment(1);
+
+                let after = SystemTime::now() + wait;
+                state.job_server.schedule(self, after).await?;
                 return Ok(());
             }
-            if e.is_bad_request() {
-                tracing::debug!("Server didn't understand the activity");
-                return Ok(());
+            Some(Deferral::Failing) => {
+                tracing::warn!(
+                    "{} kept deferring the delivery, counting it as failing",
+                    self.to
+                );
+                state.state.requests.fail_breaker(&self.to);
             }
-            return Err(e);
+            None => (),
         }
-        Ok(())
+        if gives_up_right_away(&e) {
+            tracing::debug!("Giving up without retrying, {e}");
+        } else if self.retries < MAX_RETRIES {
+            self.retries += 1;
+
+            let after = SystemTime::now() + retry_wait(self.retries);
+            state.job_server.schedule(self, after).await?;
+            return Ok(());
+        }
+
+        dead_letter(&state, self.to, self.data, &e, self.retries, self.queued_at).await
+    }
+}
+
+pub(super) enum Deferral {
+    /// Try the delivery again after this long
+    Wait(Duration),
+    /// The server kept asking for more time, so it's treated as failing
+    Failing,
+}
+
+// Only deferrals the server asked for count toward MAX_DEFERRALS. Waiting on this relay's own
+// limits says nothing about the server, so those are waited out however often they happen
+pub(super) fn deferral(error: &Error, deferrals: &mut u32) -> Option<Deferral> {
+    let wait = error.deferred()?;
+
+    if !error.is_retry_after() {
+        return Some(Deferral::Wait(wait));
+    }
+
+    if *deferrals < MAX_DEFERRALS {
+        *deferrals += 1;
+        return Some(Deferral::Wait(wait));
+    }
+
+    Some(Deferral::Failing)
+}
+
+// An open breaker skips every delivery to the server, and a server that didn't understand the
+// activity won't the next time either, so retrying wouldn't help
+pub(super) fn gives_up_right_away(error: &Error) -> bool {
+    error.is_breaker() || error.is_bad_request()
+}
+
+// Matches the exponential backoff jobs get when they fail
+pub(super) fn retry_wait(retries: u32) -> Duration {
+    Duration::from_secs(8u64.saturating_pow(retries))
+}
+
+/// Records a delivery that was given up on, so it can be looked into or replayed
+pub(super) async fn dead_letter(
+    state: &JobState,
+    inbox: IriString,
+    activity: serde_json::Value,
+    error: &Error,
+    retries: u32,
+    queued_at: SystemTime,
+) -> Result<(), Error> {
+    metrics::counter!("relay.deliver.dead-letter").increment(1);
+
+    let capacity = state.config.dead_letter_capacity();
+
+    if capacity == 0 {
+        return Ok(());
     }
+
+    let dead_letter = DeadLetter {
+        id: Uuid::new_v4(),
+        activity_id: activity
+            .get("id")
+            .and_then(|id| id.as_str())
+            .and_then(|id| id.parse().ok()),
+        inbox,
+        activity,
+        error: error.to_string(),
+        retries,
+        queued_at: queued_at.into(),
+        failed_at: SystemTime::now().into(),
+    };
+
+    state.state.db.add_dead_letter(dead_letter, capacity).await
 }
 
 impl Job for Deliver {
@@ -62,12 +172,46 @@ impl Job for Deliver {
 
     const NAME: &'static str = "relay::jobs::Deliver";
     const QUEUE: &'static str = "deliver";
-    const BACKOFF: Backoff = Backoff::Exponential(8);
-
-    //TEMPORAL PATCH
-    const MAX_RETRIES: background_jobs::MaxRetries = background_jobs::MaxRetries::Count(3);
+    // retries are scheduled by the job itself, with the delivery's own count
+    const MAX_RETRIES: MaxRetries = MaxRetries::Count(0);
 
     fn run(self, state: Self::State) -> Self::Future {
         Box::pin(async move { self.permform(state).await.map_err(Into::into) })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{deferral, Deferral, MAX_DEFERRALS};
+    use crate::error::{Error, ErrorKind};
+    use std::time::Duration;
+
+    #[test]
+    fn only_servers_asking_for_time_run_out_of_deferrals() {
+        let mut deferrals = 0;
+
+        let limited: Error = ErrorKind::Deferred(Duration::from_secs(1)).into();
+        for _ in 0..MAX_DEFERRALS * 2 {
+            assert!(matches!(
+                deferral(&limited, &mut deferrals),
+                Some(Deferral::Wait(_))
+            ));
+        }
+        assert_eq!(deferrals, 0);
+
+        let asked: Error = ErrorKind::RetryAfter(Duration::from_secs(1)).into();
+        for _ in 0..MAX_DEFERRALS {
+            assert!(matches!(
+                deferral(&asked, &mut deferrals),
+                Some(Deferral::Wait(_))
+            ));
+        }
+        assert!(matches!(
+            deferral(&asked, &mut deferrals),
+            Some(Deferral::Failing)
+        ));
+
+        let failed: Error = ErrorKind::Breaker.into();
+        assert!(deferral(&failed, &mut deferrals).is_none());
+    }
+}
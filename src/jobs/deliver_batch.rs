@@ -0,0 +1,297 @@
+use crate::{
+    db::Db,
+    error::{Error, ErrorKind},
+    future::BoxFuture,
+    jobs::{
+        deliver::{dead_letter, deferral, gives_up_right_away, retry_wait, Deferral, MAX_RETRIES},
+        JobServer, JobState,
+    },
+    requests::BreakerStrategy,
+};
+use activitystreams::iri_string::types::IriString;
+use background_jobs::{Job, MaxRetries};
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+// Activities waiting for one inbox beyond this drop the oldest
+const MAX_PENDING: usize = 1000;
+
+/// How long activities for an inbox are collected before they're sent, and how many go at once
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BatchPolicy {
+    pub(crate) window: Duration,
+    pub(crate) size: usize,
+}
+
+/// Collects the activities headed to each inbox so they're delivered by one job, in order
+///
+/// An inbox has at most one batch being delivered at a time. Activities that arrive while it's
+/// being delivered wait for it, so an inbox never sees them out of order. Pending activities are
+/// kept in the database, so they're still delivered after a restart.
+#[derive(Clone, Debug)]
+pub(crate) struct BatchScheduler {
+    policy: BatchPolicy,
+}
+
+/// Delivers the pending activities of one inbox, oldest first
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub(crate) struct DeliverBatch {
+    to: IriString,
+    batch_id: Uuid,
+    /// Retries of the first activity
+    #[serde(default)]
+    retries: u32,
+    /// Deferrals of the first activity
+    #[serde(default)]
+    deferrals: u32,
+    #[serde(default = "SystemTime::now")]
+    queued_at: SystemTime,
+}
+
+impl std::fmt::Debug for DeliverBatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeliverBatch")
+            .field("to", &self.to.to_string())
+            .field("batch_id", &self.batch_id)
+            .field("retries", &self.retries)
+            .field("deferrals", &self.deferrals)
+            .finish()
+    }
+}
+
+impl BatchScheduler {
+    pub(crate) fn new(policy: BatchPolicy) -> Self {
+        BatchScheduler { policy }
+    }
+
+    /// Adds an activity to the inbox's batch, delivering it once the window has passed
+    pub(super) async fn enqueue(
+        &self,
+        state: &JobState,
+        to: IriString,
+        data: serde_json::Value,
+    ) -> Result<(), Error> {
+        let db = &state.state.db;
+
+        let Some(batch_id) = db.add_batch_activity(to.clone(), data, MAX_PENDING).await? else {
+            return Ok(());
+        };
+
+        let after = SystemTime::now() + self.policy.window;
+
+        if let Err(e) = state
+            .job_server
+            .schedule(DeliverBatch::new(to.clone(), batch_id), after)
+            .await
+        {
+            // the next activity for the inbox starts a batch again
+            db.abandon_batch(to, batch_id).await?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+/// Queues the batches of inboxes with activities still pending, like after a restart
+pub(crate) async fn resume_batches(db: &Db, job_server: &JobServer) -> Result<usize, Error> {
+    let batches = db.resume_batches().await?;
+    let count = batches.len();
+
+    for (to, batch_id) in batches {
+        job_server.queue(DeliverBatch::new(to, batch_id)).await?;
+    }
+
+    Ok(count)
+}
+
+impl DeliverBatch {
+    fn new(to: IriString, batch_id: Uuid) -> Self {
+        DeliverBatch {
+            to,
+            batch_id,
+            retries: 0,
+            deferrals: 0,
+            queued_at: SystemTime::now(),
+        }
+    }
+
+    #[tracing::instrument(name = "Deliver batch", skip(state))]
+    async fn perform(mut self, state: JobState) -> Result<(), Error> {
+        let size = state
+            .batches
+            .as_ref()
+            .map_or(1, |batches| batches.policy.size.max(1));
+
+        let Some(activities) = state
+            .state
+            .db
+            .batch_activities(self.to.clone(), self.batch_id, size)
+            .await?
+        else {
+            tracing::debug!("Batch was replaced, skipping");
+            return Ok(());
+        };
+
+        let mut activities = activities.into_iter();
+
+        while let Some((key, activity)) = activities.next() {
+            let res = state
+                .state
+                .requests
+                .deliver(&self.to, &activity, BreakerStrategy::Allow401AndBelow)
+                .await;
+
+            let Err(e) = res else {
+                self.remove(&state, key).await?;
+                self.retries = 0;
+                self.deferrals = 0;
+                continue;
+            };
+
+            match deferral(&e, &mut self.deferrals) {
+                Some(Deferral::Wait(wait)) => {
+                    tracing::debug!("Deferring batch for {wait:?}");
+                    metrics::counter!("relay.deliver.deferred").increment(1);
+
+                    return self.retry(&state, wait).await;
+                }
+                Some(Deferral::Failing) => {
+                    tracing::warn!("{} kept deferring the batch, counting it failed", self.to);
+                    state.state.requests.fail_breaker(&self.to);
+                }
+                None => (),
+            }
+            if gives_up_right_away(&e) {
+                tracing::debug!("Giving up without retrying, {e}");
+                self.give_up(&state, key, activity, &e).await?;
+                continue;
+            }
+            if self.retries < MAX_RETRIES {
+                self.retries += 1;
+
+                let wait = retry_wait(self.retries);
+                return self.retry(&state, wait).await;
+            }
+
+            tracing::warn!("Giving up on the rest of the batch, {e}");
+
+            // the rest would fail the same way
+            self.give_up(&state, key, activity, &e).await?;
+            for (key, activity) in activities.by_ref() {
+                self.give_up(&state, key, activity, &e).await?;
+            }
+        }
+
+        Self::next(&state, self.to, self.batch_id).await
+    }
+
+    async fn remove(&self, state: &JobState, key: Vec<u8>) -> Result<(), Error> {
+        state
+            .state
+            .db
+            .remove_batch_activity(self.to.clone(), key)
+            .await
+    }
+
+    // Records the activity as a dead letter
+    async fn give_up(
+        &mut self,
+        state: &JobState,
+        key: Vec<u8>,
+        activity: serde_json::Value,
+        error: &Error,
+    ) -> Result<(), Error> {
+        dead_letter(
+            state,
+            self.to.clone(),
+            activity,
+            error,
+            self.retries,
+            self.queued_at,
+        )
+        .await?;
+
+        self.remove(state, key).await?;
+        self.retries = 0;
+        self.deferrals = 0;
+
+        Ok(())
+    }
+
+    // The inbox stays busy while its batch waits, so nothing overtakes it
+    async fn retry(self, state: &JobState, wait: Duration) -> Result<(), Error> {
+        let after = SystemTime::now() + wait;
+        let to = self.to.clone();
+        let batch_id = self.batch_id;
+
+        if let Err(e) = state.job_server.schedule(self, after).await {
+            // nothing will finish this batch, so don't leave the inbox waiting on it
+            state.state.db.abandon_batch(to, batch_id).await?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    // Delivers whatever arrived for the inbox in the meantime, or leaves it idle
+    async fn next(state: &JobState, to: IriString, batch_id: Uuid) -> Result<(), Error> {
+        if state.state.db.finish_batch(to.clone(), batch_id).await? {
+            return Ok(());
+        }
+
+        let batch = DeliverBatch::new(to.clone(), batch_id);
+
+        if let Err(e) = state.job_server.queue(batch).await {
+            state.state.db.abandon_batch(to, batch_id).await?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    // A panic part way through leaves the activity being delivered first in line, so it's given
+    // up on, and the rest of the batch carries on
+    async fn recover(self, state: &JobState) -> Result<(), Error> {
+        let first = state
+            .state
+            .db
+            .batch_activities(self.to.clone(), self.batch_id, 1)
+            .await?
+            .and_then(|activities| activities.into_iter().next());
+
+        if let Some((key, activity)) = first {
+            let error = ErrorKind::Panicked.into();
+            let mut batch = self.clone();
+            batch.give_up(state, key, activity, &error).await?;
+        }
+
+        Self::next(state, self.to, self.batch_id).await
+    }
+}
+
+impl Job for DeliverBatch {
+    type State = JobState;
+    type Future = BoxFuture<'static, anyhow::Result<()>>;
+
+    const NAME: &'static str = "relay::jobs::DeliverBatch";
+    const QUEUE: &'static str = "deliver";
+    // retries are scheduled by the job itself, so a batch isn't delivered twice
+    const MAX_RETRIES: MaxRetries = MaxRetries::Count(0);
+
+    fn run(self, state: Self::State) -> Self::Future {
+        Box::pin(async move {
+            let batch = self.clone();
+            let job_state = state.clone();
+
+            // run apart, so a panic can't leave the inbox waiting on this batch forever
+            match tokio::spawn(self.perform(state)).await {
+                Ok(res) => res.map_err(Into::into),
+                Err(e) => {
+                    tracing::error!("Delivering a batch to {} panicked, {e}", batch.to);
+                    batch.recover(&job_state).await.map_err(Into::into)
+                }
+            }
+        })
+    }
+}
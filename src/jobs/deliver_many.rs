@@ -1,4 +1,5 @@
 use crate::{
+    apub::normalize_tag,
     error::Error,
     future::BoxFuture,
     jobs::{debug_object, Deliver, JobState},
@@ -6,8 +7,9 @@ use crate::{
 use activitystreams::iri_string::types::IriString;
 use background_jobs::Job;
 use rand::Rng;
+use std::collections::BTreeSet;
 
-use crate::data::NodeConfig;
+use crate::data::{DeliveryMode, NodeConfig};
 
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub(crate) struct DeliverMany {
@@ -15,6 +17,94 @@ pub(crate) struct DeliverMany {
     filterable: bool,
     data: serde_json::Value,
     actor_authority: String,
+    #[serde(default)]
+    object: ObjectInfo,
+    /// The activity as it was received, for subscribers in litepub mode
+    #[serde(default)]
+    original: Option<serde_json::Value>,
+}
+
+/// What subscribers can filter on, read from the relayed object
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub(crate) struct ObjectInfo {
+    /// Primary language subtags, empty when the language is unknown
+    languages: BTreeSet<String>,
+    /// Normalized hashtags
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    #[serde(default)]
+    sensitive: bool,
+    #[serde(default)]
+    has_media: bool,
+    /// Whether the summary holds a content warning
+    #[serde(default)]
+    has_cw: bool,
+    /// Set when the object couldn't be read, so none of the above is known
+    #[serde(default)]
+    unknown: bool,
+}
+
+impl ObjectInfo {
+    pub(crate) fn unknown() -> Self {
+        ObjectInfo {
+            unknown: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn from_object(object: &serde_json::Value) -> Self {
+        let mut languages = BTreeSet::new();
+
+        for field in ["contentMap", "summaryMap", "nameMap"] {
+            if let Some(map) = object.get(field).and_then(|v| v.as_object()) {
+                languages.extend(map.keys().map(|tag| primary_language(tag)));
+            }
+        }
+
+        if let Some(tag) = object.get("language").and_then(|v| v.as_str()) {
+            languages.insert(primary_language(tag));
+        }
+
+        // "und" is the BCP 47 tag for an undetermined language
+        languages.remove("und");
+
+        let tags = match object.get("tag") {
+            Some(serde_json::Value::Array(tags)) => tags.iter().filter_map(hashtag).collect(),
+            Some(tag) => hashtag(tag).into_iter().collect(),
+            None => BTreeSet::new(),
+        };
+
+        let has_media = match object.get("attachment") {
+            Some(serde_json::Value::Array(attachments)) => !attachments.is_empty(),
+            Some(attachment) => !attachment.is_null(),
+            None => false,
+        };
+
+        ObjectInfo {
+            languages,
+            tags,
+            sensitive: object.get("sensitive").and_then(|v| v.as_bool()) == Some(true),
+            has_media,
+            has_cw: object
+                .get("summary")
+                .and_then(|v| v.as_str())
+                .is_some_and(|summary| !summary.trim().is_empty()),
+            unknown: false,
+        }
+    }
+}
+
+fn hashtag(tag: &serde_json::Value) -> Option<String> {
+    if tag.get("type").and_then(|v| v.as_str()) != Some("Hashtag") {
+        return None;
+    }
+
+    normalize_tag(tag.get("name")?.as_str()?)
+}
+
+// en-US and en both become en
+fn primary_language(tag: &str) -> String {
+    tag.split(['-', '_']).next().unwrap_or(tag).to_lowercase()
 }
 
 impl std::fmt::Debug for DeliverMany {
@@ -36,14 +126,58 @@ impl DeliverMany {
             filterable,
             data: serde_json::to_value(data)?,
             actor_authority,
+            object: ObjectInfo::default(),
+            original: None,
         })
     }
 
-    fn apply_filter(dice: u8, authority: &str, config: &NodeConfig) -> bool {
+    pub(crate) fn with_object(mut self, object: ObjectInfo) -> Self {
+        self.object = object;
+        self
+    }
+
+    pub(crate) fn with_original(mut self, original: Option<serde_json::Value>) -> Self {
+        self.original = original;
+        self
+    }
+
+    fn apply_filter(dice: u8, authority: &str, object: &ObjectInfo, config: &NodeConfig) -> bool {
         if config.enable_probability && config.probability < dice {
             return false;
         }
 
+        if config.reject_sensitive && object.sensitive {
+            return false;
+        }
+
+        // a post that couldn't be read might be sensitive or have media
+        if object.unknown
+            && (config.reject_sensitive || config.reject_media || config.require_cw_for_media)
+        {
+            return false;
+        }
+
+        if object.has_media
+            && (config.reject_media || (config.require_cw_for_media && !object.has_cw))
+        {
+            return false;
+        }
+
+        if !config.languages.is_empty() {
+            let wanted = if object.languages.is_empty() {
+                config.accept_unknown_language
+            } else {
+                config
+                    .languages
+                    .iter()
+                    .any(|language| object.languages.contains(&primary_language(language)))
+            };
+
+            if !wanted {
+                return false;
+            }
+        }
+
         let has_authority = config.authority_set.contains(authority);
 
         if config.is_allowlist {
@@ -57,7 +191,26 @@ impl DeliverMany {
     async fn perform(self, state: JobState) -> Result<(), Error> {
         let dice = rand::thread_rng().gen::<u8>();
 
+        let inbox_tags = if self.filterable {
+            state.state.db.inbox_tags().await?
+        } else {
+            Default::default()
+        };
+
+        let delivery_modes = if self.original.is_some() {
+            state.state.delivery_modes().await?
+        } else {
+            Default::default()
+        };
+
         for inbox in self.to {
+            if let Some(tags) = inbox_tags.get(&inbox) {
+                if tags.is_disjoint(&self.object.tags) {
+                    tracing::debug!("Skipping egress to {}, no followed tags", inbox);
+                    continue;
+                }
+            }
+
             if self.filterable {
                 // All inbox should have... authority... but...
                 let inbox_authority = inbox.authority_str().unwrap_or("");
@@ -71,17 +224,29 @@ impl DeliverMany {
                 };
 
                 if let Some(cfg) = node_config.get(inbox_authority) {
-                    if !Self::apply_filter(dice, &self.actor_authority, cfg) {
+                    if !Self::apply_filter(dice, &self.actor_authority, &self.object, cfg) {
                         tracing::info!("Skipping egress to {} due to given criteria", inbox_authority);
                         continue;
                     }
                 }
             }
 
-            state
-                .job_server
-                .queue(Deliver::new(inbox, self.data.clone())?)
-                .await?;
+            let mode = inbox
+                .authority_str()
+                .and_then(|authority| delivery_modes.get(authority))
+                .copied()
+                .unwrap_or_default();
+
+            let data = match (&self.original, mode) {
+                (Some(original), DeliveryMode::Litepub) => original.clone(),
+                _ => self.data.clone(),
+            };
+
+            if let Some(batches) = &state.batches {
+                batches.enqueue(&state, inbox, data).await?;
+            } else {
+                state.job_server.queue(Deliver::new(inbox, data)?).await?;
+            }
         }
 
         Ok(())
@@ -99,3 +264,162 @@ impl Job for DeliverMany {
         Box::pin(async move { self.perform(state).await.map_err(Into::into) })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{DeliverMany, ObjectInfo};
+    use crate::data::{DeliveryMode, NodeConfig};
+
+    fn node_config(accept_unknown_language: bool) -> NodeConfig {
+        serde_json::from_value(serde_json::json!({
+            "probability": 0,
+            "enable_probability": false,
+            "authority_set": [],
+            "is_allowlist": false,
+            "receive_only": false,
+            "languages": ["en", "pt-BR"],
+            "accept_unknown_language": accept_unknown_language,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn languages_are_matched_on_primary_subtag() {
+        let config = node_config(false);
+
+        let portuguese = ObjectInfo::from_object(&serde_json::json!({
+            "contentMap": { "pt": "Olá" },
+        }));
+        let german = ObjectInfo::from_object(&serde_json::json!({
+            "contentMap": { "de-AT": "Servus" },
+        }));
+
+        assert!(DeliverMany::apply_filter(
+            0,
+            "example.com",
+            &portuguese,
+            &config
+        ));
+        assert!(!DeliverMany::apply_filter(
+            0,
+            "example.com",
+            &german,
+            &config
+        ));
+    }
+
+    #[test]
+    fn media_needs_a_content_warning_when_required() {
+        let mut config = node_config(true);
+        config.languages.clear();
+        config.require_cw_for_media = true;
+
+        let attachment = serde_json::json!([{
+            "type": "Document",
+            "url": "https://example.com/a.png",
+        }]);
+        let bare = ObjectInfo::from_object(&serde_json::json!({ "attachment": attachment }));
+        let warned = ObjectInfo::from_object(&serde_json::json!({
+            "summary": "eye contact",
+            "sensitive": true,
+            "attachment": attachment,
+        }));
+
+        assert!(!DeliverMany::apply_filter(0, "example.com", &bare, &config));
+        assert!(DeliverMany::apply_filter(
+            0,
+            "example.com",
+            &warned,
+            &config
+        ));
+
+        config.reject_sensitive = true;
+        assert!(!DeliverMany::apply_filter(
+            0,
+            "example.com",
+            &warned,
+            &config
+        ));
+    }
+
+    #[test]
+    fn unreadable_posts_skip_sensitive_and_media_policies() {
+        let mut config = node_config(true);
+        let unknown = ObjectInfo::unknown();
+
+        assert!(DeliverMany::apply_filter(
+            0,
+            "example.com",
+            &unknown,
+            &config
+        ));
+
+        config.accept_unknown_language = false;
+        assert!(!DeliverMany::apply_filter(
+            0,
+            "example.com",
+            &unknown,
+            &config
+        ));
+
+        config.languages.clear();
+        config.reject_media = true;
+        assert!(!DeliverMany::apply_filter(
+            0,
+            "example.com",
+            &unknown,
+            &config
+        ));
+    }
+
+    #[test]
+    fn delivery_mode_follows_software_unless_overridden() {
+        assert_eq!(DeliveryMode::detect("Akkoma"), DeliveryMode::Litepub);
+        assert_eq!(DeliveryMode::detect("mastodon"), DeliveryMode::Mastodon);
+        assert_eq!(node_config(true).delivery_mode, None);
+
+        let config: NodeConfig = serde_json::from_value(serde_json::json!({
+            "probability": 0,
+            "enable_probability": false,
+            "authority_set": [],
+            "is_allowlist": false,
+            "receive_only": false,
+            "delivery_mode": "litepub",
+        }))
+        .unwrap();
+        assert_eq!(config.delivery_mode, Some(DeliveryMode::Litepub));
+    }
+
+    #[test]
+    fn hashtags_are_read_from_tag() {
+        let object = ObjectInfo::from_object(&serde_json::json!({
+            "tag": [
+                { "type": "Hashtag", "name": "#Rust" },
+                { "type": "Mention", "name": "@someone" },
+            ],
+        }));
+
+        assert_eq!(object.tags.into_iter().collect::<Vec<_>>(), ["rust"]);
+    }
+
+    #[test]
+    fn unknown_languages_follow_the_node_config() {
+        let unknown = ObjectInfo::from_object(&serde_json::json!({
+            "content": "hello",
+            "contentMap": { "und": "hello" },
+        }));
+
+        assert!(DeliverMany::apply_filter(
+            0,
+            "example.com",
+            &unknown,
+            &node_config(true)
+        ));
+        assert!(!DeliverMany::apply_filter(
+            0,
+            "example.com",
+            &unknown,
+            &node_config(false)
+        ));
+    }
+}
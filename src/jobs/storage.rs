@@ -0,0 +1,90 @@
+use crate::{db::Db, error::Error};
+use background_jobs::dev::{JobInfo, NewJobInfo, ReturnJobInfo};
+use dashmap::DashMap;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+const MAX_WAIT: Duration = Duration::from_secs(10);
+
+/// Job storage that keeps queued jobs in sled, so they survive restarts
+#[derive(Clone)]
+pub(crate) struct Storage {
+    db: Db,
+    queues: Arc<DashMap<String, Arc<Notify>>>,
+}
+
+impl std::fmt::Debug for Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Storage").field("db", &"Db").finish()
+    }
+}
+
+impl Storage {
+    pub(crate) fn new(db: Db) -> Self {
+        Storage {
+            db,
+            queues: Arc::new(DashMap::new()),
+        }
+    }
+
+    fn notifier(&self, queue: &str) -> Arc<Notify> {
+        self.queues
+            .entry(queue.to_string())
+            .or_insert_with(|| Arc::new(Notify::new()))
+            .clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl background_jobs::dev::Storage for Storage {
+    type Error = Error;
+
+    async fn info(&self, job_id: Uuid) -> Result<Option<JobInfo>, Self::Error> {
+        self.db.job(job_id).await
+    }
+
+    async fn push(&self, job: NewJobInfo) -> Result<Uuid, Self::Error> {
+        let job = job.build();
+        let queue = job.queue.clone();
+
+        let id = self.db.insert_job(job).await?;
+
+        self.notifier(&queue).notify_one();
+
+        Ok(id)
+    }
+
+    async fn pop(&self, queue: &str, runner_id: Uuid) -> Result<JobInfo, Self::Error> {
+        let notify = self.notifier(queue);
+
+        loop {
+            if let Some(job) = self.db.claim_job(queue.to_string(), runner_id).await? {
+                return Ok(job);
+            }
+
+            let wait = self
+                .db
+                .next_job_in(queue.to_string())
+                .await?
+                .map(|duration| duration.min(MAX_WAIT))
+                .unwrap_or(MAX_WAIT);
+
+            let _ = tokio::time::timeout(wait, notify.notified()).await;
+        }
+    }
+
+    async fn heartbeat(&self, job_id: Uuid, runner_id: Uuid) -> Result<(), Self::Error> {
+        self.db.heartbeat_job(job_id, runner_id).await
+    }
+
+    async fn complete(&self, return_job_info: ReturnJobInfo) -> Result<bool, Self::Error> {
+        match self.db.complete_job(return_job_info).await? {
+            Some(queue) => {
+                self.notifier(&queue).notify_one();
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+}
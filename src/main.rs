@@ -33,6 +33,7 @@ mod db;
 mod error;
 mod extractors;
 mod future;
+mod integrity;
 mod jobs;
 mod middleware;
 mod requests;
@@ -47,9 +48,12 @@ use self::{
     config::Config,
     data::{ActorCache, MediaCache, State},
     db::Db,
-    jobs::create_workers,
-    middleware::{DebugPayload, MyVerify, RelayResolver, Timings},
-    routes::{actor, healthz, inbox, index, nodeinfo, nodeinfo_meta, statics},
+    jobs::{create_queue, create_workers, resume_batches, Storage},
+    middleware::{DebugPayload, MessageSignature, MyVerify, RateLimit, RelayResolver, Timings},
+    routes::{
+        actor, followers, following, healthz, inbox, index, nodeinfo, nodeinfo_meta, outbox,
+        statics, tag,
+    },
     spawner::Spawner,
 };
 
@@ -269,6 +273,140 @@ async fn do_client_main(config: Config, args: Args) -> Result<(), anyhow::Error>
     if args.stats() {
         let stats = admin::client::stats(&client, &config).await?;
         stats.present();
+
+        let jobs = admin::client::jobs(&client, &config).await?;
+        println!("Job queues");
+        if jobs.queues.is_empty() {
+            println!("\tNo queued jobs");
+        }
+        for (queue, stats) in jobs.queues {
+            println!("\t{queue}: {stats}");
+        }
+    }
+
+    if !args.approve().is_empty() {
+        admin::client::approve_follows(&client, &config, args.approve().to_vec()).await?;
+        println!("Approved follows");
+    }
+
+    if !args.deny().is_empty() {
+        admin::client::deny_follows(&client, &config, args.deny().to_vec()).await?;
+        println!("Denied follows");
+    }
+
+    if args.pending() {
+        let pending = admin::client::pending_follows(&client, &config).await?;
+
+        let mut report = String::from("Pending follows:\n");
+
+        for follower in pending.pending_follows {
+            report += "\t";
+            report += &follower.received_at.to_string();
+            report += " - ";
+            report += follower.actor_id.as_str();
+            report += "\n";
+        }
+
+        println!("{report}");
+    }
+
+    if !args.follow_upstream().is_empty() {
+        admin::client::follow_upstreams(&client, &config, args.follow_upstream().to_vec()).await?;
+        println!("Following upstream relays");
+    }
+
+    if !args.unfollow_upstream().is_empty() {
+        admin::client::unfollow_upstreams(&client, &config, args.unfollow_upstream().to_vec())
+            .await?;
+        println!("Unfollowed upstream relays");
+    }
+
+    if args.rotate_key() {
+        let signing_key = admin::client::rotate_key(&client, &config).await?;
+        println!("Rotated signing key, new key id {}", signing_key.key_id);
+    }
+
+    if !args.replay_dead_letters().is_empty() {
+        let ids = args.replay_dead_letters().to_vec();
+        let replayed = admin::client::replay_dead_letters(&client, &config, ids).await?;
+        println!("Replayed {} dead letters", replayed.replayed);
+    }
+
+    if args.purge_dead_letters() {
+        admin::client::purge_dead_letters(&client, &config).await?;
+        println!("Purged dead letters");
+    }
+
+    if args.dead_letters() {
+        let dead_letters = admin::client::dead_letters(&client, &config).await?;
+
+        let mut report = String::from("Dead letters:\n");
+
+        for dead_letter in dead_letters.dead_letters {
+            report += "\t";
+            report += &dead_letter.id.to_string();
+            report += " - ";
+            report += dead_letter.inbox.as_str();
+            if let Some(activity_id) = &dead_letter.activity_id {
+                report += ", ";
+                report += activity_id.as_str();
+            }
+            report += ", failed ";
+            report += &dead_letter.failed_at.to_string();
+            report += " after ";
+            report += &dead_letter.retries.to_string();
+            report += " retries: ";
+            report += &dead_letter.error;
+            report += "\n";
+        }
+
+        println!("{report}");
+    }
+
+    if args.upstreams() {
+        let upstreams = admin::client::upstreams(&client, &config).await?;
+
+        let mut report = String::from("Upstream relays:\n");
+
+        for upstream in upstreams.upstreams {
+            report += "\t";
+            report += upstream.actor_id.as_str();
+            report += if upstream.accepted {
+                " - accepted"
+            } else {
+                " - waiting for accept"
+            };
+            report += ", followed ";
+            report += &upstream.followed_at.to_string();
+            report += "\n";
+        }
+
+        println!("{report}");
+    }
+
+    for domain in args.reset_breakers() {
+        admin::client::reset_breaker(&client, &config, domain.clone()).await?;
+        println!("Reset breaker for {domain}");
+    }
+
+    if args.breakers() {
+        let breakers = admin::client::breakers(&client, &config).await?;
+
+        let mut report = String::from("Breakers:\n");
+
+        for (domain, breaker) in breakers.breakers {
+            report += "\t";
+            report += &domain;
+            report += " - ";
+            report += &breaker.failures.to_string();
+            report += " failures, last attempt ";
+            report += &breaker.last_attempt.to_string();
+            report += ", last success ";
+            report += &breaker.last_success.to_string();
+            report += "\n";
+        }
+
+        println!("{report}");
     }
 
     Ok(())
@@ -290,7 +428,6 @@ async fn server_main(
     )?;
 
     tracing::warn!("Creating state");
-    let node_config = std::collections::HashMap::new();
 
     let (signature_threads, verify_threads) = match config.signature_threads() {
         0 | 1 => (1, 1),
@@ -307,11 +444,43 @@ async fn server_main(
     let sign_spawner = Spawner::build("sign-cpu", signature_threads.try_into()?)?;
 
     let key_id = config.generate_url(UrlKind::MainKey).to_string();
-    let state = State::build(db.clone(), key_id, sign_spawner.clone(), client, node_config).await?;
+    let ed25519_key_id = config
+        .ed25519_signatures()
+        .then(|| config.generate_url(UrlKind::Ed25519Key).to_string());
+    let state = State::build(
+        db.clone(),
+        key_id,
+        sign_spawner.clone(),
+        client,
+        config.breaker_policy(),
+        config.object_cache_policy(),
+        config.dedup_policy(),
+        config.rate_limit_policy(),
+        config.delivery_policy(),
+        ed25519_key_id,
+    )
+    .await?;
+
+    let released = db.release_jobs().await?;
+    if released > 0 {
+        tracing::info!("Released {released} jobs from previous run");
+    }
+    let job_storage = Storage::new(db.clone());
+    let job_queue = create_queue(job_storage.clone())?;
+
+    let resumed = resume_batches(&db, &job_queue).await?;
+    if resumed > 0 {
+        tracing::info!("Resumed delivery batches for {resumed} inboxes");
+    }
 
     if let Some((token, admin_handle)) = config.telegram_info() {
         tracing::warn!("Creating telegram handler");
-        telegram::start(admin_handle.to_owned(), db.clone(), token);
+        telegram::start(
+            admin_handle.to_owned(),
+            state.clone(),
+            job_queue.clone(),
+            token,
+        );
     }
 
     let keys = config.open_keys()?;
@@ -320,9 +489,14 @@ async fn server_main(
     let sign_spawner2 = sign_spawner.clone();
     let verify_spawner2 = verify_spawner.clone();
     let server = HttpServer::new(move || {
-        let job_server =
-            create_workers(state.clone(), actors.clone(), media.clone(), config.clone())
-                .expect("Failed to create job server");
+        let job_server = create_workers(
+            job_storage.clone(),
+            state.clone(),
+            actors.clone(),
+            media.clone(),
+            config.clone(),
+        )
+        .expect("Failed to create job server");
 
         let app = App::new()
             .app_data(web::Data::new(db.clone()))
@@ -343,28 +517,41 @@ async fn server_main(
             app
         };
 
+        let verifier = MyVerify(
+            state.requests.clone().spawner(verify_spawner.clone()),
+            actors.clone(),
+            state.clone(),
+            verify_spawner.clone(),
+        );
+
         app.wrap(Compress::default())
             .wrap(TracingLogger::default())
             .wrap(Timings)
             .route("/healthz", web::get().to(healthz))
             .service(web::resource("/").route(web::get().to(index)))
             .service(web::resource("/media/{path}").route(web::get().to(routes::media)))
+            .service(web::resource("/objects/{uuid}").route(web::get().to(routes::object)))
             .service(
-                web::resource("/inbox")
+                web::resource(["/inbox", "/tags/{tag}/inbox"])
+                    .wrap(RateLimit::verified(state.inbox_limiter.clone()))
                     .wrap(config.digest_middleware().spawner(verify_spawner.clone()))
                     .wrap(VerifySignature::new(
-                        MyVerify(
-                            state.requests.clone().spawner(verify_spawner.clone()),
-                            actors.clone(),
-                            state.clone(),
-                            verify_spawner.clone(),
-                        ),
+                        verifier.clone(),
                         http_signature_normalization_actix::Config::new(),
                     ))
+                    .wrap(
+                        MessageSignature::new(verifier, &config.generate_url(UrlKind::Index))
+                            .require_digest(config.validate_signatures()),
+                    )
+                    .wrap(RateLimit::claimed(state.inbox_limiter.clone()))
                     .wrap(DebugPayload(config.debug()))
                     .route(web::post().to(inbox)),
             )
             .service(web::resource("/actor").route(web::get().to(actor)))
+            .service(web::resource("/followers").route(web::get().to(followers)))
+            .service(web::resource("/following").route(web::get().to(following)))
+            .service(web::resource("/outbox").route(web::get().to(outbox)))
+            .service(web::resource("/tags/{tag}").route(web::get().to(tag)))
             .service(web::resource("/nodeinfo/2.0.json").route(web::get().to(nodeinfo)))
             .service(
                 web::scope("/.well-known")
@@ -387,7 +574,54 @@ async fn server_main(
                         .route("/blocked", web::get().to(admin::routes::blocked))
                         .route("/connected", web::get().to(admin::routes::connected))
                         .route("/stats", web::get().to(admin::routes::stats))
-                        .route("/last_seen", web::get().to(admin::routes::last_seen)),
+                        .route("/jobs", web::get().to(admin::routes::jobs))
+                        .route("/last_seen", web::get().to(admin::routes::last_seen))
+                        .route(
+                            "/pending_follows",
+                            web::get().to(admin::routes::pending_follows),
+                        )
+                        .route(
+                            "/pending_follows/approve",
+                            web::post().to(admin::routes::approve_follows),
+                        )
+                        .route(
+                            "/pending_follows/deny",
+                            web::post().to(admin::routes::deny_follows),
+                        )
+                        .route("/upstreams", web::get().to(admin::routes::upstreams))
+                        .route(
+                            "/upstreams/follow",
+                            web::post().to(admin::routes::follow_upstreams),
+                        )
+                        .route(
+                            "/upstreams/unfollow",
+                            web::post().to(admin::routes::unfollow_upstreams),
+                        )
+                        .route("/breakers", web::get().to(admin::routes::breakers))
+                        .route(
+                            "/breakers/{domain}",
+                            web::delete().to(admin::routes::reset_breaker),
+                        )
+                        .route("/rotate_key", web::post().to(admin::routes::rotate_key))
+                        .route("/dead_letters", web::get().to(admin::routes::dead_letters))
+                        .route(
+                            "/dead_letters",
+                            web::delete().to(admin::routes::purge_dead_letters),
+                        )
+                        .route(
+                            "/dead_letters/replay",
+                            web::post().to(admin::routes::replay_dead_letters),
+                        )
+                        .route(
+                            "/dead_letters/{id}",
+                            web::delete().to(admin::routes::purge_dead_letter),
+                        )
+                        .route("/filters", web::get().to(admin::routes::filters))
+                        .route("/filters", web::post().to(admin::routes::add_filter))
+                        .route(
+                            "/filters/{id}",
+                            web::delete().to(admin::routes::remove_filter),
+                        ),
                 ),
             )
     });
@@ -1,9 +1,13 @@
+mod message_signature;
 mod payload;
+mod rate_limit;
 mod timings;
 mod verifier;
 mod webfinger;
 
+pub(crate) use message_signature::{MessageSignature, MessageSignatureVerified};
 pub(crate) use payload::DebugPayload;
+pub(crate) use rate_limit::RateLimit;
 pub(crate) use timings::Timings;
 pub(crate) use verifier::MyVerify;
 pub(crate) use webfinger::RelayResolver;
@@ -0,0 +1,527 @@
+use crate::{
+    error::{Error, ErrorKind},
+    future::LocalBoxFuture,
+    middleware::MyVerify,
+};
+use activitystreams::iri_string::types::IriString;
+use actix_web::{
+    dev::{Payload, RequestHead, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    FromRequest, HttpMessage, HttpRequest,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::digest::{Algorithm, Context, SHA256, SHA512};
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    task::Poll,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use streem::IntoStreamer;
+
+// How far in the future a signature's created time may be, to allow for clock drift
+const MAX_SKEW: u64 = 5 * 60;
+// How old a signature without an expiry may be
+const MAX_AGE: u64 = 60 * 60;
+
+/// Verifies RFC 9421 HTTP Message Signatures
+///
+/// Requests carrying a Signature-Input header are handled here and their Signature header is
+/// removed, so VerifySignature only sees draft-cavage signatures. Other requests need a Digest
+/// header when one is required, since the digest middleware can only be made optional for all.
+#[derive(Clone, Debug)]
+pub(crate) struct MessageSignature {
+    verify: MyVerify,
+    scheme: String,
+    authority: String,
+    require_digest: bool,
+}
+
+#[doc(hidden)]
+pub(crate) struct MessageSignatureMiddleware<S> {
+    config: MessageSignature,
+    service: Rc<S>,
+}
+
+/// The key an RFC 9421 signature was made with. The Content-Digest it covers is checked as the
+/// body is read
+#[derive(Clone, Debug)]
+pub(crate) struct MessageSignatureVerified(String);
+
+#[derive(Debug)]
+struct SignedMessage {
+    key_id: String,
+    signature: String,
+    signature_base: String,
+    content_digest: Option<(&'static Algorithm, Vec<u8>)>,
+}
+
+#[derive(Debug)]
+struct SignatureInput<'a> {
+    components: Vec<&'a str>,
+    // the serialized inner list and parameters, signed as @signature-params
+    params: &'a str,
+    key_id: String,
+    created: Option<u64>,
+    expires: Option<u64>,
+}
+
+impl MessageSignature {
+    /// The relay's base URI stands in for the request's, since requests often reach it through a
+    /// proxy
+    pub(crate) fn new(verify: MyVerify, base_uri: &IriString) -> Self {
+        MessageSignature {
+            verify,
+            scheme: base_uri.scheme_str().to_owned(),
+            authority: base_uri.authority_str().unwrap_or_default().to_lowercase(),
+            require_digest: false,
+        }
+    }
+
+    pub(crate) fn require_digest(mut self, require_digest: bool) -> Self {
+        self.require_digest = require_digest;
+        self
+    }
+}
+
+impl MessageSignatureVerified {
+    pub(crate) fn key_id(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for MessageSignatureVerified {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Self>()
+                .cloned()
+                .ok_or_else(|| ErrorKind::NoSignature(None).into()),
+        )
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for MessageSignature
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = MessageSignatureMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MessageSignatureMiddleware {
+            config: self.clone(),
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for MessageSignatureMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        if !req.headers().contains_key("Signature-Input") {
+            if self.config.require_digest && !req.headers().contains_key("Digest") {
+                return Box::pin(ready(Err(Error::from(ErrorKind::NoSignature(None)).into())));
+            }
+
+            return Box::pin(self.service.call(req));
+        }
+
+        let config = self.config.clone();
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let message = SignedMessage::parse(req.head(), &config.scheme, &config.authority)?;
+
+            if let Some((algorithm, expected)) = message.content_digest {
+                verify_content_digest(&mut req, algorithm, expected);
+            }
+
+            req.headers_mut().remove("Signature");
+
+            let verified = config
+                .verify
+                .verify(
+                    None,
+                    message.key_id.clone(),
+                    message.signature,
+                    message.signature_base,
+                )
+                .await?;
+
+            if verified {
+                req.extensions_mut()
+                    .insert(MessageSignatureVerified(message.key_id));
+            }
+
+            service.call(req).await
+        })
+    }
+}
+
+// Fails the body when it's fully read and doesn't match its Content-Digest
+fn verify_content_digest(
+    req: &mut ServiceRequest,
+    algorithm: &'static Algorithm,
+    expected: Vec<u8>,
+) {
+    let mut payload = req.take_payload().into_streamer();
+
+    req.set_payload(Payload::Stream {
+        payload: Box::pin(streem::try_from_fn(move |yielder| async move {
+            let mut context = Context::new(algorithm);
+
+            while let Some(bytes) = payload.try_next().await? {
+                context.update(&bytes);
+                yielder.yield_ok(bytes).await;
+            }
+
+            if context.finish().as_ref() != expected.as_slice() {
+                return Err(PayloadError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Content-Digest doesn't match the body",
+                )));
+            }
+
+            Ok(())
+        })),
+    });
+}
+
+impl SignedMessage {
+    fn parse(head: &RequestHead, scheme: &str, authority: &str) -> Result<Self, Error> {
+        let signature_inputs = header_values(head, "Signature-Input")?;
+        let signatures = header_values(head, "Signature")?;
+        let signatures = dictionary(&signatures);
+
+        // verify the first signature that's present in both headers
+        let (input, signature) = dictionary(&signature_inputs)
+            .into_iter()
+            .find_map(|(label, input)| {
+                let signature = signatures.iter().find(|(l, _)| *l == label)?.1;
+                Some((input, signature))
+            })
+            .ok_or_else(|| invalid("no Signature matches the Signature-Input"))?;
+
+        let input = SignatureInput::parse(input)?;
+        input.check_time(now())?;
+
+        let signature = signature
+            .strip_prefix(':')
+            .and_then(|s| s.strip_suffix(':'))
+            .ok_or_else(|| invalid("the signature isn't a byte sequence"))?
+            .to_owned();
+
+        let has = |name: &str| input.components.contains(&name);
+
+        if !has("@method") || !(has("@target-uri") || has("@path") || has("@request-target")) {
+            return Err(invalid("the method and path must be signed"));
+        }
+
+        let content_digest = if has("content-digest") {
+            Some(content_digest(&header_values(head, "Content-Digest")?)?)
+        } else if head.method == actix_web::http::Method::POST {
+            return Err(invalid("the content-digest must be signed"));
+        } else {
+            None
+        };
+
+        let path = head.uri.path();
+        let query = head.uri.query();
+        let path_and_query = match query {
+            Some(query) => format!("{path}?{query}"),
+            None => path.to_owned(),
+        };
+
+        let mut signature_base = String::new();
+
+        for name in &input.components {
+            let value = match *name {
+                "@method" => head.method.as_str().to_owned(),
+                "@target-uri" => format!("{scheme}://{authority}{path_and_query}"),
+                "@authority" => authority.to_owned(),
+                "@scheme" => scheme.to_owned(),
+                "@request-target" => path_and_query.clone(),
+                "@path" => path.to_owned(),
+                "@query" => format!("?{}", query.unwrap_or_default()),
+                name if name.starts_with('@') => {
+                    return Err(invalid(&format!("unsupported component {name}")));
+                }
+                name => header_values(head, name)?,
+            };
+
+            signature_base.push_str(&format!("\"{name}\": {value}\n"));
+        }
+        signature_base.push_str(&format!("\"@signature-params\": {}", input.params));
+
+        Ok(SignedMessage {
+            key_id: input.key_id,
+            signature,
+            signature_base,
+            content_digest,
+        })
+    }
+}
+
+impl<'a> SignatureInput<'a> {
+    fn parse(params: &'a str) -> Result<Self, Error> {
+        let list = params
+            .strip_prefix('(')
+            .and_then(|rest| rest.split_once(')'))
+            .map(|(list, _)| list)
+            .ok_or_else(|| invalid("the Signature-Input isn't an inner list"))?;
+
+        let mut components = Vec::new();
+        for item in list.split_whitespace() {
+            // component parameters like ;sf or ;req aren't supported
+            let name = item
+                .strip_prefix('"')
+                .and_then(|item| item.strip_suffix('"'))
+                .ok_or_else(|| invalid(&format!("unsupported component {item}")))?;
+
+            components.push(name);
+        }
+
+        let mut input = SignatureInput {
+            components,
+            params,
+            key_id: String::new(),
+            created: None,
+            expires: None,
+        };
+
+        for param in params[list.len() + 2..].split(';').skip(1) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| invalid(&format!("malformed parameter {param}")))?;
+
+            match key.trim() {
+                "keyid" => input.key_id = value.trim_matches('"').to_owned(),
+                "created" => input.created = Some(integer(value)?),
+                "expires" => input.expires = Some(integer(value)?),
+                _ => (),
+            }
+        }
+
+        if input.key_id.is_empty() {
+            return Err(invalid("missing keyid"));
+        }
+
+        Ok(input)
+    }
+
+    fn check_time(&self, now: u64) -> Result<(), Error> {
+        // without a created time, a signature could be replayed forever
+        let Some(created) = self.created else {
+            return Err(invalid("missing created"));
+        };
+
+        if created > now + MAX_SKEW {
+            return Err(invalid("the signature was created in the future"));
+        }
+
+        let expired = match self.expires {
+            Some(expires) => expires < now,
+            None => created + MAX_AGE < now,
+        };
+
+        if expired {
+            return Err(invalid("the signature has expired"));
+        }
+
+        Ok(())
+    }
+}
+
+// Splits a structured field dictionary into its members, keeping their values as they're written
+fn dictionary(header: &str) -> Vec<(&str, &str)> {
+    let mut members = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut quoted = false;
+
+    for (i, c) in header.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                members.push(&header[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    members.push(&header[start..]);
+
+    members
+        .into_iter()
+        .filter_map(|member| {
+            let (key, value) = member.trim().split_once('=')?;
+            Some((key.trim(), value.trim()))
+        })
+        .collect()
+}
+
+fn content_digest(header: &str) -> Result<(&'static Algorithm, Vec<u8>), Error> {
+    dictionary(header)
+        .into_iter()
+        .find_map(|(algorithm, value)| {
+            let algorithm = match algorithm {
+                "sha-256" => &SHA256,
+                "sha-512" => &SHA512,
+                _ => return None,
+            };
+
+            let encoded = value.strip_prefix(':')?.strip_suffix(':')?;
+            Some((algorithm, STANDARD.decode(encoded).ok()?))
+        })
+        .ok_or_else(|| invalid("no supported Content-Digest"))
+}
+
+// Every line of the header, joined the way RFC 9421 signs them
+fn header_values(head: &RequestHead, name: &str) -> Result<String, Error> {
+    let values = head
+        .headers
+        .get_all(name)
+        .map(|value| value.to_str().map(str::trim))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid(&format!("{name} isn't valid text")))?;
+
+    if values.is_empty() {
+        return Err(invalid(&format!("missing {name} header")));
+    }
+
+    Ok(values.join(", "))
+}
+
+fn integer(value: &str) -> Result<u64, Error> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid(&format!("{value} isn't an integer")))
+}
+
+fn invalid(reason: &str) -> Error {
+    ErrorKind::MessageSignature(reason.to_owned()).into()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{now, SignatureInput, SignedMessage, MAX_AGE};
+    use crate::requests::{content_digest, message_signature};
+    use actix_web::test::TestRequest;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519},
+    };
+
+    const KEY_ID: &str = "https://example.com/actor#ed25519-key";
+
+    #[test]
+    fn signed_requests_verify_until_changed() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = UnparsedPublicKey::new(&ED25519, key_pair.public_key().as_ref());
+
+        let body = r#"{"type":"Announce"}"#;
+        let digest = content_digest(body);
+        let (signature_input, signature) = message_signature(
+            &key_pair,
+            KEY_ID,
+            "POST",
+            "https://relay.example/inbox",
+            Some(&digest),
+            now(),
+        );
+
+        let request = |path: &str| {
+            TestRequest::post()
+                .uri(path)
+                .insert_header(("Signature-Input", signature_input.clone()))
+                .insert_header(("Signature", signature.clone()))
+                .insert_header(("Content-Digest", digest.clone()))
+                .to_srv_request()
+        };
+
+        let message =
+            SignedMessage::parse(request("/inbox").head(), "https", "relay.example").unwrap();
+        let decoded = STANDARD.decode(&message.signature).unwrap();
+
+        assert_eq!(message.key_id, KEY_ID);
+        assert!(public_key
+            .verify(message.signature_base.as_bytes(), &decoded)
+            .is_ok());
+
+        let message =
+            SignedMessage::parse(request("/elsewhere").head(), "https", "relay.example").unwrap();
+        assert!(public_key
+            .verify(message.signature_base.as_bytes(), &decoded)
+            .is_err());
+    }
+
+    #[test]
+    fn posts_must_sign_their_content_digest() {
+        let req = TestRequest::post()
+            .uri("/inbox")
+            .insert_header((
+                "Signature-Input",
+                format!(r#"sig1=("@method" "@target-uri");keyid="{KEY_ID}""#),
+            ))
+            .insert_header(("Signature", "sig1=:AAAA:"))
+            .to_srv_request();
+
+        assert!(SignedMessage::parse(req.head(), "https", "relay.example").is_err());
+    }
+
+    #[test]
+    fn old_signatures_expire() {
+        let input = format!(r#"("@method");created=1000;keyid="{KEY_ID}""#);
+        let input = SignatureInput::parse(&input).unwrap();
+
+        assert!(input.check_time(1000 + MAX_AGE).is_ok());
+        assert!(input.check_time(1001 + MAX_AGE).is_err());
+
+        let input = format!(r#"("@method");created=1000;expires=1010;keyid="{KEY_ID}""#);
+        let input = SignatureInput::parse(&input).unwrap();
+
+        assert!(input.check_time(1010).is_ok());
+        assert!(input.check_time(1011).is_err());
+    }
+
+    #[test]
+    fn signatures_need_a_created_time() {
+        let input = format!(r#"("@method");expires=1010;keyid="{KEY_ID}""#);
+        let input = SignatureInput::parse(&input).unwrap();
+
+        assert!(input.check_time(1000).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{
+    data::RateLimiter,
+    error::{Error, ErrorKind},
+    future::LocalBoxFuture,
+    middleware::MessageSignatureVerified,
+};
+use activitystreams::iri_string::types::IriString;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::HeaderMap,
+    HttpMessage,
+};
+use http_signature_normalization_actix::prelude::SignatureVerified;
+use std::{
+    future::{ready, Ready},
+    task::Poll,
+    time::Instant,
+};
+
+/// Throttles requests by the authority of the key they're signed with
+///
+/// This wraps the inbox twice. Outside the signature checks, it turns away requests whose claimed
+/// key's server is already out of tokens, so a flood costs a header parse instead of a signature
+/// check, but takes nothing. Inside them, it takes a token for the verified key, so a server can't
+/// be charged for requests that only claim its key.
+#[derive(Clone, Debug)]
+pub(crate) struct RateLimit {
+    limiter: RateLimiter,
+    verified: bool,
+}
+
+impl RateLimit {
+    /// Rejects requests claiming an out of tokens server's key, before they're verified
+    pub(crate) fn claimed(limiter: RateLimiter) -> Self {
+        RateLimit {
+            limiter,
+            verified: false,
+        }
+    }
+
+    /// Takes a token for the verified key's server
+    pub(crate) fn verified(limiter: RateLimiter) -> Self {
+        RateLimit {
+            limiter,
+            verified: true,
+        }
+    }
+}
+
+#[doc(hidden)]
+pub(crate) struct RateLimitMiddleware<S> {
+    limiter: RateLimiter,
+    verified: bool,
+    service: S,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            limiter: self.limiter.clone(),
+            verified: self.verified,
+            service,
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let authority = if self.verified {
+            verified_authority(&req)
+        } else {
+            claimed_authority(req.headers())
+        };
+
+        if let Some(authority) = authority {
+            let now = Instant::now();
+
+            let limited = if self.verified {
+                self.limiter.check(&authority, now)
+            } else {
+                self.limiter.peek(&authority, now)
+            };
+
+            if let Err(wait) = limited {
+                tracing::info!("Throttling inbox requests from {authority}");
+                metrics::counter!("relay.inbox.throttled").increment(1);
+
+                // round up, so retrying right when told to finds a token
+                let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                let error: Error = ErrorKind::RateLimited(retry_after).into();
+
+                return Box::pin(ready(Err(error.into())));
+            }
+        }
+
+        Box::pin(self.service.call(req))
+    }
+}
+
+// The keyId of a draft-cavage Signature, or the keyid parameter of an RFC 9421 Signature-Input
+fn claimed_authority(headers: &HeaderMap) -> Option<String> {
+    let key_id = ["Signature-Input", "Signature"]
+        .into_iter()
+        .filter_map(|name| headers.get(name)?.to_str().ok())
+        .find_map(find_key_id)?;
+
+    key_authority(key_id)
+}
+
+fn find_key_id(header: &str) -> Option<&str> {
+    let start = header
+        .find("keyId=\"")
+        .or_else(|| header.find("keyid=\""))?
+        + "keyId=\"".len();
+    let rest = &header[start..];
+
+    rest.find('"').map(|end| &rest[..end])
+}
+
+// The authority of the key a draft-cavage or RFC 9421 signature was verified with
+fn verified_authority(req: &ServiceRequest) -> Option<String> {
+    let extensions = req.extensions();
+
+    let key_id = extensions
+        .get::<SignatureVerified>()
+        .map(SignatureVerified::key_id)
+        .or_else(|| {
+            extensions
+                .get::<MessageSignatureVerified>()
+                .map(MessageSignatureVerified::key_id)
+        })?;
+
+    key_authority(key_id)
+}
+
+fn key_authority(key_id: &str) -> Option<String> {
+    let key_id: IriString = key_id.parse().ok()?;
+
+    key_id.authority_str().map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{claimed_authority, key_authority};
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_static(value),
+        );
+        headers
+    }
+
+    #[test]
+    fn key_authority_is_the_lowercased_domain() {
+        assert_eq!(
+            key_authority("https://Example.com/actor#main-key").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(key_authority("garbage"), None);
+    }
+
+    #[test]
+    fn claimed_authority_is_read_from_either_signature_format() {
+        let cavage = headers(
+            "signature",
+            "keyId=\"https://Example.com/actor#main-key\",algorithm=\"rsa-sha256\"",
+        );
+        assert_eq!(claimed_authority(&cavage).as_deref(), Some("example.com"));
+
+        let rfc9421 = headers(
+            "signature-input",
+            "sig1=(\"@method\");created=1;keyid=\"https://relay.example/actor#key\"",
+        );
+        assert_eq!(
+            claimed_authority(&rfc9421).as_deref(),
+            Some("relay.example")
+        );
+
+        assert_eq!(claimed_authority(&headers("signature", "garbage")), None);
+        assert_eq!(claimed_authority(&HeaderMap::new()), None);
+    }
+}
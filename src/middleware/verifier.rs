@@ -1,6 +1,7 @@
 use crate::{
     apub::AcceptedActors,
     data::{ActorCache, State},
+    db::Actor,
     error::{Error, ErrorKind},
     requests::{BreakerStrategy, Requests},
     spawner::Spawner,
@@ -8,6 +9,9 @@ use crate::{
 use activitystreams::{base::BaseExt, iri, iri_string::types::IriString};
 use base64::{engine::general_purpose::STANDARD, Engine};
 use http_signature_normalization_actix::{prelude::*, verify::DeprecatedAlgorithm, Spawn};
+use ring::signature::{
+    UnparsedPublicKey, VerificationAlgorithm, ED25519, RSA_PKCS1_2048_8192_SHA256,
+};
 use rsa::{pkcs1::EncodeRsaPublicKey, pkcs8::DecodePublicKey, RsaPublicKey};
 use std::{future::Future, pin::Pin};
 
@@ -16,7 +20,7 @@ pub(crate) struct MyVerify(pub Requests, pub ActorCache, pub State, pub Spawner)
 
 impl MyVerify {
     #[tracing::instrument("Verify request", skip(self, signature, signing_string))]
-    async fn verify(
+    pub(super) async fn verify(
         &self,
         algorithm: Option<Algorithm>,
         key_id: String,
@@ -46,6 +50,7 @@ impl MyVerify {
             match algorithm {
                 Some(Algorithm::Hs2019) => (),
                 Some(Algorithm::Deprecated(DeprecatedAlgorithm::RsaSha256)) => (),
+                Some(Algorithm::Unknown(ref alg)) if alg == "ed25519" => (),
                 Some(other) => {
                     return Err(ErrorKind::Algorithm(other.to_string()).into());
                 }
@@ -54,7 +59,8 @@ impl MyVerify {
 
             let res = do_verify(
                 &self.3,
-                &actor.public_key,
+                &actor,
+                &key_id,
                 signature.clone(),
                 signing_string.clone(),
             )
@@ -92,7 +98,7 @@ impl MyVerify {
         // Now we make sure we fetch an updated actor
         let actor = self.1.get_no_cache(&actor_id, &self.0).await?;
 
-        do_verify(&self.3, &actor.public_key, signature, signing_string).await?;
+        do_verify(&self.3, &actor, &key_id, signature, signing_string).await?;
 
         Ok(true)
     }
@@ -109,6 +115,13 @@ enum PublicKeyResponse {
         #[allow(dead_code)]
         public_key_pem: String,
     },
+    Multikey {
+        #[allow(dead_code)]
+        id: IriString,
+        controller: IriString,
+        #[allow(dead_code)]
+        public_key_multibase: String,
+    },
     Actor(Box<AcceptedActors>),
 }
 
@@ -116,26 +129,33 @@ impl PublicKeyResponse {
     fn actor_id(&self) -> Option<IriString> {
         match self {
             PublicKeyResponse::PublicKey { owner, .. } => Some(owner.clone()),
+            PublicKeyResponse::Multikey { controller, .. } => Some(controller.clone()),
             PublicKeyResponse::Actor(actor) => actor.id_unchecked().cloned(),
         }
     }
 }
 
-#[tracing::instrument("Verify signature")]
+#[tracing::instrument("Verify signature", skip(actor))]
 async fn do_verify(
     spawner: &Spawner,
-    public_key: &str,
+    actor: &Actor,
+    key_id: &str,
     signature: String,
     signing_string: String,
 ) -> Result<(), Error> {
-    let public_key = RsaPublicKey::from_public_key_pem(public_key.trim())?;
-    let public_key_der = public_key
-        .to_pkcs1_der()
-        .map_err(|_| ErrorKind::DerEncode)?;
-    let public_key = ring::signature::UnparsedPublicKey::new(
-        &ring::signature::RSA_PKCS1_2048_8192_SHA256,
-        public_key_der,
-    );
+    let public_key = if let Some(public_key) = actor.ed25519_key(key_id) {
+        UnparsedPublicKey::new(&ED25519 as &dyn VerificationAlgorithm, public_key.to_vec())
+    } else {
+        let public_key = RsaPublicKey::from_public_key_pem(actor.public_key.trim())?;
+        let public_key_der = public_key
+            .to_pkcs1_der()
+            .map_err(|_| ErrorKind::DerEncode)?;
+
+        UnparsedPublicKey::new(
+            &RSA_PKCS1_2048_8192_SHA256 as &dyn VerificationAlgorithm,
+            public_key_der.into_vec(),
+        )
+    };
 
     let span = tracing::Span::current();
     spawner
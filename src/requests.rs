@@ -4,19 +4,22 @@ use crate::{
     spawner::Spawner,
 };
 use activitystreams::iri_string::types::IriString;
-use actix_web::http::header::Date;
+use actix_web::http::header::{Date, HttpDate};
 use base64::{engine::general_purpose::STANDARD, Engine};
 use dashmap::DashMap;
 use http_signature_normalization_reqwest::{digest::ring::Sha256, prelude::*};
-use reqwest_middleware::ClientWithMiddleware;
+use rand::Rng;
+use reqwest_middleware::{ClientWithMiddleware, RequestBuilder};
 use ring::{
+    digest::{digest, SHA256},
     rand::SystemRandom,
-    signature::{RsaKeyPair, RSA_PKCS1_SHA256},
+    signature::{Ed25519KeyPair, RsaKeyPair, RSA_PKCS1_SHA256},
 };
 use rsa::{pkcs1::EncodeRsaPrivateKey, RsaPrivateKey};
 use std::{
-    sync::Arc,
-    time::{Duration, SystemTime},
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 const ONE_SECOND: u64 = 1;
@@ -37,19 +40,61 @@ pub(crate) enum BreakerStrategy {
 #[derive(Clone)]
 pub(crate) struct Breakers {
     inner: Arc<DashMap<String, Breaker>>,
+    policy: BreakerPolicy,
+    overrides: Arc<DashMap<String, BreakerPolicy>>,
 }
 
 impl std::fmt::Debug for Breakers {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Breakers").finish()
+        f.debug_struct("Breakers")
+            .field("policy", &self.policy)
+            .finish()
     }
 }
 
 impl Breakers {
+    pub(crate) fn restore(breakers: HashMap<String, Breaker>, policy: BreakerPolicy) -> Self {
+        Breakers {
+            inner: Arc::new(breakers.into_iter().collect()),
+            policy,
+            overrides: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> HashMap<String, Breaker> {
+        self.inner
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    pub(crate) fn remove(&self, authority: &str) -> bool {
+        self.inner.remove(authority).is_some()
+    }
+
+    pub(crate) fn policy(&self) -> BreakerPolicy {
+        self.policy
+    }
+
+    pub(crate) fn set_override(&self, authority: &str, policy: Option<BreakerPolicy>) {
+        if let Some(policy) = policy {
+            self.overrides.insert(authority.to_owned(), policy);
+        } else {
+            self.overrides.remove(authority);
+        }
+    }
+
+    fn policy_for(&self, authority: &str) -> BreakerPolicy {
+        self.overrides
+            .get(authority)
+            .map(|policy| *policy)
+            .unwrap_or(self.policy)
+    }
+
     pub(crate) fn should_try(&self, url: &IriString) -> bool {
         if let Some(authority) = url.authority_str() {
             if let Some(breaker) = self.inner.get(authority) {
-                breaker.should_try()
+                breaker.should_try(&self.policy_for(authority))
             } else {
                 true
             }
@@ -63,7 +108,7 @@ impl Breakers {
             let should_write = {
                 if let Some(mut breaker) = self.inner.get_mut(authority) {
                     breaker.fail();
-                    if !breaker.should_try() {
+                    if !breaker.should_try(&self.policy_for(authority)) {
                         tracing::warn!("Failed breaker for {authority}");
                     }
                     false
@@ -100,26 +145,50 @@ impl Breakers {
 
 impl Default for Breakers {
     fn default() -> Self {
-        Breakers {
-            inner: Arc::new(DashMap::new()),
+        Breakers::restore(HashMap::new(), BreakerPolicy::default())
+    }
+}
+
+/// How many failures trip a breaker, and how long it stays open afterwards
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BreakerPolicy {
+    pub(crate) failure_threshold: usize,
+    pub(crate) failure_wait: Duration,
+    pub(crate) max_failure_wait: Duration,
+}
+
+impl BreakerPolicy {
+    // Each failed retry past the threshold doubles the wait, up to max_failure_wait
+    fn wait(&self, failures: usize) -> Duration {
+        let doublings = failures.saturating_sub(self.failure_threshold).min(31) as u32;
+
+        self.failure_wait
+            .saturating_mul(2u32.pow(doublings))
+            .min(self.max_failure_wait.max(self.failure_wait))
+    }
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        BreakerPolicy {
+            failure_threshold: 10,
+            failure_wait: Duration::from_secs(ONE_DAY),
+            max_failure_wait: Duration::from_secs(ONE_DAY),
         }
     }
 }
 
-#[derive(Debug)]
-struct Breaker {
-    failures: usize,
-    last_attempt: SystemTime,
-    last_success: SystemTime,
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) struct Breaker {
+    pub(crate) failures: usize,
+    pub(crate) last_attempt: SystemTime,
+    pub(crate) last_success: SystemTime,
 }
 
 impl Breaker {
-    const FAILURE_WAIT: Duration = Duration::from_secs(ONE_DAY);
-    const FAILURE_THRESHOLD: usize = 10;
-
-    fn should_try(&self) -> bool {
-        self.failures < Self::FAILURE_THRESHOLD
-            || self.last_attempt + Self::FAILURE_WAIT < SystemTime::now()
+    fn should_try(&self, policy: &BreakerPolicy) -> bool {
+        self.failures < policy.failure_threshold
+            || self.last_attempt + policy.wait(self.failures) < SystemTime::now()
     }
 
     fn fail(&mut self) {
@@ -146,21 +215,206 @@ impl Default for Breaker {
     }
 }
 
+/// How much a single authority may be sent at once, 0 leaves a limit off
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DeliveryPolicy {
+    pub(crate) concurrency: usize,
+    /// Deliveries per second, with up to a second's worth allowed at once
+    pub(crate) rate: u32,
+}
+
+/// Per-authority delivery limits. Deliveries over a limit are deferred instead of sent
+#[derive(Clone)]
+pub(crate) struct DeliveryLimits {
+    inner: Arc<DashMap<String, Destination>>,
+    policy: DeliveryPolicy,
+    overrides: Arc<DashMap<String, DeliveryPolicy>>,
+}
+
+#[derive(Debug)]
+struct Destination {
+    in_flight: usize,
+    tokens: f64,
+    updated: Instant,
+    /// Set from the Retry-After of a 429 or 503
+    retry_after: Option<Instant>,
+}
+
+/// A delivery slot for an authority, given back when dropped
+#[derive(Debug)]
+pub(crate) struct DeliveryPermit {
+    limits: DeliveryLimits,
+    authority: String,
+}
+
+impl std::fmt::Debug for DeliveryLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeliveryLimits")
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl DeliveryLimits {
+    pub(crate) fn new(policy: DeliveryPolicy) -> Self {
+        DeliveryLimits {
+            inner: Arc::new(DashMap::new()),
+            policy,
+            overrides: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub(crate) fn policy(&self) -> DeliveryPolicy {
+        self.policy
+    }
+
+    pub(crate) fn set_override(&self, authority: &str, policy: Option<DeliveryPolicy>) {
+        if let Some(policy) = policy {
+            self.overrides.insert(authority.to_owned(), policy);
+        } else {
+            self.overrides.remove(authority);
+        }
+    }
+
+    fn policy_for(&self, authority: &str) -> DeliveryPolicy {
+        self.overrides
+            .get(authority)
+            .map(|policy| *policy)
+            .unwrap_or(self.policy)
+    }
+
+    /// Takes a delivery slot for the authority, or returns how long to wait before trying again
+    pub(crate) fn acquire(
+        &self,
+        authority: &str,
+        now: Instant,
+    ) -> Result<DeliveryPermit, Duration> {
+        let policy = self.policy_for(authority);
+        let capacity = f64::from(policy.rate.max(1));
+
+        let mut destination = self
+            .inner
+            .entry(authority.to_owned())
+            .or_insert(Destination {
+                in_flight: 0,
+                tokens: capacity,
+                updated: now,
+                retry_after: None,
+            });
+
+        if let Some(retry_after) = destination.retry_after {
+            if retry_after > now {
+                return Err(retry_after - now);
+            }
+
+            destination.retry_after = None;
+        }
+
+        if policy.concurrency > 0 && destination.in_flight >= policy.concurrency {
+            // there's no telling when a slot frees up, so spread the retries out
+            let jitter = rand::thread_rng().gen_range(0..1000);
+            return Err(Duration::from_millis(1000 + jitter));
+        }
+
+        if policy.rate > 0 {
+            let elapsed = now
+                .saturating_duration_since(destination.updated)
+                .as_secs_f64();
+            destination.tokens =
+                (destination.tokens + elapsed * f64::from(policy.rate)).min(capacity);
+            destination.updated = now;
+
+            if destination.tokens < 1.0 {
+                let wait = (1.0 - destination.tokens) / f64::from(policy.rate);
+                return Err(Duration::from_secs_f64(wait));
+            }
+
+            destination.tokens -= 1.0;
+        }
+
+        destination.in_flight += 1;
+
+        Ok(DeliveryPermit {
+            limits: self.clone(),
+            authority: authority.to_owned(),
+        })
+    }
+
+    /// Holds off every delivery to the authority until the wait is over
+    pub(crate) fn retry_after(&self, authority: &str, until: Instant) {
+        if let Some(mut destination) = self.inner.get_mut(authority) {
+            destination.retry_after = Some(until.max(destination.retry_after.unwrap_or(until)));
+        }
+    }
+}
+
+impl Drop for DeliveryPermit {
+    fn drop(&mut self) {
+        if let Some(mut destination) = self.limits.inner.get_mut(&self.authority) {
+            destination.in_flight = destination.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn retry_after(res: &reqwest::Response) -> Option<Duration> {
+    let value = res.headers().get("Retry-After")?.to_str().ok()?;
+
+    let wait = if let Ok(seconds) = value.trim().parse::<u64>() {
+        Duration::from_secs(seconds)
+    } else {
+        let date: HttpDate = value.parse().ok()?;
+        SystemTime::from(date)
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+    };
+
+    // a misbehaving server shouldn't be able to park deliveries forever
+    Some(wait.min(Duration::from_secs(ONE_HOUR)))
+}
+
+/// The signature format an authority accepted, learned by double-knocking
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SignatureFormat {
+    Cavage,
+    MessageSignature,
+}
+
+#[derive(Clone)]
+struct RsaKey {
+    id: String,
+    key_pair: Arc<RsaKeyPair>,
+}
+
+struct RsaKeys {
+    current: RsaKey,
+    /// The key from before the last rotation, and when to stop using it
+    retired: Option<(RsaKey, SystemTime)>,
+}
+
+#[derive(Clone)]
+struct Ed25519Signer {
+    key_id: String,
+    key_pair: Arc<Ed25519KeyPair>,
+}
+
 #[derive(Clone)]
 pub(crate) struct Requests {
     client: ClientWithMiddleware,
-    key_id: String,
-    private_key: Arc<RsaKeyPair>,
+    rsa_keys: Arc<RwLock<RsaKeys>>,
+    ed25519: Option<Ed25519Signer>,
+    signature_formats: Arc<DashMap<String, SignatureFormat>>,
     rng: SystemRandom,
     config: Config<Spawner>,
     breakers: Breakers,
+    delivery_limits: DeliveryLimits,
     last_online: Arc<LastOnline>,
 }
 
 impl std::fmt::Debug for Requests {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Requests")
-            .field("key_id", &self.key_id)
+            .field("key_id", &self.key_id())
             .field("config", &self.config)
             .field("breakers", &self.breakers)
             .finish()
@@ -173,24 +427,65 @@ impl Requests {
         key_id: String,
         private_key: RsaPrivateKey,
         breakers: Breakers,
+        delivery_limits: DeliveryLimits,
         last_online: Arc<LastOnline>,
         spawner: Spawner,
         client: ClientWithMiddleware,
     ) -> Self {
-        let private_key_der = private_key.to_pkcs1_der().expect("Can encode der");
-        let private_key = ring::signature::RsaKeyPair::from_der(private_key_der.as_bytes())
-            .expect("Key is valid");
         Requests {
             client,
-            key_id,
-            private_key: Arc::new(private_key),
+            rsa_keys: Arc::new(RwLock::new(RsaKeys {
+                current: RsaKey::new(key_id, &private_key),
+                retired: None,
+            })),
+            ed25519: None,
+            signature_formats: Arc::new(DashMap::new()),
             rng: SystemRandom::new(),
             config: Config::new_with_spawner(spawner).mastodon_compat(),
             breakers,
+            delivery_limits,
             last_online,
         }
     }
 
+    /// Sign with RFC 9421 and this key first, falling back to draft-cavage where it's rejected
+    pub(crate) fn with_ed25519_key(mut self, key_id: String, key_pair: Ed25519KeyPair) -> Self {
+        self.ed25519 = Some(Ed25519Signer {
+            key_id,
+            key_pair: Arc::new(key_pair),
+        });
+        self
+    }
+
+    /// Keep offering a retired key to servers that reject the current one, until expires_at
+    pub(crate) fn with_retired_key(
+        self,
+        key_id: String,
+        private_key: &RsaPrivateKey,
+        expires_at: SystemTime,
+    ) -> Self {
+        self.rsa_keys.write().unwrap().retired =
+            Some((RsaKey::new(key_id, private_key), expires_at));
+        self
+    }
+
+    /// Signs with the new key from now on, retiring the current one until expires_at
+    pub(crate) fn rotate_key(
+        &self,
+        key_id: String,
+        private_key: &RsaPrivateKey,
+        expires_at: SystemTime,
+    ) {
+        let mut keys = self.rsa_keys.write().unwrap();
+
+        let retired = std::mem::replace(&mut keys.current, RsaKey::new(key_id, private_key));
+        keys.retired = Some((retired, expires_at));
+    }
+
+    pub(crate) fn key_id(&self) -> String {
+        self.rsa_keys.read().unwrap().current.id.clone()
+    }
+
     pub(crate) fn spawner(mut self, spawner: Spawner) -> Self {
         self.config = self.config.set_spawner(spawner);
         self
@@ -200,6 +495,10 @@ impl Requests {
         self.breakers.succeed(iri);
     }
 
+    pub(crate) fn fail_breaker(&self, iri: &IriString) {
+        self.breakers.fail(iri);
+    }
+
     async fn check_response(
         &self,
         parsed_url: &IriString,
@@ -327,7 +626,36 @@ impl Requests {
             return Err(ErrorKind::Breaker.into());
         }
 
-        let signer = self.signer();
+        let request = self.client.get(url.as_str()).header("Accept", accept);
+
+        if let Some(res) = self
+            .try_message_signature(url, request, "GET", None)
+            .await?
+        {
+            return self.check_response(url, strategy, res).await;
+        }
+
+        let (signer, retired) = self.signers();
+        let mut res = self.signed_get(url, accept, signer).await?;
+
+        if let Some(retired) = retired {
+            let (key_error, checked) = rejects_key(res).await;
+            res = checked;
+
+            if key_error {
+                res = self.signed_get(url, accept, retired).await?;
+            }
+        }
+
+        self.check_response(url, strategy, res).await
+    }
+
+    async fn signed_get(
+        &self,
+        url: &IriString,
+        accept: &str,
+        signer: Signer,
+    ) -> Result<Result<reqwest::Response, reqwest_middleware::Error>, Error> {
         let span = tracing::Span::current();
 
         let request = self
@@ -335,17 +663,13 @@ impl Requests {
             .get(url.as_str())
             .header("Accept", accept)
             .header("Date", Date(SystemTime::now().into()).to_string())
-            .signature(&self.config, self.key_id.clone(), move |signing_string| {
+            .signature(&self.config, signer.key_id.clone(), move |signing_string| {
                 span.record("signing_string", signing_string);
                 span.in_scope(|| signer.sign(signing_string))
             })
             .await?;
 
-        let res = self.client.execute(request).await;
-
-        let res = self.check_response(url, strategy, res).await?;
-
-        Ok(res)
+        Ok(self.client.execute(request).await)
     }
 
     #[tracing::instrument(
@@ -388,10 +712,81 @@ impl Requests {
             return Err(ErrorKind::Breaker.into());
         }
 
-        let signer = self.signer();
-        let span = tracing::Span::current();
+        let authority = inbox.authority_str().unwrap_or_default();
+        let _permit = self
+            .delivery_limits
+            .acquire(authority, Instant::now())
+            .map_err(ErrorKind::Deferred)?;
+
         let item_string = serde_json::to_string(item)?;
 
+        let request = self
+            .client
+            .post(inbox.as_str())
+            .header("Accept", accept)
+            .header("Content-Type", content_type);
+
+        if let Some(res) = self
+            .try_message_signature(inbox, request, "POST", Some(&item_string))
+            .await?
+        {
+            return self.check_delivery(inbox, strategy, res).await;
+        }
+
+        let (signer, retired) = self.signers();
+        let mut res = self
+            .signed_post(inbox, item_string.clone(), content_type, accept, signer)
+            .await?;
+
+        if let Some(retired) = retired {
+            let (key_error, checked) = rejects_key(res).await;
+            res = checked;
+
+            if key_error {
+                res = self
+                    .signed_post(inbox, item_string, content_type, accept, retired)
+                    .await?;
+            }
+        }
+
+        self.check_delivery(inbox, strategy, res).await
+    }
+
+    // A server that's asked to be left alone for a while defers every delivery to it, and only
+    // counts as failing once it keeps asking
+    async fn check_delivery(
+        &self,
+        inbox: &IriString,
+        strategy: BreakerStrategy,
+        res: Result<reqwest::Response, reqwest_middleware::Error>,
+    ) -> Result<reqwest::Response, Error> {
+        if let Ok(response) = &res {
+            if matches!(response.status().as_u16(), 429 | 503) {
+                if let Some(wait) = retry_after(response) {
+                    let authority = inbox.authority_str().unwrap_or_default();
+                    tracing::info!("{authority} asked to retry in {}s", wait.as_secs());
+
+                    self.delivery_limits
+                        .retry_after(authority, Instant::now() + wait);
+
+                    return Err(ErrorKind::RetryAfter(wait).into());
+                }
+            }
+        }
+
+        self.check_response(inbox, strategy, res).await
+    }
+
+    async fn signed_post(
+        &self,
+        inbox: &IriString,
+        item_string: String,
+        content_type: &str,
+        accept: &str,
+        signer: Signer,
+    ) -> Result<Result<reqwest::Response, reqwest_middleware::Error>, Error> {
+        let span = tracing::Span::current();
+
         let request = self
             .client
             .post(inbox.as_str())
@@ -400,7 +795,7 @@ impl Requests {
             .header("Date", Date(SystemTime::now().into()).to_string())
             .signature_with_digest(
                 self.config.clone(),
-                self.key_id.clone(),
+                signer.key_id.clone(),
                 Sha256::new(),
                 item_string,
                 move |signing_string| {
@@ -410,22 +805,150 @@ impl Requests {
             )
             .await?;
 
-        let res = self.client.execute(request).await;
+        Ok(self.client.execute(request).await)
+    }
 
-        let res = self.check_response(inbox, strategy, res).await?;
+    // Sends the request with an RFC 9421 signature when the relay has an Ed25519 key. Returns
+    // None when the authority only takes draft-cavage signatures, so the caller knocks again
+    async fn try_message_signature(
+        &self,
+        url: &IriString,
+        request: RequestBuilder,
+        method: &str,
+        body: Option<&str>,
+    ) -> Result<Option<Result<reqwest::Response, reqwest_middleware::Error>>, Error> {
+        let Some(signer) = &self.ed25519 else {
+            return Ok(None);
+        };
 
-        Ok(res)
+        let authority = url.authority_str().ok_or(ErrorKind::MissingDomain)?;
+
+        if self
+            .signature_formats
+            .get(authority)
+            .is_some_and(|format| *format == SignatureFormat::Cavage)
+        {
+            return Ok(None);
+        }
+
+        let content_digest = body.map(content_digest);
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0);
+
+        let (signature_input, signature) = message_signature(
+            &signer.key_pair,
+            &signer.key_id,
+            method,
+            url.as_str(),
+            content_digest.as_deref(),
+            created,
+        );
+
+        let mut request = request
+            .header("Date", Date(SystemTime::now().into()).to_string())
+            .header("Signature-Input", signature_input)
+            .header("Signature", signature);
+
+        if let (Some(content_digest), Some(body)) = (content_digest, body) {
+            request = request
+                .header("Content-Digest", content_digest)
+                .body(body.to_owned());
+        }
+
+        let res = request.send().await;
+
+        match &res {
+            Ok(res) if matches!(res.status().as_u16(), 400 | 401 | 403) => {
+                tracing::debug!("{authority} rejected an RFC 9421 signature, using draft-cavage");
+                self.signature_formats
+                    .insert(authority.to_owned(), SignatureFormat::Cavage);
+                return Ok(None);
+            }
+            Ok(res) if res.status().is_success() => {
+                self.signature_formats
+                    .insert(authority.to_owned(), SignatureFormat::MessageSignature);
+            }
+            _ => (),
+        }
+
+        Ok(Some(res))
     }
 
-    fn signer(&self) -> Signer {
-        Signer {
-            private_key: self.private_key.clone(),
+    // The current key, and the retired key while it's still offered
+    fn signers(&self) -> (Signer, Option<Signer>) {
+        let keys = self.rsa_keys.read().unwrap();
+
+        let signer = |key: &RsaKey| Signer {
+            key_id: key.id.clone(),
+            private_key: key.key_pair.clone(),
             rng: self.rng.clone(),
+        };
+
+        let retired = keys
+            .retired
+            .as_ref()
+            .filter(|(_, expires_at)| *expires_at > SystemTime::now())
+            .map(|(key, _)| signer(key));
+
+        (signer(&keys.current), retired)
+    }
+}
+
+impl RsaKey {
+    fn new(id: String, private_key: &RsaPrivateKey) -> Self {
+        let private_key_der = private_key.to_pkcs1_der().expect("Can encode der");
+        let key_pair = RsaKeyPair::from_der(private_key_der.as_bytes()).expect("Key is valid");
+
+        RsaKey {
+            id,
+            key_pair: Arc::new(key_pair),
         }
     }
 }
 
+// A server that cached the relay's key from before a rotation rejects the new one. Other 401s
+// and 403s aren't about the key, so the body is read to tell them apart, and the response is
+// rebuilt from it for the caller
+async fn rejects_key(
+    res: Result<reqwest::Response, reqwest_middleware::Error>,
+) -> (bool, Result<reqwest::Response, reqwest_middleware::Error>) {
+    let response = match res {
+        Ok(response) if matches!(response.status().as_u16(), 401 | 403) => response,
+        res => return (false, res),
+    };
+
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = match response.bytes().await {
+        Ok(body) => body,
+        Err(e) => return (false, Err(e.into())),
+    };
+
+    let key_error = is_key_error(&String::from_utf8_lossy(&body))
+        || headers
+            .get("WWW-Authenticate")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(is_key_error);
+
+    let mut rebuilt = http::Response::new(body);
+    *rebuilt.status_mut() = status;
+    *rebuilt.headers_mut() = headers;
+
+    (key_error, Ok(rebuilt.into()))
+}
+
+fn is_key_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+
+    ["signature", "verification", "key"]
+        .iter()
+        .any(|word| message.contains(word))
+}
+
 struct Signer {
+    key_id: String,
     private_key: Arc<RsaKeyPair>,
     rng: SystemRandom,
 }
@@ -446,3 +969,144 @@ impl Signer {
         Ok(STANDARD.encode(&signature))
     }
 }
+
+/// The Content-Digest header (RFC 9530) for a body
+pub(crate) fn content_digest(body: &str) -> String {
+    format!(
+        "sha-256=:{}:",
+        STANDARD.encode(digest(&SHA256, body.as_bytes()))
+    )
+}
+
+/// Signs a request as an RFC 9421 HTTP Message Signature, returning the Signature-Input and
+/// Signature headers
+pub(crate) fn message_signature(
+    key_pair: &Ed25519KeyPair,
+    key_id: &str,
+    method: &str,
+    target_uri: &str,
+    content_digest: Option<&str>,
+    created: u64,
+) -> (String, String) {
+    let mut components = vec![("@method", method), ("@target-uri", target_uri)];
+
+    if let Some(content_digest) = content_digest {
+        components.push(("content-digest", content_digest));
+    }
+
+    let names = components
+        .iter()
+        .map(|(name, _)| format!("\"{name}\""))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let params = format!("({names});created={created};keyid=\"{key_id}\";alg=\"ed25519\"");
+
+    let mut signature_base = String::new();
+    for (name, value) in components {
+        signature_base.push_str(&format!("\"{name}\": {value}\n"));
+    }
+    signature_base.push_str(&format!("\"@signature-params\": {params}"));
+
+    let signature = key_pair.sign(signature_base.as_bytes());
+
+    (
+        format!("sig1={params}"),
+        format!("sig1=:{}:", STANDARD.encode(signature)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_key_error, Breaker, BreakerPolicy, Breakers, DeliveryLimits, DeliveryPolicy};
+    use activitystreams::iri_string::types::IriString;
+    use std::time::{Duration, Instant, SystemTime};
+
+    const POLICY: BreakerPolicy = BreakerPolicy {
+        failure_threshold: 3,
+        failure_wait: Duration::from_secs(60),
+        max_failure_wait: Duration::from_secs(300),
+    };
+
+    #[test]
+    fn only_signature_and_key_errors_retry_with_the_retired_key() {
+        assert!(is_key_error(
+            r#"{"error":"Verification failed for relay@relay.example using rsa-sha256"}"#
+        ));
+        assert!(is_key_error(
+            r#"Signature keyId="https://relay.example/actor#main-key""#
+        ));
+        assert!(!is_key_error(r#"{"error":"This action is not allowed"}"#));
+        assert!(!is_key_error("Forbidden"));
+    }
+
+    #[test]
+    fn breaker_wait_doubles_up_to_max() {
+        assert_eq!(POLICY.wait(3), Duration::from_secs(60));
+        assert_eq!(POLICY.wait(4), Duration::from_secs(120));
+        assert_eq!(POLICY.wait(5), Duration::from_secs(240));
+        assert_eq!(POLICY.wait(6), Duration::from_secs(300));
+        assert_eq!(POLICY.wait(usize::MAX), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn override_reopens_breaker_sooner() {
+        let url: IriString = "https://example.com/inbox".parse().unwrap();
+        let breaker = Breaker {
+            failures: 3,
+            last_attempt: SystemTime::now() - Duration::from_secs(30),
+            last_success: SystemTime::now() - Duration::from_secs(3600),
+        };
+
+        let breakers = Breakers::restore([("example.com".to_string(), breaker)].into(), POLICY);
+        assert!(!breakers.should_try(&url));
+
+        let aggressive = BreakerPolicy {
+            failure_wait: Duration::from_secs(10),
+            ..POLICY
+        };
+        breakers.set_override("example.com", Some(aggressive));
+        assert!(breakers.should_try(&url));
+
+        breakers.set_override("example.com", None);
+        assert!(!breakers.should_try(&url));
+    }
+
+    #[test]
+    fn deliveries_are_limited_per_authority() {
+        let limits = DeliveryLimits::new(DeliveryPolicy {
+            concurrency: 2,
+            rate: 3,
+        });
+        let now = Instant::now();
+
+        let first = limits.acquire("example.com", now).unwrap();
+        let _second = limits.acquire("example.com", now).unwrap();
+        assert!(limits.acquire("example.com", now).is_err());
+        assert!(limits.acquire("other.example", now).is_ok());
+
+        // a finished delivery frees its slot, but the rate still applies
+        drop(first);
+        drop(limits.acquire("example.com", now).unwrap());
+        let wait = limits.acquire("example.com", now).unwrap_err();
+        assert!(wait <= Duration::from_millis(334));
+    }
+
+    #[test]
+    fn retry_after_defers_every_delivery() {
+        let limits = DeliveryLimits::new(DeliveryPolicy {
+            concurrency: 0,
+            rate: 0,
+        });
+        let now = Instant::now();
+
+        drop(limits.acquire("example.com", now).unwrap());
+        limits.retry_after("example.com", now + Duration::from_secs(30));
+
+        let wait = limits.acquire("example.com", now).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(30));
+
+        assert!(limits
+            .acquire("example.com", now + Duration::from_secs(30))
+            .is_ok());
+    }
+}
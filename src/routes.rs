@@ -1,18 +1,22 @@
 mod actor;
+mod collection;
 mod healthz;
 mod inbox;
 mod index;
 mod media;
 mod nodeinfo;
+mod object;
 mod statics;
 
 pub(crate) use self::{
-    actor::route as actor,
+    actor::{document as actor_document, route as actor},
+    collection::{followers, following, outbox, tag},
     healthz::route as healthz,
     inbox::route as inbox,
     index::route as index,
     media::route as media,
     nodeinfo::{route as nodeinfo, well_known as nodeinfo_meta},
+    object::route as object,
     statics::route as statics,
 };
 
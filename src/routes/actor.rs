@@ -3,6 +3,7 @@ use crate::{
     config::{Config, UrlKind},
     data::State,
     error::Error,
+    integrity,
     routes::ok,
 };
 use activitystreams::{
@@ -20,14 +21,19 @@ pub(crate) async fn route(
     state: web::Data<State>,
     config: web::Data<Config>,
 ) -> Result<impl Responder, Error> {
+    Ok(ok(document(&config, &state)?))
+}
+
+/// The relay's actor, also sent to subscribers in an Update when its key changes
+pub(crate) fn document(config: &Config, state: &State) -> Result<serde_json::Value, Error> {
     let mut application = Ext1::new(
         ApActor::new(config.generate_url(UrlKind::Inbox), Application::new()),
         PublicKey {
             public_key: PublicKeyInner {
-                id: config.generate_url(UrlKind::MainKey),
+                id: state.requests.key_id().parse()?,
                 owner: config.generate_url(UrlKind::Actor),
                 public_key_pem: state
-                    .public_key
+                    .public_key()
                     .to_public_key_pem(rsa::pkcs8::LineEnding::default())?,
             },
         },
@@ -48,5 +54,36 @@ pub(crate) async fn route(
             ..Default::default()
         });
 
-    Ok(ok(application))
+    let mut application = serde_json::to_value(application)?;
+
+    // servers that cached the retired key can still look it up until it expires
+    if let Some((key_id, public_key)) = state.retired_public_key() {
+        let retired = PublicKeyInner {
+            id: key_id.parse()?,
+            owner: config.generate_url(UrlKind::Actor),
+            public_key_pem: public_key.to_public_key_pem(rsa::pkcs8::LineEnding::default())?,
+        };
+
+        let current = application["publicKey"].take();
+        application["publicKey"] = serde_json::json!([current, retired]);
+    }
+
+    let Some(ed25519_public_key) = &state.ed25519_public_key else {
+        return Ok(application);
+    };
+
+    // FEP-521a, so peers can check the relay's RFC 9421 signatures
+    application["@context"] = serde_json::json!([
+        context(),
+        security(),
+        "https://w3id.org/security/multikey/v1"
+    ]);
+    application["assertionMethod"] = serde_json::json!([{
+        "id": config.generate_url(UrlKind::Ed25519Key),
+        "type": "Multikey",
+        "controller": config.generate_url(UrlKind::Actor),
+        "publicKeyMultibase": integrity::encode_multikey(ed25519_public_key),
+    }]);
+
+    Ok(application)
 }
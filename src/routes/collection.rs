@@ -0,0 +1,168 @@
+use crate::{
+    apub::normalize_tag,
+    config::{Config, UrlKind},
+    data::State,
+    error::{Error, ErrorKind},
+    routes::ok,
+};
+use activitystreams::{
+    base::AnyBase,
+    collection::{OrderedCollection, OrderedCollectionPage},
+    context,
+    iri_string::types::IriString,
+    prelude::*,
+};
+use actix_web::{web, HttpResponse};
+
+const PAGE_SIZE: usize = 20;
+
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct PageQuery {
+    page: Option<usize>,
+}
+
+#[tracing::instrument(name = "Followers", skip(config, state))]
+pub(crate) async fn followers(
+    state: web::Data<State>,
+    config: web::Data<Config>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse, Error> {
+    if config.hide_followers() {
+        return Ok(hidden(config.generate_url(UrlKind::Followers)));
+    }
+
+    let items = state
+        .db
+        .connected_ids()
+        .await?
+        .into_iter()
+        .map(AnyBase::from)
+        .collect();
+
+    paginate(
+        &config,
+        items,
+        query.page,
+        UrlKind::Followers,
+        UrlKind::FollowersPage,
+    )
+}
+
+#[tracing::instrument(name = "Following", skip(config, state))]
+pub(crate) async fn following(
+    state: web::Data<State>,
+    config: web::Data<Config>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse, Error> {
+    if config.hide_following() {
+        return Ok(hidden(config.generate_url(UrlKind::Following)));
+    }
+
+    let items = state
+        .db
+        .following_ids()
+        .await?
+        .into_iter()
+        .map(AnyBase::from)
+        .collect();
+
+    paginate(
+        &config,
+        items,
+        query.page,
+        UrlKind::Following,
+        UrlKind::FollowingPage,
+    )
+}
+
+#[tracing::instrument(name = "Outbox", skip(config, state))]
+pub(crate) async fn outbox(
+    state: web::Data<State>,
+    config: web::Data<Config>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse, Error> {
+    if config.hide_outbox() {
+        return Ok(hidden(config.generate_url(UrlKind::Outbox)));
+    }
+
+    let items = state
+        .db
+        .outbox()
+        .await?
+        .into_iter()
+        .map(AnyBase::from_arbitrary_json)
+        .collect::<Result<_, _>>()?;
+
+    paginate(
+        &config,
+        items,
+        query.page,
+        UrlKind::Outbox,
+        UrlKind::OutboxPage,
+    )
+}
+
+// Tag collections only exist to be followed, the relay doesn't keep their posts
+#[tracing::instrument(name = "Tag", skip(config))]
+pub(crate) async fn tag(
+    config: web::Data<Config>,
+    tag: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let tag = normalize_tag(&tag).ok_or(ErrorKind::NotFound)?;
+
+    Ok(hidden(config.generate_url(UrlKind::Tag(tag))))
+}
+
+// Hidden collections still resolve, but don't reveal their size or contents
+fn hidden(id: IriString) -> HttpResponse {
+    let mut collection = OrderedCollection::new();
+    collection.set_context(context()).set_id(id);
+
+    ok(collection)
+}
+
+fn paginate(
+    config: &Config,
+    items: Vec<AnyBase>,
+    page: Option<usize>,
+    collection_kind: UrlKind,
+    page_kind: fn(usize) -> UrlKind,
+) -> Result<HttpResponse, Error> {
+    let last_page = items.len().div_ceil(PAGE_SIZE).max(1);
+
+    let Some(page) = page else {
+        let mut collection = OrderedCollection::new();
+        collection
+            .set_context(context())
+            .set_id(config.generate_url(collection_kind))
+            .set_total_items(items.len() as u64)
+            .set_first(config.generate_url(page_kind(1)))
+            .set_last(config.generate_url(page_kind(last_page)));
+
+        return Ok(ok(collection));
+    };
+
+    let page = page.max(1);
+
+    let mut collection_page = OrderedCollectionPage::new();
+    collection_page
+        .set_context(context())
+        .set_id(config.generate_url(page_kind(page)))
+        .set_part_of(config.generate_url(collection_kind))
+        .set_many_ordered_items(
+            items
+                .into_iter()
+                .skip((page - 1).saturating_mul(PAGE_SIZE))
+                .take(PAGE_SIZE)
+                .collect::<Vec<_>>(),
+        );
+
+    if page > 1 {
+        collection_page.set_prev(config.generate_url(page_kind(page - 1)));
+    }
+    if page < last_page {
+        collection_page.set_next(config.generate_url(page_kind(page + 1)));
+    }
+
+    Ok(ok(collection_page))
+}
@@ -1,11 +1,15 @@
 use crate::{
-    apub::{AcceptedActivities, AcceptedUndoObjects, UndoTypes, ValidTypes},
+    apub::{
+        is_authored_by, normalize_tag, AcceptedActivities, AcceptedUndoObjects, UndoTypes,
+        ValidTypes,
+    },
     config::{Config, UrlKind},
     data::{ActorCache, State},
-    db::Actor,
+    db::{Actor, PendingFollow},
     error::{Error, ErrorKind},
-    jobs::apub::{Announce, Follow, Forward, Reject, Undo},
+    jobs::apub::{Announce, Follow, Forward, Move, Reject, Undo},
     jobs::JobServer,
+    middleware::MessageSignatureVerified,
     requests::Requests,
     routes::accepted,
 };
@@ -15,8 +19,9 @@ use activitystreams::{
 };
 use actix_web::{web, HttpResponse};
 use http_signature_normalization_actix::prelude::{DigestVerified, SignatureVerified};
+use time::OffsetDateTime;
 
-#[tracing::instrument(name = "Inbox", skip_all, fields(id = tracing::field::debug(&input.id_unchecked()), kind = tracing::field::debug(&input.kind())))]
+#[tracing::instrument(name = "Inbox", skip_all, fields(id = tracing::field::debug(&raw.get("id")), kind = tracing::field::debug(&raw.get("type"))))]
 #[allow(clippy::too_many_arguments)]
 pub(crate) async fn route(
     state: web::Data<State>,
@@ -24,19 +29,35 @@ pub(crate) async fn route(
     config: web::Data<Config>,
     client: web::Data<Requests>,
     jobs: web::Data<JobServer>,
-    input: web::Json<AcceptedActivities>,
+    raw: web::Json<serde_json::Value>,
+    path_tag: Option<web::Path<String>>,
     digest_verified: Option<DigestVerified>,
     signature_verified: Option<SignatureVerified>,
+    message_signature: Option<MessageSignatureVerified>,
 ) -> Result<HttpResponse, Error> {
-    let input = input.into_inner();
+    // kept as it arrived, so signatures over it still hold when it's passed along
+    let raw = raw.into_inner();
+    let input: AcceptedActivities =
+        serde_json::from_value(raw.clone()).map_err(|_| ErrorKind::ObjectFormat)?;
+    let path_tag = match path_tag {
+        Some(tag) => Some(normalize_tag(&tag).ok_or(ErrorKind::NotFound)?),
+        None => None,
+    };
 
     let kind = input.kind().ok_or(ErrorKind::MissingKind)?;
 
-    if digest_verified.is_some() && signature_verified.is_none() && *kind == ValidTypes::Delete {
+    // RFC 9421 signatures cover a Content-Digest, which is checked as the body is read
+    let digest_verified = digest_verified.is_some() || message_signature.is_some();
+    let verified_key_id = signature_verified
+        .as_ref()
+        .map(SignatureVerified::key_id)
+        .or(message_signature
+            .as_ref()
+            .map(MessageSignatureVerified::key_id));
+
+    if digest_verified && verified_key_id.is_none() && *kind == ValidTypes::Delete {
         return Ok(accepted(serde_json::json!({})));
-    } else if config.validate_signatures()
-        && (digest_verified.is_none() || signature_verified.is_none())
-    {
+    } else if config.validate_signatures() && (!digest_verified || verified_key_id.is_none()) {
         return Err(ErrorKind::NoSignature(None).into());
     }
 
@@ -51,14 +72,12 @@ pub(crate) async fn route(
 
     let actor = actors.get(actor_id, &client).await?.into_inner();
 
-    if let Some(verified) = signature_verified {
-        if actor.public_key_id.as_str() != verified.key_id() {
+    if let Some(key_id) = verified_key_id {
+        if !actor.has_key(key_id) {
             tracing::error!("Actor signed with wrong key");
-            return Err(ErrorKind::BadActor(
-                actor.public_key_id.to_string(),
-                verified.key_id().to_owned(),
-            )
-            .into());
+            return Err(
+                ErrorKind::BadActor(actor.public_key_id.to_string(), key_id.to_owned()).into(),
+            );
         }
     } else if config.validate_signatures() {
         tracing::error!("This case should never be reachable, since I handle signature checks earlier in the flow. If you see this in a log it means I did it wrong");
@@ -67,29 +86,40 @@ pub(crate) async fn route(
 
     let is_allowed = state.db.is_allowed(actor.id.clone()).await?;
     let is_connected = state.db.is_connected(actor.id.clone()).await?;
+    // relays this relay follows send posts without subscribing to it
+    let is_upstream = state.db.upstream(actor.id.clone()).await?.is_some();
 
     if !is_allowed {
+        if *kind == ValidTypes::Follow && config.restricted_mode() && config.follow_approval() {
+            return hold_follow(&state, &config, input, actor, path_tag).await;
+        }
+
         return Err(ErrorKind::NotAllowed(actor.id.to_string()).into());
     }
 
-    if !is_connected && !valid_without_listener(&input)? {
+    if !is_connected && !is_upstream && !valid_without_listener(&input)? {
         return Err(ErrorKind::NotSubscribed(actor.id.to_string()).into());
     }
 
+    if config.verify_integrity_proofs() {
+        crate::integrity::verify(&actor, &raw)?;
+    }
+
     match kind {
-        ValidTypes::Accept => handle_accept(&config, input).await?,
-        ValidTypes::Reject => handle_reject(&config, &jobs, input, actor).await?,
+        ValidTypes::Accept => handle_accept(&state, &config, input, actor).await?,
+        ValidTypes::Reject => handle_reject(&state, &config, &jobs, input, actor).await?,
         ValidTypes::Announce | ValidTypes::Create => {
-            handle_announce(&state, &jobs, input, actor).await?
+            let is_create = *kind == ValidTypes::Create;
+            handle_announce(&state, &jobs, is_create, input, raw, actor).await?
         }
-        ValidTypes::Follow => handle_follow(&config, &jobs, input, actor).await?,
+        ValidTypes::Follow => handle_follow(&config, &jobs, input, actor, path_tag).await?,
         ValidTypes::Add | ValidTypes::Delete | ValidTypes::Remove | ValidTypes::Update => {
-            handle_forward(&jobs, input, actor).await?
+            handle_forward(&jobs, input, raw, actor).await?
         }
-        ValidTypes::Undo => handle_undo(&config, &jobs, input, actor, is_connected).await?,
-        ValidTypes::Move => {
-            tracing::warn!("Move activity received, ignoring");
+        ValidTypes::Undo => {
+            handle_undo(&config, &jobs, input, raw, actor, is_connected, path_tag).await?
         }
+        ValidTypes::Move => handle_move(&jobs, input, actor).await?,
     };
 
     Ok(accepted(serde_json::json!({})))
@@ -119,7 +149,47 @@ fn single_object(o: &OneOrMany<AnyBase>) -> Result<&AnyBase, Error> {
     o.as_one().ok_or(ErrorKind::ObjectCount).map_err(Into::into)
 }
 
-async fn handle_accept(config: &Config, input: AcceptedActivities) -> Result<(), Error> {
+// Hashtag follows either arrive at /tags/{tag}/inbox, or name the tag's collection as their object
+fn follow_tag(
+    config: &Config,
+    object: &OneOrMany<AnyBase>,
+    path_tag: Option<String>,
+) -> Option<String> {
+    if path_tag.is_some() {
+        return path_tag;
+    }
+
+    let prefix = config.generate_url(UrlKind::Tag(String::new()));
+
+    object
+        .as_single_id()?
+        .as_str()
+        .strip_prefix(prefix.as_str())
+        .and_then(normalize_tag)
+}
+
+// A follow can target the relay actor, Public, or a single hashtag
+fn check_follow_object(
+    config: &Config,
+    object: &OneOrMany<AnyBase>,
+    path_tag: Option<String>,
+) -> Result<Option<String>, Error> {
+    let tag = follow_tag(config, object, path_tag);
+    let my_id: IriString = config.generate_url(UrlKind::Actor);
+
+    if tag.is_none() && !object.is_single_id(&my_id) && !object.is_single_id(&public()) {
+        return Err(ErrorKind::WrongActor(id_string(object.as_single_id())?).into());
+    }
+
+    Ok(tag)
+}
+
+async fn handle_accept(
+    state: &State,
+    config: &Config,
+    input: AcceptedActivities,
+    actor: Actor,
+) -> Result<(), Error> {
     let base = single_object(input.object_unchecked())?.clone();
     let follow = if let Some(follow) = activity::Follow::from_any_base(base)? {
         follow
@@ -134,10 +204,21 @@ async fn handle_accept(config: &Config, input: AcceptedActivities) -> Result<(),
         return Err(ErrorKind::WrongActor(id_string(follow.actor()?.as_single_id())?).into());
     }
 
+    if let Some(follow_id) = follow.id_unchecked() {
+        if state
+            .db
+            .accept_upstream(actor.id.clone(), follow_id.clone())
+            .await?
+        {
+            tracing::info!("Upstream relay {} accepted our follow", actor.id);
+        }
+    }
+
     Ok(())
 }
 
 async fn handle_reject(
+    state: &State,
     config: &Config,
     jobs: &JobServer,
     input: AcceptedActivities,
@@ -157,17 +238,29 @@ async fn handle_reject(
         return Err(ErrorKind::WrongActor(id_string(follow.actor()?.as_single_id())?).into());
     }
 
+    // an upstream relay turning down our follow doesn't touch its own subscription
+    if let Some(upstream) = state.db.upstream(actor.id.clone()).await? {
+        if follow.id_unchecked() == Some(&upstream.follow_id) {
+            tracing::info!("Upstream relay {} rejected our follow", actor.id);
+            state.db.remove_upstream(actor.id).await?;
+            return Ok(());
+        }
+    }
+
     jobs.queue(Reject(actor)).await?;
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_undo(
     config: &Config,
     jobs: &JobServer,
     input: AcceptedActivities,
+    raw: serde_json::Value,
     actor: Actor,
     is_listener: bool,
+    path_tag: Option<String>,
 ) -> Result<(), Error> {
     let any_base = single_object(input.object_unchecked())?.clone();
     let undone_object =
@@ -175,36 +268,58 @@ async fn handle_undo(
 
     if !undone_object.is_kind(&UndoTypes::Follow) {
         if is_listener {
-            jobs.queue(Forward::new(input, actor)).await?;
+            jobs.queue(Forward::new(input, actor).with_raw(raw)).await?;
             return Ok(());
         } else {
             return Err(ErrorKind::NotSubscribed(actor.id.to_string()).into());
         }
     }
 
-    let my_id: IriString = config.generate_url(UrlKind::Actor);
+    let tag = check_follow_object(config, undone_object.object_unchecked(), path_tag)?;
+
+    if !is_listener {
+        return Ok(());
+    }
 
-    if !undone_object.object_is(&my_id) && !undone_object.object_is(&public()) {
+    jobs.queue(Undo::new(input, actor).with_tag(tag)).await?;
+    Ok(())
+}
+
+async fn handle_move(
+    jobs: &JobServer,
+    input: AcceptedActivities,
+    actor: Actor,
+) -> Result<(), Error> {
+    // activitystreams' Move type has no target, so parse it as a generic targeted activity
+    let base = AnyBase::from_extended(input)?;
+    let move_activity = activity::ActorAndObjectOptTarget::<ValidTypes>::from_any_base(base)?
+        .ok_or(ErrorKind::ObjectFormat)?;
+
+    // actors can only move themselves
+    if !move_activity.object_is(&actor.id) {
         return Err(ErrorKind::WrongActor(id_string(
-            undone_object.object_unchecked().as_single_id(),
+            move_activity.object_unchecked().as_single_id(),
         )?)
         .into());
     }
 
-    if !is_listener {
-        return Ok(());
-    }
+    let target = move_activity
+        .target()
+        .and_then(|target| target.as_single_id())
+        .ok_or(ErrorKind::MissingId)?;
+
+    jobs.queue(Move::new(actor, target.to_owned())).await?;
 
-    jobs.queue(Undo::new(input, actor)).await?;
     Ok(())
 }
 
 async fn handle_forward(
     jobs: &JobServer,
     input: AcceptedActivities,
+    raw: serde_json::Value,
     actor: Actor,
 ) -> Result<(), Error> {
-    jobs.queue(Forward::new(input, actor)).await?;
+    jobs.queue(Forward::new(input, actor).with_raw(raw)).await?;
 
     Ok(())
 }
@@ -212,40 +327,80 @@ async fn handle_forward(
 async fn handle_announce(
     state: &State,
     jobs: &JobServer,
+    is_create: bool,
     input: AcceptedActivities,
+    raw: serde_json::Value,
     actor: Actor,
 ) -> Result<(), Error> {
-    let object_id = input
-        .object_unchecked()
-        .as_single_id()
-        .ok_or(ErrorKind::MissingId)?;
+    let object = single_object(input.object_unchecked())?;
+    let object_id = object.id().ok_or(ErrorKind::MissingId)?;
 
-    if state.is_cached(object_id) {
+    if state.is_duplicate(object_id).await? {
         return Ok(());
-        //return Err(ErrorKind::Duplicate.into());
     }
 
-    jobs.queue(Announce::new(object_id.to_owned(), actor))
-        .await?;
+    // a Create from the post's author carries the post itself, which saves filters a fetch.
+    // Anything else could have been changed by the sender, so it's fetched from its origin
+    let embedded = if is_create && object.as_xsd_any_uri().is_none() {
+        Some(serde_json::to_value(object)?).filter(|object| is_authored_by(object, &actor.id))
+    } else {
+        None
+    };
+
+    // only the author's own Create is worth forwarding as received, since the author signed it
+    let announce = if embedded.is_some() {
+        Announce::new(object_id.to_owned(), embedded, actor).with_activity(raw)
+    } else {
+        Announce::new(object_id.to_owned(), embedded, actor)
+    };
+
+    jobs.queue(announce).await?;
 
     Ok(())
 }
 
+// Keep the follow around for an admin to approve or deny, instead of rejecting it
+async fn hold_follow(
+    state: &State,
+    config: &Config,
+    input: AcceptedActivities,
+    actor: Actor,
+    path_tag: Option<String>,
+) -> Result<HttpResponse, Error> {
+    let tag = check_follow_object(config, input.object_unchecked(), path_tag)?;
+
+    tracing::info!("Holding follow from {} for approval", actor.id);
+
+    let actor_id = actor.id.to_string();
+    let pending = PendingFollow {
+        input,
+        actor,
+        tag,
+        received_at: OffsetDateTime::now_utc(),
+    };
+
+    if !state
+        .db
+        .add_pending_follow(pending, config.pending_follow_capacity())
+        .await?
+    {
+        tracing::warn!("Too many follows waiting for approval, rejecting {actor_id}");
+        return Err(ErrorKind::NotAllowed(actor_id).into());
+    }
+
+    Ok(accepted(serde_json::json!({})))
+}
+
 async fn handle_follow(
     config: &Config,
     jobs: &JobServer,
     input: AcceptedActivities,
     actor: Actor,
+    path_tag: Option<String>,
 ) -> Result<(), Error> {
-    let my_id: IriString = config.generate_url(UrlKind::Actor);
-
-    if !input.object_is(&my_id) && !input.object_is(&public()) {
-        return Err(
-            ErrorKind::WrongActor(id_string(input.object_unchecked().as_single_id())?).into(),
-        );
-    }
+    let tag = check_follow_object(config, input.object_unchecked(), path_tag)?;
 
-    jobs.queue(Follow::new(input, actor)).await?;
+    jobs.queue(Follow::new(input, actor).with_tag(tag)).await?;
 
     Ok(())
 }
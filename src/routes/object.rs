@@ -0,0 +1,22 @@
+use crate::{
+    data::State,
+    error::{Error, ErrorKind},
+    routes::ok,
+};
+use actix_web::{web, HttpResponse};
+use uuid::Uuid;
+
+#[tracing::instrument(name = "Object", skip(state))]
+pub(crate) async fn route(
+    state: web::Data<State>,
+    uuid: web::Path<Uuid>,
+) -> Result<HttpResponse, Error> {
+    let objects = state.objects.as_ref().ok_or(ErrorKind::NotFound)?;
+
+    let object = objects
+        .get_by_uuid(uuid.into_inner())
+        .await?
+        .ok_or(ErrorKind::NotFound)?;
+
+    Ok(ok(object))
+}
@@ -1,9 +1,9 @@
-use crate::db::Db;
+use crate::{admin::QueueStats, data::State, db::PendingFollow, jobs::JobServer};
 use std::sync::Arc;
 use teloxide::{
     dispatching::{Dispatcher, UpdateFilterExt},
     requests::{Requester, ResponseResult},
-    types::{Message, Update},
+    types::{ChatId, Message, Update},
     utils::command::BotCommands,
     Bot,
 };
@@ -40,17 +40,38 @@ enum Command {
 
     #[command(description = "List connected domains")]
     ListConnected,
+
+    #[command(description = "List domains with failing circuit breakers")]
+    ListBreakers,
+
+    #[command(description = "Reset the circuit breaker for a domain")]
+    ResetBreaker { domain: String },
+
+    #[command(description = "List job queues with their running, retrying and oldest jobs")]
+    ListJobs,
+
+    #[command(description = "List follows waiting for approval (for FOLLOW_APPROVAL)")]
+    ListPending,
+
+    #[command(description = "Approve a domain's follow request (for FOLLOW_APPROVAL)")]
+    Approve { domain: String },
+
+    #[command(description = "Deny a domain's follow request (for FOLLOW_APPROVAL)")]
+    Deny { domain: String },
 }
 
-pub(crate) fn start(admin_handle: String, db: Db, token: &str) {
+pub(crate) fn start(admin_handle: String, state: State, jobs: JobServer, token: &str) {
     let bot = Bot::new(token);
     let admin_handle = Arc::new(admin_handle);
 
+    tokio::spawn(notify_pending_follows(bot.clone(), state.clone()));
+
     tokio::spawn(async move {
         let command_handler = teloxide::filter_command::<Command, _>().endpoint(
             move |bot: Bot, msg: Message, cmd: Command| {
                 let admin_handle = admin_handle.clone();
-                let db = db.clone();
+                let state = state.clone();
+                let jobs = jobs.clone();
 
                 async move {
                     if !is_admin(&admin_handle, &msg) {
@@ -59,7 +80,12 @@ pub(crate) fn start(admin_handle: String, db: Db, token: &str) {
                         return Ok(());
                     }
 
-                    answer(bot, msg, cmd, db).await
+                    // bots can't start conversations, so remember where to send notifications
+                    if let Err(e) = state.db.set_telegram_chat(msg.chat.id.0).await {
+                        tracing::warn!("Failed to save telegram chat: {e}");
+                    }
+
+                    answer(bot, msg, cmd, state, jobs).await
                 }
             },
         );
@@ -73,6 +99,42 @@ pub(crate) fn start(admin_handle: String, db: Db, token: &str) {
     });
 }
 
+async fn notify_pending_follows(bot: Bot, state: State) {
+    let mut subscriber = state.db.watch_pending_follows();
+
+    while let Some(event) = (&mut subscriber).await {
+        let sled::Event::Insert { value, .. } = event else {
+            continue;
+        };
+
+        let Ok(pending) = serde_json::from_slice::<PendingFollow>(&value) else {
+            continue;
+        };
+
+        let chat_id = match state.db.telegram_chat().await {
+            Ok(Some(chat_id)) => ChatId(chat_id),
+            Ok(None) => {
+                tracing::warn!("No telegram chat to notify, send the bot a command first");
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to read telegram chat: {e}");
+                continue;
+            }
+        };
+
+        let domain = pending.actor.id.authority_str().unwrap_or_default();
+        let message = format!(
+            "{} wants to join the relay\n/approve {domain}\n/deny {domain}",
+            pending.actor.id
+        );
+
+        if let Err(e) = bot.send_message(chat_id, message).await {
+            tracing::warn!("Failed to notify about pending follow: {e}");
+        }
+    }
+}
+
 fn is_admin(admin_handle: &str, message: &Message) -> bool {
     message
         .from()
@@ -81,14 +143,27 @@ fn is_admin(admin_handle: &str, message: &Message) -> bool {
         .unwrap_or(false)
 }
 
-#[tracing::instrument(skip(bot, msg, db))]
-async fn answer(bot: Bot, msg: Message, cmd: Command, db: Db) -> ResponseResult<()> {
+#[tracing::instrument(skip(bot, msg, state, jobs))]
+async fn answer(
+    bot: Bot,
+    msg: Message,
+    cmd: Command,
+    state: State,
+    jobs: JobServer,
+) -> ResponseResult<()> {
+    let db = &state.db;
+
     match cmd {
         Command::Help | Command::Start => {
             bot.send_message(msg.chat.id, Command::descriptions().to_string())
                 .await?;
         }
-        Command::Block { domain } if db.add_blocks(vec![domain.clone()]).await.is_ok() => {
+        Command::Block { domain }
+            if state
+                .block_domains(&jobs, vec![domain.clone()])
+                .await
+                .is_ok() =>
+        {
             bot.send_message(msg.chat.id, format!("{domain} has been blocked"))
                 .await?;
         }
@@ -100,7 +175,12 @@ async fn answer(bot: Bot, msg: Message, cmd: Command, db: Db) -> ResponseResult<
             bot.send_message(msg.chat.id, format!("{domain} has been allowed"))
                 .await?;
         }
-        Command::Disallow { domain } if db.remove_allows(vec![domain.clone()]).await.is_ok() => {
+        Command::Disallow { domain }
+            if state
+                .disallow_domains(&jobs, vec![domain.clone()])
+                .await
+                .is_ok() =>
+        {
             bot.send_message(msg.chat.id, format!("{domain} has been disallowed"))
                 .await?;
         }
@@ -119,6 +199,70 @@ async fn answer(bot: Bot, msg: Message, cmd: Command, db: Db) -> ResponseResult<
                 bot.send_message(msg.chat.id, connected.join("\n")).await?;
             }
         }
+        Command::ListBreakers => {
+            let breakers = state
+                .breakers
+                .snapshot()
+                .into_iter()
+                .filter(|(_, breaker)| breaker.failures > 0)
+                .map(|(domain, breaker)| format!("{domain}: {} failures", breaker.failures))
+                .collect::<Vec<_>>();
+
+            bot.send_message(msg.chat.id, breakers.join("\n")).await?;
+        }
+        Command::ResetBreaker { domain } => {
+            let message = match state.reset_breaker(&domain).await {
+                Ok(true) => format!("{domain}'s breaker has been reset"),
+                Ok(false) => format!("No breaker for {domain}"),
+                Err(_) => String::from("Internal server error"),
+            };
+
+            bot.send_message(msg.chat.id, message).await?;
+        }
+        Command::ListJobs => {
+            if let Ok(queues) = db.job_stats().await {
+                let queues = queues
+                    .into_iter()
+                    .map(|(queue, stats)| format!("{queue}: {}", QueueStats::from(stats)))
+                    .collect::<Vec<_>>();
+
+                let message = if queues.is_empty() {
+                    String::from("No queued jobs")
+                } else {
+                    queues.join("\n")
+                };
+
+                bot.send_message(msg.chat.id, message).await?;
+            }
+        }
+        Command::ListPending => {
+            if let Ok(pending) = db.pending_follows().await {
+                let actors = pending
+                    .into_iter()
+                    .map(|pending| pending.actor.id.to_string())
+                    .collect::<Vec<_>>();
+
+                bot.send_message(msg.chat.id, actors.join("\n")).await?;
+            }
+        }
+        Command::Approve { domain }
+            if state
+                .approve_follows(&jobs, vec![domain.clone()])
+                .await
+                .is_ok() =>
+        {
+            bot.send_message(msg.chat.id, format!("{domain} has been approved"))
+                .await?;
+        }
+        Command::Deny { domain }
+            if state
+                .deny_follows(&jobs, vec![domain.clone()])
+                .await
+                .is_ok() =>
+        {
+            bot.send_message(msg.chat.id, format!("{domain} has been denied"))
+                .await?;
+        }
         _ => {
             bot.send_message(msg.chat.id, "Internal server error")
                 .await?;